@@ -1 +1,1260 @@
-// create emebdeinngs for the documents to store in 
\ No newline at end of file
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use rusqlite::{params, Connection};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, State};
+
+use crate::chunking::{chunk_document, Chunk};
+use crate::db::{load_document, Block, EditorDocument};
+use crate::markdown::{block_to_markdown, markdown_to_inline, new_block_id};
+use crate::error::AppError;
+use crate::search::{hybrid_search, SearchFilters, SearchResult};
+use crate::settings::{get_json, set_json};
+use crate::{backend, events, vault, DB_PATH};
+
+// Embedding index for semantic search without the Python backend, and chat
+// answers grounded in the notes.
+//
+// Documents are split with `chunking` and every chunk gets a vector from the
+// configured provider, stored in `embedding_chunks`. Re-indexing a document only
+// embeds chunks whose text (or the model) changed; the rest keep their vectors.
+// Saves are picked up from the change events by a background indexer. Search is a
+// brute-force cosine scan, which is fast enough for a personal vault.
+//
+// Chat retrieves the best sections with hybrid search, numbers them in the system
+// prompt and asks the model to cite them as `[n]`. Tokens are streamed to the
+// windows as `chat-token` events; the finished answer is stored with the cited
+// document and block ids.
+
+const CONFIG_KEY: &str = "ai.embedding";
+const BATCH_SIZE: usize = 32;
+// Waits for a burst of autosaves to settle before indexing
+const INDEX_DELAY: Duration = Duration::from_secs(3);
+// Pause before documents that failed to index are tried again
+const INDEX_RETRY_DELAY: Duration = Duration::from_secs(60);
+const CHAT_CONFIG_KEY: &str = "ai.chat";
+// Sections put into the prompt
+const CHAT_SOURCES: usize = 6;
+// Earlier messages sent along for follow-up questions
+const CHAT_HISTORY: usize = 10;
+const CHAT_TIMEOUT: Duration = Duration::from_secs(300);
+const DEFAULT_CONVERSATION_TITLE: &str = "New chat";
+const DEFAULT_MAX_CARDS: usize = 10;
+// Characters of note text sent along for generation tasks
+const MAX_PROMPT_CHARS: usize = 12000;
+// Stands in for stored API keys in configs sent to the frontend
+const MASKED_KEY: &str = "********";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EmbeddingConfig {
+    // Local Ollama, e.g. `nomic-embed-text`
+    Ollama { url: String, model: String },
+    // Any OpenAI-compatible `/embeddings` endpoint
+    OpenAi { url: String, model: String, api_key: Option<String> },
+    // Feature hashing of words and character trigrams. Runs anywhere without a
+    // model download; matches vocabulary rather than meaning.
+    Local { dimensions: usize },
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        EmbeddingConfig::Local { dimensions: 384 }
+    }
+}
+
+pub trait EmbeddingProvider {
+    // Identifies the vector space; vectors of different models are never compared
+    fn model_id(&self) -> String;
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, AppError>;
+}
+
+struct OllamaProvider {
+    url: String,
+    model: String,
+}
+
+impl EmbeddingProvider for OllamaProvider {
+    fn model_id(&self) -> String {
+        format!("ollama:{}", self.model)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, AppError> {
+        #[derive(Deserialize)]
+        struct Response {
+            embeddings: Vec<Vec<f32>>,
+        }
+        let response: Response = Client::new()
+            .post(format!("{}/api/embed", self.url.trim_end_matches('/')))
+            .json(&json!({ "model": self.model, "input": texts }))
+            .send()?
+            .error_for_status()?
+            .json()?;
+        Ok(response.embeddings)
+    }
+}
+
+struct OpenAiProvider {
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl EmbeddingProvider for OpenAiProvider {
+    fn model_id(&self) -> String {
+        format!("openai:{}", self.model)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, AppError> {
+        #[derive(Deserialize)]
+        struct Item {
+            index: usize,
+            embedding: Vec<f32>,
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            data: Vec<Item>,
+        }
+        let mut request = Client::new()
+            .post(format!("{}/embeddings", self.url.trim_end_matches('/')))
+            .json(&json!({ "model": self.model, "input": texts }));
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let mut response: Response = request.send()?.error_for_status()?.json()?;
+        response.data.sort_by_key(|item| item.index);
+        Ok(response.data.into_iter().map(|item| item.embedding).collect())
+    }
+}
+
+struct LocalProvider {
+    dimensions: usize,
+}
+
+// Lowercased alphanumeric words; shared with keyword search so both agree on terms
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+// FNV-1a; stable across builds, unlike `DefaultHasher`
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+impl LocalProvider {
+    fn vector(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0f32; self.dimensions.max(1)];
+        let mut add = |feature: &str, weight: f32| {
+            let hash = fnv1a(feature.as_bytes());
+            let sign = if hash & 1 == 0 { 1.0 } else { -1.0 };
+            let len = vector.len();
+            vector[(hash >> 1) as usize % len] += sign * weight;
+        };
+        for word in words(text) {
+            add(&word, 1.0);
+            let padded: Vec<char> = format!(" {} ", word).chars().collect();
+            for trigram in padded.windows(3) {
+                add(&trigram.iter().collect::<String>(), 0.5);
+            }
+        }
+        normalize(&mut vector);
+        vector
+    }
+}
+
+impl EmbeddingProvider for LocalProvider {
+    fn model_id(&self) -> String {
+        format!("local:hash-{}", self.dimensions)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, AppError> {
+        Ok(texts.iter().map(|text| self.vector(text)).collect())
+    }
+}
+
+pub fn provider_from_config(config: &EmbeddingConfig) -> Box<dyn EmbeddingProvider> {
+    match config.clone() {
+        EmbeddingConfig::Ollama { url, model } => Box::new(OllamaProvider { url, model }),
+        EmbeddingConfig::OpenAi { url, model, api_key } => Box::new(OpenAiProvider { url, model, api_key }),
+        EmbeddingConfig::Local { dimensions } => Box::new(LocalProvider { dimensions }),
+    }
+}
+
+pub fn load_provider(conn: &Connection) -> Result<Box<dyn EmbeddingProvider>, AppError> {
+    Ok(provider_from_config(&get_json(conn, CONFIG_KEY, EmbeddingConfig::default())?))
+}
+
+pub fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+// Vectors are stored normalized, so this is the cosine similarity
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticHit {
+    pub document_id: i64,
+    pub title: String,
+    pub chunk_index: usize,
+    pub block_ids: Vec<String>,
+    pub headings: Vec<String>,
+    pub text: String,
+    pub score: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexReport {
+    pub documents: usize,
+    pub embedded: usize,
+    pub reused: usize,
+}
+
+// Managed Tauri state: documents waiting for the indexer
+#[derive(Default)]
+pub struct AiState {
+    pending: Mutex<HashSet<i64>>,
+    signal: Condvar,
+}
+
+impl AiState {
+    pub fn queue(&self, document_id: i64) {
+        self.pending.lock().unwrap().insert(document_id);
+        self.signal.notify_one();
+    }
+
+    // Makes the indexer check the queue again, e.g. after the vault was unlocked
+    fn wake(&self) {
+        let _pending = self.pending.lock().unwrap();
+        self.signal.notify_one();
+    }
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS embedding_chunks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            document_id INTEGER NOT NULL,
+            chunk_index INTEGER NOT NULL,
+            block_ids TEXT NOT NULL,
+            headings TEXT NOT NULL,
+            text TEXT NOT NULL,
+            content_hash TEXT NOT NULL,
+            model TEXT NOT NULL,
+            vector BLOB NOT NULL,
+            FOREIGN KEY(document_id) REFERENCES documents(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS embedding_chunks_document ON embedding_chunks (document_id)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chat_conversations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chat_messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            citations TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY(conversation_id) REFERENCES chat_conversations(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn chunk_hash(model: &str, text: &str) -> String {
+    hex::encode(Sha256::digest(format!("{}\n{}", model, text).as_bytes()))
+}
+
+// Text that gets embedded for a chunk; the title gives short sections context
+fn embedding_text(title: &str, chunk: &Chunk) -> String {
+    format!("{}\n\n{}", title, chunk.text)
+}
+
+pub fn remove_document(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    conn.execute("DELETE FROM embedding_chunks WHERE document_id = ?", [document_id])?;
+    Ok(())
+}
+
+// Brings the vectors of one document up to date and returns (embedded, reused)
+pub fn index_document(
+    conn: &Connection,
+    provider: &dyn EmbeddingProvider,
+    document_id: i64,
+) -> Result<(usize, usize), AppError> {
+    let doc = match load_document(conn, document_id) {
+        Ok(doc) => doc,
+        Err(AppError::SqliteError(rusqlite::Error::QueryReturnedNoRows)) => {
+            remove_document(conn, document_id)?;
+            return Ok((0, 0));
+        }
+        Err(e) => return Err(e),
+    };
+    let editor_doc: EditorDocument = serde_json::from_str(&doc.content)?;
+    let folder_path = backend::folder_path(conn, doc.folder_id)?;
+    let chunks = chunk_document(&editor_doc, doc.id, &folder_path);
+    let model = provider.model_id();
+
+    let existing: HashMap<String, Vec<u8>> = {
+        let mut stmt = conn.prepare("SELECT content_hash, vector FROM embedding_chunks WHERE document_id = ?")?;
+        let rows = stmt
+            .query_map([document_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<String, Vec<u8>>, rusqlite::Error>>()?;
+        rows
+    };
+
+    let title = crate::markdown::inline_to_text(&doc.title);
+    let texts: Vec<String> = chunks.iter().map(|chunk| embedding_text(&title, chunk)).collect();
+    let hashes: Vec<String> = texts.iter().map(|text| chunk_hash(&model, text)).collect();
+
+    let missing: Vec<usize> = (0..chunks.len()).filter(|i| !existing.contains_key(&hashes[*i])).collect();
+    let mut fresh: HashMap<usize, Vec<u8>> = HashMap::new();
+    for batch in missing.chunks(BATCH_SIZE) {
+        let inputs: Vec<String> = batch.iter().map(|i| texts[*i].clone()).collect();
+        let vectors = provider.embed(&inputs)?;
+        if vectors.len() != inputs.len() {
+            return Err(AppError::AiError(format!(
+                "Embedding provider returned {} vectors for {} inputs",
+                vectors.len(),
+                inputs.len()
+            )));
+        }
+        for (i, mut vector) in batch.iter().zip(vectors) {
+            normalize(&mut vector);
+            fresh.insert(*i, to_blob(&vector));
+        }
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM embedding_chunks WHERE document_id = ?", [document_id])?;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let vector = match fresh.remove(&i) {
+            Some(vector) => vector,
+            None => existing.get(&hashes[i]).cloned().unwrap_or_default(),
+        };
+        tx.execute(
+            "INSERT INTO embedding_chunks (document_id, chunk_index, block_ids, headings, text, content_hash, model, vector)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                document_id,
+                i as i64,
+                serde_json::to_string(&chunk.metadata.block_ids)?,
+                serde_json::to_string(&chunk.metadata.headings)?,
+                vault::seal_text(&chunk.text)?,
+                hashes[i],
+                model,
+                vector
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok((missing.len(), chunks.len() - missing.len()))
+}
+
+pub fn index_all(conn: &Connection, provider: &dyn EmbeddingProvider) -> Result<IndexReport, AppError> {
+    let ids = {
+        let mut stmt = conn.prepare("SELECT id FROM documents")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+        ids
+    };
+    let mut report = IndexReport { documents: 0, embedded: 0, reused: 0 };
+    for id in ids {
+        match index_document(conn, provider, id) {
+            Ok((embedded, reused)) => {
+                report.documents += 1;
+                report.embedded += embedded;
+                report.reused += reused;
+            }
+            // One broken document should not stop the rebuild
+            Err(AppError::SerdeError(e)) => eprintln!("Skipping document {} in embedding index: {}", id, e),
+            Err(e) => return Err(e),
+        }
+    }
+    // Chunks of documents deleted while the indexer was not listening
+    conn.execute("DELETE FROM embedding_chunks WHERE document_id NOT IN (SELECT id FROM documents)", [])?;
+    Ok(report)
+}
+
+pub fn semantic_search(
+    conn: &Connection,
+    provider: &dyn EmbeddingProvider,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SemanticHit>, AppError> {
+    let mut query_vector = provider
+        .embed(&[query.to_string()])?
+        .pop()
+        .ok_or_else(|| AppError::AiError("Embedding provider returned no vector".to_string()))?;
+    normalize(&mut query_vector);
+
+    let mut stmt = conn.prepare(
+        "SELECT c.document_id, d.title, c.chunk_index, c.block_ids, c.headings, c.text, c.vector
+         FROM embedding_chunks c JOIN documents d ON d.id = c.document_id
+         WHERE c.model = ?",
+    )?;
+    let mut scored = stmt
+        .query_map([provider.model_id()], |row| {
+            let vector: Vec<u8> = row.get(6)?;
+            Ok((
+                dot(&query_vector, &from_blob(&vector)),
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    scored.truncate(limit);
+
+    scored
+        .into_iter()
+        .map(|(score, document_id, title, chunk_index, block_ids, headings, text)| {
+            Ok(SemanticHit {
+                document_id,
+                title: crate::markdown::inline_to_text(&title),
+                chunk_index: chunk_index as usize,
+                block_ids: serde_json::from_str(&block_ids)?,
+                headings: serde_json::from_str(&headings)?,
+                text: vault::open_text(&text)?,
+                score,
+            })
+        })
+        .collect()
+}
+
+fn run_indexer(app: &AppHandle) {
+    let state = app.state::<AiState>();
+    loop {
+        {
+            // A locked vault can't be read; unlocking wakes the indexer up again
+            let pending = state.pending.lock().unwrap();
+            let _guard = state
+                .signal
+                .wait_while(pending, |pending| pending.is_empty() || vault::is_locked())
+                .unwrap();
+        }
+        thread::sleep(INDEX_DELAY);
+        let ids: Vec<i64> = state.pending.lock().unwrap().drain().collect();
+        let mut indexed = 0;
+        let result = Connection::open(DB_PATH).map_err(AppError::from).and_then(|conn| {
+            let provider = load_provider(&conn)?;
+            for id in &ids {
+                index_document(&conn, provider.as_ref(), *id)?;
+                indexed += 1;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            eprintln!("Embedding index update failed: {}", e);
+            state.pending.lock().unwrap().extend(&ids[indexed..]);
+            if !matches!(e, AppError::VaultLocked) {
+                thread::sleep(INDEX_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+pub fn start(app: AppHandle) {
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_CREATED, move |e: events::DocumentCreated| {
+        handle.state::<AiState>().queue(e.id)
+    });
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_UPDATED, move |e: events::DocumentUpdated| {
+        handle.state::<AiState>().queue(e.id)
+    });
+    events::listen(&app, events::DOCUMENT_DELETED, move |e: events::DocumentDeleted| {
+        let result = Connection::open(DB_PATH).map_err(AppError::from).and_then(|conn| remove_document(&conn, e.id));
+        if let Err(err) = result {
+            eprintln!("Failed to drop embeddings of document {}: {}", e.id, err);
+        }
+    });
+    let handle = app.clone();
+    events::listen(&app, events::VAULT_UNLOCKED, move |_: events::VaultUnlocked| {
+        handle.state::<AiState>().wake()
+    });
+    let handle = app.clone();
+    events::listen(&app, events::VAULT_SYNCED, move |_: events::VaultSynced| {
+        let ids = Connection::open(DB_PATH)
+            .and_then(|conn| {
+                let mut stmt = conn.prepare("SELECT id FROM documents")?;
+                let ids = stmt.query_map([], |row| row.get(0))?.collect::<Result<Vec<i64>, rusqlite::Error>>();
+                ids
+            })
+            .unwrap_or_default();
+        let state = handle.state::<AiState>();
+        ids.into_iter().for_each(|id| state.queue(id));
+    });
+
+    thread::spawn(move || run_indexer(&app));
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatConfig {
+    Ollama { url: String, model: String },
+    OpenAi { url: String, model: String, api_key: Option<String> },
+    // Replies with the retrieved passages instead of generating text. Needs no
+    // model, so chat works offline and in tests.
+    #[default]
+    Local,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ChatTurn {
+    pub role: String,
+    pub content: String,
+}
+
+pub struct ChatRequest<'a> {
+    pub messages: Vec<ChatTurn>,
+    pub sources: &'a [SearchResult],
+}
+
+pub trait ChatProvider {
+    // Streams the reply to `on_token` and returns it whole
+    fn complete(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<String, AppError>;
+}
+
+struct OllamaChat {
+    url: String,
+    model: String,
+}
+
+impl ChatProvider for OllamaChat {
+    fn complete(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<String, AppError> {
+        let response = Client::builder()
+            .timeout(CHAT_TIMEOUT)
+            .build()?
+            .post(format!("{}/api/chat", self.url.trim_end_matches('/')))
+            .json(&json!({ "model": self.model, "messages": request.messages, "stream": true }))
+            .send()?
+            .error_for_status()?;
+        // One JSON object per line
+        let mut answer = String::new();
+        for line in BufReader::new(response).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let chunk: Value = serde_json::from_str(&line)?;
+            if let Some(error) = chunk["error"].as_str() {
+                return Err(AppError::AiError(format!("Ollama: {}", error)));
+            }
+            if let Some(token) = chunk["message"]["content"].as_str().filter(|t| !t.is_empty()) {
+                answer.push_str(token);
+                on_token(token);
+            }
+            if chunk["done"].as_bool() == Some(true) {
+                break;
+            }
+        }
+        Ok(answer)
+    }
+}
+
+struct OpenAiChat {
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl ChatProvider for OpenAiChat {
+    fn complete(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<String, AppError> {
+        let mut builder = Client::builder()
+            .timeout(CHAT_TIMEOUT)
+            .build()?
+            .post(format!("{}/chat/completions", self.url.trim_end_matches('/')))
+            .json(&json!({ "model": self.model, "messages": request.messages, "stream": true }));
+        if let Some(key) = &self.api_key {
+            builder = builder.bearer_auth(key);
+        }
+        let response = builder.send()?.error_for_status()?;
+        // Server-sent events: `data: {...}` lines, ending with `data: [DONE]`
+        let mut answer = String::new();
+        for line in BufReader::new(response).lines() {
+            let line = line?;
+            let data = match line.strip_prefix("data:") {
+                Some(data) => data.trim(),
+                None => continue,
+            };
+            if data == "[DONE]" {
+                break;
+            }
+            let chunk: Value = serde_json::from_str(data)?;
+            if let Some(token) = chunk["choices"][0]["delta"]["content"].as_str().filter(|t| !t.is_empty()) {
+                answer.push_str(token);
+                on_token(token);
+            }
+        }
+        Ok(answer)
+    }
+}
+
+struct LocalChat;
+
+impl ChatProvider for LocalChat {
+    fn complete(&self, request: &ChatRequest, on_token: &mut dyn FnMut(&str)) -> Result<String, AppError> {
+        let answer = if request.sources.is_empty() {
+            "I could not find anything about this in your notes.".to_string()
+        } else {
+            let passages: Vec<String> = request
+                .sources
+                .iter()
+                .take(3)
+                .enumerate()
+                .map(|(i, source)| {
+                    let text = source.text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    let text: String = text.chars().take(300).collect();
+                    format!("- {} [{}]", text, i + 1)
+                })
+                .collect();
+            format!("This is what your notes say:\n\n{}", passages.join("\n"))
+        };
+        for token in answer.split_inclusive(' ') {
+            on_token(token);
+        }
+        Ok(answer)
+    }
+}
+
+pub fn chat_provider_from_config(config: &ChatConfig) -> Box<dyn ChatProvider> {
+    match config.clone() {
+        ChatConfig::Ollama { url, model } => Box::new(OllamaChat { url, model }),
+        ChatConfig::OpenAi { url, model, api_key } => Box::new(OpenAiChat { url, model, api_key }),
+        ChatConfig::Local => Box::new(LocalChat),
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Citation {
+    pub document_id: i64,
+    pub title: String,
+    pub block_ids: Vec<String>,
+    pub headings: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Conversation {
+    pub id: i64,
+    pub title: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub conversation_id: i64,
+    pub role: String,
+    pub content: String,
+    pub citations: Vec<Citation>,
+    pub created_at: String,
+}
+
+fn create_conversation(conn: &Connection, title: &str) -> Result<Conversation, AppError> {
+    let now = chrono::Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO chat_conversations (title, created_at, updated_at) VALUES (?1, ?2, ?2)",
+        params![vault::seal_text(title)?, now],
+    )?;
+    Ok(Conversation { id: conn.last_insert_rowid(), title: title.to_string(), created_at: now.clone(), updated_at: now })
+}
+
+fn list_conversations(conn: &Connection) -> Result<Vec<Conversation>, AppError> {
+    let mut stmt =
+        conn.prepare("SELECT id, title, created_at, updated_at FROM chat_conversations ORDER BY updated_at DESC")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?, row.get(2)?, row.get(3)?)))?
+        .collect::<Result<Vec<(i64, String, String, String)>, rusqlite::Error>>()?;
+    rows.into_iter()
+        .map(|(id, title, created_at, updated_at)| {
+            Ok(Conversation { id, title: vault::open_text(&title)?, created_at, updated_at })
+        })
+        .collect()
+}
+
+fn load_messages(conn: &Connection, conversation_id: i64) -> Result<Vec<ChatMessage>, AppError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, role, content, citations, created_at FROM chat_messages WHERE conversation_id = ? ORDER BY id",
+    )?;
+    let rows = stmt
+        .query_map([conversation_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?, row.get(4)?))
+        })?
+        .collect::<Result<Vec<(i64, String, String, String, String)>, rusqlite::Error>>()?;
+    rows.into_iter()
+        .map(|(id, role, content, citations, created_at)| {
+            Ok(ChatMessage {
+                id,
+                conversation_id,
+                role,
+                content: vault::open_text(&content)?,
+                citations: serde_json::from_str(&citations)?,
+                created_at,
+            })
+        })
+        .collect()
+}
+
+fn insert_message(
+    conn: &Connection,
+    conversation_id: i64,
+    role: &str,
+    content: &str,
+    citations: Vec<Citation>,
+) -> Result<ChatMessage, AppError> {
+    let now = chrono::Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO chat_messages (conversation_id, role, content, citations, created_at) VALUES (?, ?, ?, ?, ?)",
+        params![conversation_id, role, vault::seal_text(content)?, serde_json::to_string(&citations)?, now],
+    )?;
+    let id = conn.last_insert_rowid();
+    conn.execute("UPDATE chat_conversations SET updated_at = ? WHERE id = ?", params![now, conversation_id])?;
+    Ok(ChatMessage {
+        id,
+        conversation_id,
+        role: role.to_string(),
+        content: content.to_string(),
+        citations,
+        created_at: now,
+    })
+}
+
+fn system_prompt(sources: &[SearchResult]) -> String {
+    let mut prompt = String::from(
+        "You answer questions about the user's notes. Use only the numbered sources below. \
+         If they do not contain the answer, say so instead of guessing. Cite the sources you \
+         use by their number in square brackets, e.g. [2].\n\nSources:",
+    );
+    for (i, source) in sources.iter().enumerate() {
+        let mut location = vec![source.title.clone()];
+        location.extend(source.headings.iter().cloned());
+        prompt.push_str(&format!("\n\n[{}] {}\n{}", i + 1, location.join(" › "), source.text));
+    }
+    if sources.is_empty() {
+        prompt.push_str("\n\n(no matching notes)");
+    }
+    prompt
+}
+
+// Sources referenced as `[n]` in the answer, in order of first mention
+fn cited(answer: &str, sources: &[SearchResult]) -> Vec<Citation> {
+    let mut numbers: Vec<usize> = Vec::new();
+    for part in answer.split('[').skip(1) {
+        // Also accepts grouped citations like `[1, 3]`
+        let inside = match part.split_once(']') {
+            Some((inside, _)) => inside,
+            None => continue,
+        };
+        for n in inside.split(',').filter_map(|n| n.trim().parse::<usize>().ok()) {
+            if n >= 1 && n <= sources.len() && !numbers.contains(&n) {
+                numbers.push(n);
+            }
+        }
+    }
+    numbers
+        .into_iter()
+        .map(|n| {
+            let source = &sources[n - 1];
+            Citation {
+                document_id: source.document_id,
+                title: source.title.clone(),
+                block_ids: source.block_ids.clone(),
+                headings: source.headings.clone(),
+            }
+        })
+        .collect()
+}
+
+// Answers `question` in the conversation, streaming tokens as `chat-token` events
+pub fn answer(app: &AppHandle, conn: &Connection, conversation_id: i64, question: &str) -> Result<ChatMessage, AppError> {
+    let history = load_messages(conn, conversation_id)?;
+    if history.is_empty() {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM chat_conversations WHERE id = ?)",
+            [conversation_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(AppError::NotFound(format!("conversation {}", conversation_id)));
+        }
+        // Name the conversation after its first question
+        let title: String = question.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(60).collect();
+        conn.execute(
+            "UPDATE chat_conversations SET title = ? WHERE id = ?",
+            params![vault::seal_text(&title)?, conversation_id],
+        )?;
+    }
+    insert_message(conn, conversation_id, "user", question, Vec::new())?;
+
+    let embedder = load_provider(conn)?;
+    let sources = hybrid_search(conn, embedder.as_ref(), question, &SearchFilters::default(), CHAT_SOURCES)?;
+
+    let mut messages = vec![ChatTurn { role: "system".to_string(), content: system_prompt(&sources) }];
+    let skip = history.len().saturating_sub(CHAT_HISTORY);
+    messages.extend(
+        history
+            .into_iter()
+            .skip(skip)
+            .map(|m| ChatTurn { role: m.role, content: m.content }),
+    );
+    messages.push(ChatTurn { role: "user".to_string(), content: question.to_string() });
+
+    let provider = chat_provider_from_config(&get_json(conn, CHAT_CONFIG_KEY, ChatConfig::default())?);
+    let reply = provider.complete(&ChatRequest { messages, sources: &sources }, &mut |token| {
+        events::chat_token(app, events::ChatToken { conversation_id, token: token.to_string() })
+    })?;
+
+    let citations = cited(&reply, &sources);
+    insert_message(conn, conversation_id, "assistant", &reply, citations)
+}
+
+pub fn chat_model_configured(conn: &Connection) -> Result<bool, AppError> {
+    Ok(!matches!(get_json(conn, CHAT_CONFIG_KEY, ChatConfig::default())?, ChatConfig::Local))
+}
+
+// One-shot completion with the configured chat model, for generated content that
+// has no extractive fallback
+pub fn generate(conn: &Connection, system: String, user: String) -> Result<String, AppError> {
+    let config = get_json(conn, CHAT_CONFIG_KEY, ChatConfig::default())?;
+    if let ChatConfig::Local = config {
+        return Err(AppError::AiError(
+            "This needs a chat model; configure Ollama or an OpenAI-compatible endpoint".to_string(),
+        ));
+    }
+    let messages = vec![
+        ChatTurn { role: "system".to_string(), content: system },
+        ChatTurn { role: "user".to_string(), content: user },
+    ];
+    chat_provider_from_config(&config).complete(&ChatRequest { messages, sources: &[] }, &mut |_| {})
+}
+
+// The JSON object in a model reply, which may be wrapped in prose or a code fence
+pub fn reply_json<T: DeserializeOwned>(reply: &str) -> Result<T, AppError> {
+    match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => Ok(serde_json::from_str(&reply[start..=end])?),
+        _ => Err(AppError::AiError("The model did not reply with JSON".to_string())),
+    }
+}
+
+// Question text reduced to its words, for spotting duplicates
+fn card_key(question: &str) -> String {
+    words(&crate::markdown::inline_to_text(question)).collect::<Vec<_>>().join(" ")
+}
+
+fn collect_questions(items: &[Value], out: &mut HashSet<String>) {
+    for item in items {
+        if let Some(question) = item.get("question").and_then(Value::as_str) {
+            out.insert(card_key(question));
+        }
+        if let Some(children) = item.get("items").and_then(Value::as_array) {
+            collect_questions(children, out);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GeneratedCard {
+    question: String,
+    answer: String,
+    block: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct GeneratedCards {
+    cards: Vec<GeneratedCard>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CardSource {
+    pub question: String,
+    pub block_id: Option<String>,
+}
+
+// A `flashcard` block for the editor to insert after the user accepts it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FlashcardProposal {
+    pub block: Block,
+    pub sources: Vec<CardSource>,
+    pub skipped_duplicates: usize,
+}
+
+pub fn generate_flashcards(
+    conn: &Connection,
+    doc: &EditorDocument,
+    max_cards: usize,
+) -> Result<FlashcardProposal, AppError> {
+    let mut existing = HashSet::new();
+    let mut numbered: Vec<&Block> = Vec::new();
+    let mut text = String::new();
+    for block in &doc.blocks {
+        if block.r#type == "flashcard" {
+            if let Some(items) = block.data.get("items").and_then(Value::as_array) {
+                collect_questions(items, &mut existing);
+            }
+            continue;
+        }
+        let markdown = block_to_markdown(block);
+        if markdown.trim().is_empty() || text.len() + markdown.len() > MAX_PROMPT_CHARS {
+            continue;
+        }
+        numbered.push(block);
+        text.push_str(&format!("[{}] {}\n\n", numbered.len(), markdown));
+    }
+    if numbered.is_empty() {
+        return Err(AppError::InvalidInput("The document has no text to make flashcards from".to_string()));
+    }
+
+    let mut system = format!(
+        "You write flashcards for spaced repetition from the user's notes. Write at most {} cards. \
+         Each card asks one thing and has a short answer taken from the notes. The notes are split \
+         into numbered blocks; give the number of the block each card comes from. Reply with JSON only: \
+         {{\"cards\": [{{\"question\": \"...\", \"answer\": \"...\", \"block\": 1}}]}}",
+        max_cards
+    );
+    if !existing.is_empty() {
+        system.push_str("\n\nThese cards already exist; do not repeat them:\n");
+        for block in doc.blocks.iter().filter(|b| b.r#type == "flashcard") {
+            system.push_str(&block_to_markdown(block));
+            system.push('\n');
+        }
+    }
+    let reply: GeneratedCards = reply_json(&generate(conn, system, text)?)?;
+
+    let mut seen = existing;
+    let mut skipped_duplicates = 0;
+    let mut items = Vec::new();
+    let mut sources = Vec::new();
+    for card in reply.cards {
+        let (question, answer) = (card.question.trim(), card.answer.trim());
+        if question.is_empty() || answer.is_empty() {
+            continue;
+        }
+        if !seen.insert(card_key(question)) {
+            skipped_duplicates += 1;
+            continue;
+        }
+        if items.len() == max_cards {
+            break;
+        }
+        let block_id = card.block.and_then(|n| n.checked_sub(1)).and_then(|i| numbered.get(i)).map(|b| b.id.clone());
+        let (question, answer) = (markdown_to_inline(question), markdown_to_inline(answer));
+        // Same item shape as the editor tool, plus the block the card was made from
+        items.push(json!({
+            "content": format!("{} &gt;&gt; {}", question, answer),
+            "items": [],
+            "question": question,
+            "answer": answer,
+            "source_block_id": block_id,
+        }));
+        sources.push(CardSource { question, block_id });
+    }
+
+    Ok(FlashcardProposal {
+        block: Block {
+            id: new_block_id(),
+            r#type: "flashcard".to_string(),
+            data: json!({ "style": "unordered", "items": items }),
+        },
+        sources,
+        skipped_duplicates,
+    })
+}
+
+// The API key stays in the backend
+fn masked<T: Serialize>(config: &T) -> Result<Value, AppError> {
+    let mut value = serde_json::to_value(config)?;
+    if let Some(key) = value.get_mut("api_key").filter(|key| !key.is_null()) {
+        *key = json!(MASKED_KEY);
+    }
+    Ok(value)
+}
+
+#[tauri::command]
+pub fn get_embedding_config_command() -> Result<Value, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let config = get_json(&conn, CONFIG_KEY, EmbeddingConfig::default()).map_err(|e| e.to_string())?;
+    masked(&config).map_err(|e| e.to_string())
+}
+
+// Changing the provider invalidates every vector; the index is rebuilt in the background
+#[tauri::command]
+pub fn set_embedding_config_command(state: State<AiState>, config: EmbeddingConfig) -> Result<(), String> {
+    println!("set_embedding_config_command -> {}", provider_from_config(&config).model_id());
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let config = match config {
+        // The masked key from `get_embedding_config_command` means "keep the stored one"
+        EmbeddingConfig::OpenAi { url, model, api_key } if api_key.as_deref() == Some(MASKED_KEY) => {
+            let stored = match get_json(&conn, CONFIG_KEY, EmbeddingConfig::default()).map_err(|e| e.to_string())? {
+                EmbeddingConfig::OpenAi { api_key, .. } => api_key,
+                _ => None,
+            };
+            EmbeddingConfig::OpenAi { url, model, api_key: stored }
+        }
+        config => config,
+    };
+    set_json(&conn, CONFIG_KEY, &config).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare("SELECT id FROM documents").map_err(|e| e.to_string())?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<i64>, rusqlite::Error>>()
+        .map_err(|e| e.to_string())?;
+    ids.into_iter().for_each(|id| state.queue(id));
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn rebuild_embedding_index_command() -> Result<IndexReport, String> {
+    println!("rebuild_embedding_index_command");
+    tauri::async_runtime::spawn_blocking(|| {
+        vault::guard()?;
+        let conn = Connection::open(DB_PATH)?;
+        let provider = load_provider(&conn)?;
+        index_all(&conn, provider.as_ref())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+#[tauri::command]
+pub async fn semantic_search_command(query: String, limit: Option<usize>) -> Result<Vec<SemanticHit>, String> {
+    println!("semantic_search_command -> limit: {:?}", limit);
+    tauri::async_runtime::spawn_blocking(move || {
+        vault::guard()?;
+        let conn = Connection::open(DB_PATH)?;
+        let provider = load_provider(&conn)?;
+        semantic_search(&conn, provider.as_ref(), &query, limit.unwrap_or(10))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_chat_config_command() -> Result<Value, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let config = get_json(&conn, CHAT_CONFIG_KEY, ChatConfig::default()).map_err(|e| e.to_string())?;
+    masked(&config).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_chat_config_command(config: ChatConfig) -> Result<(), String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let config = match config {
+        ChatConfig::OpenAi { url, model, api_key } if api_key.as_deref() == Some(MASKED_KEY) => {
+            let stored = match get_json(&conn, CHAT_CONFIG_KEY, ChatConfig::default()).map_err(|e| e.to_string())? {
+                ChatConfig::OpenAi { api_key, .. } => api_key,
+                _ => None,
+            };
+            ChatConfig::OpenAi { url, model, api_key: stored }
+        }
+        config => config,
+    };
+    println!("set_chat_config_command -> {:?}", masked(&config).map_err(|e| e.to_string())?);
+    set_json(&conn, CHAT_CONFIG_KEY, &config).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn create_conversation_command(title: Option<String>) -> Result<Conversation, String> {
+    println!("create_conversation_command");
+    vault::guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    create_conversation(&conn, title.as_deref().unwrap_or(DEFAULT_CONVERSATION_TITLE)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_conversations_command() -> Result<Vec<Conversation>, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    list_conversations(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_conversation_messages_command(conversation_id: i64) -> Result<Vec<ChatMessage>, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    load_messages(&conn, conversation_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn delete_conversation_command(conversation_id: i64) -> Result<(), String> {
+    println!("delete_conversation_command -> id: {}", conversation_id);
+    vault::guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    // Messages go first so this does not depend on the cascade
+    conn.execute("DELETE FROM chat_messages WHERE conversation_id = ?", [conversation_id])
+        .map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM chat_conversations WHERE id = ?", [conversation_id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Returns the stored answer once it is complete; tokens arrive earlier as `chat-token` events
+#[tauri::command]
+pub async fn send_chat_message_command(
+    app: AppHandle,
+    conversation_id: i64,
+    message: String,
+) -> Result<ChatMessage, String> {
+    println!("send_chat_message_command -> conversation: {}", conversation_id);
+    tauri::async_runtime::spawn_blocking(move || {
+        vault::guard()?;
+        if message.trim().is_empty() {
+            return Err(AppError::InvalidInput("Message must not be empty".to_string()));
+        }
+        let conn = Connection::open(DB_PATH)?;
+        answer(&app, &conn, conversation_id, message.trim())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+// Proposes cards for the document open in the editor; nothing is saved
+#[tauri::command]
+pub async fn generate_flashcards_command(
+    document: EditorDocument,
+    max_cards: Option<usize>,
+) -> Result<FlashcardProposal, String> {
+    println!("generate_flashcards_command -> blocks: {}", document.blocks.len());
+    tauri::async_runtime::spawn_blocking(move || {
+        vault::guard()?;
+        let conn = Connection::open(DB_PATH)?;
+        generate_flashcards(&conn, &document, max_cards.unwrap_or(DEFAULT_MAX_CARDS).max(1))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::MatchExplanation;
+
+    fn source(document_id: i64, title: &str) -> SearchResult {
+        SearchResult {
+            document_id,
+            title: title.to_string(),
+            folder_path: String::new(),
+            score: 1.0,
+            block_ids: vec![format!("blk-{}", document_id)],
+            headings: vec!["Plans".to_string()],
+            snippet: String::new(),
+            text: format!("Text of {}", title),
+            explanation: MatchExplanation::default(),
+        }
+    }
+
+    fn cited_ids(answer: &str, sources: &[SearchResult]) -> Vec<i64> {
+        cited(answer, sources).into_iter().map(|c| c.document_id).collect()
+    }
+
+    #[test]
+    fn citations_follow_first_mention_of_valid_numbers() {
+        let sources = vec![source(10, "Garden"), source(20, "Kitchen"), source(30, "Budget")];
+        assert_eq!(cited_ids("Water daily [2]. Compost first [1].", &sources), vec![20, 10]);
+        assert_eq!(cited_ids("See [1, 3] and again [3][1].", &sources), vec![10, 30]);
+        // `[0]`, numbers past the sources and non-numbers are ignored
+        assert_eq!(cited_ids("[0] [4] [x] [2 ,  ] [-1]", &sources), vec![20]);
+        assert_eq!(cited_ids("An unclosed [3", &sources), Vec::<i64>::new());
+        assert!(cited("Anything [1]", &[]).is_empty());
+
+        let citation = &cited("[3]", &sources)[0];
+        assert_eq!(citation.title, "Budget");
+        assert_eq!(citation.block_ids, vec!["blk-30"]);
+        assert_eq!(citation.headings, vec!["Plans"]);
+    }
+
+    #[test]
+    fn citations_inside_links_still_count() {
+        let sources = vec![source(10, "Garden"), source(20, "Kitchen")];
+        assert_eq!(cited_ids("As noted in [the plan [2]](note-20.md) and [[1]]", &sources), vec![20, 10]);
+        assert!(cited("[Garden notes](note-10.md)", &sources).is_empty());
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Reply {
+        title: String,
+    }
+
+    #[test]
+    fn reply_json_finds_the_object_in_prose_and_fences() {
+        let expected = Reply { title: "Garden {plan}".to_string() };
+        assert_eq!(reply_json::<Reply>(r#"{"title": "Garden {plan}"}"#).unwrap(), expected);
+        assert_eq!(reply_json::<Reply>("Sure! Here it is:\n{\"title\": \"Garden {plan}\"}\nHope that helps.").unwrap(), expected);
+        assert_eq!(reply_json::<Reply>("```json\n{\"title\": \"Garden {plan}\"}\n```").unwrap(), expected);
+
+        assert!(matches!(reply_json::<Reply>("No JSON here"), Err(AppError::AiError(_))));
+        assert!(matches!(reply_json::<Reply>("} backwards {"), Err(AppError::AiError(_))));
+        assert!(matches!(reply_json::<Reply>("{\"other\": 1}"), Err(AppError::SerdeError(_))));
+    }
+
+    #[test]
+    fn system_prompt_numbers_sources_with_their_location() {
+        let prompt = system_prompt(&[source(10, "Garden"), source(20, "Kitchen")]);
+        assert!(prompt.contains("\n\n[1] Garden › Plans\nText of Garden"));
+        assert!(prompt.contains("\n\n[2] Kitchen › Plans\nText of Kitchen"));
+        assert!(!prompt.contains("no matching notes"));
+        assert!(system_prompt(&[]).ends_with("\n\n(no matching notes)"));
+    }
+
+    #[test]
+    fn inserted_messages_keep_their_own_id() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        let conversation = create_conversation(&conn, "Garden").unwrap();
+        let question = insert_message(&conn, conversation.id, "user", "When to water?", Vec::new()).unwrap();
+        let citations = cited("[1]", &[source(10, "Garden")]);
+        let reply = insert_message(&conn, conversation.id, "assistant", "Daily [1]", citations).unwrap();
+
+        let stored = load_messages(&conn, conversation.id).unwrap();
+        assert_eq!(stored.iter().map(|m| m.id).collect::<Vec<_>>(), vec![question.id, reply.id]);
+        assert_eq!(stored[1].content, "Daily [1]");
+        assert_eq!(stored[1].citations[0].document_id, 10);
+        assert_eq!(list_conversations(&conn).unwrap()[0].updated_at, reply.created_at);
+    }
+}
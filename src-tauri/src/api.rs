@@ -0,0 +1,538 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use reqwest::Url;
+use rusqlite::Connection;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager};
+
+use crate::db::{
+    delete_document, delete_folder, extract_title, insert_new_folder, load_document, load_folders, move_document,
+    rename_folder, save_document_in, save_timer_session, update_document, Document, EditorDocument, TimerSession,
+};
+use crate::error::AppError;
+use crate::markdown::{document_to_markdown, markdown_to_document};
+use crate::search::{hybrid_search, SearchFilters};
+use crate::settings::{get_json, set_json};
+use crate::sync::{record_change, ChangeOp, Entity};
+use crate::{ai, collab, events, summaries, vault, DB_PATH};
+
+// Opt-in HTTP API on 127.0.0.1 for scripts and editor plugins.
+//
+// Every route except the OpenAPI description needs `Authorization: Bearer <token>`.
+// The server only binds to loopback and also rejects requests whose `Host` is not
+// a loopback name, so web pages can't reach it through DNS rebinding. Writes go
+// through the same steps as the Tauri commands (sync log, collab, change events),
+// so open windows and background services see them like edits made in the app.
+
+const CONFIG_KEY: &str = "api";
+const PREFIX: &str = "/api/v1";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub port: u16,
+    pub token: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig { enabled: false, port: 48722, token: None }
+    }
+}
+
+// Managed Tauri state: the running server, so it can be stopped on reconfiguration
+#[derive(Default)]
+pub struct ApiState {
+    server: Mutex<Option<Arc<tiny_http::Server>>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocumentSummary {
+    pub id: i64,
+    pub title: String,
+    pub time: String,
+    pub folder_id: Option<i64>,
+}
+
+// Content as Editor.js JSON or as Markdown
+#[derive(Deserialize, Debug)]
+struct DocumentInput {
+    document: Option<EditorDocument>,
+    markdown: Option<String>,
+    folder_id: Option<i64>,
+}
+
+impl DocumentInput {
+    fn editor_document(&self) -> Result<EditorDocument, Failure> {
+        match (&self.document, &self.markdown) {
+            (Some(doc), _) => Ok(doc.clone()),
+            (None, Some(md)) => Ok(markdown_to_document(md, chrono::Utc::now().timestamp_millis())),
+            (None, None) => Err(Failure(400, "Expected `document` or `markdown`".to_string())),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct FolderInput {
+    name: String,
+    parent_id: Option<i64>,
+}
+
+// HTTP status and message of a failed request
+struct Failure(u16, String);
+
+impl From<AppError> for Failure {
+    fn from(e: AppError) -> Self {
+        let status = match &e {
+            AppError::VaultLocked => 423,
+            AppError::SqliteError(rusqlite::Error::QueryReturnedNoRows) | AppError::NotFound(_) => 404,
+            AppError::SerdeError(_) | AppError::InvalidInput(_) => 400,
+            _ => 500,
+        };
+        Failure(status, e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for Failure {
+    fn from(e: rusqlite::Error) -> Self {
+        AppError::from(e).into()
+    }
+}
+
+fn load_config(conn: &Connection) -> Result<ApiConfig, AppError> {
+    get_json(conn, CONFIG_KEY, ApiConfig::default())
+}
+
+fn body<T: DeserializeOwned>(request: &mut tiny_http::Request) -> Result<T, Failure> {
+    let mut text = String::new();
+    request.as_reader().read_to_string(&mut text).map_err(AppError::from)?;
+    serde_json::from_str(&text).map_err(|e| Failure(400, format!("Invalid request body: {}", e)))
+}
+
+fn header<'a>(request: &'a tiny_http::Request, name: &'static str) -> Option<&'a str> {
+    request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str())
+}
+
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        // `[::1]:48722`, `localhost:48722`
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    matches!(name, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+fn list_documents(conn: &Connection, folder_id: Option<i64>) -> Result<Vec<DocumentSummary>, AppError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, title, time, folder_id FROM documents WHERE ?1 IS NULL OR folder_id = ?1 ORDER BY id",
+    )?;
+    let docs = stmt
+        .query_map([folder_id], |row| {
+            Ok(DocumentSummary { id: row.get(0)?, title: row.get(1)?, time: row.get(2)?, folder_id: row.get(3)? })
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    Ok(docs)
+}
+
+fn read_document(conn: &Connection, id: i64) -> Result<Value, AppError> {
+    let doc = load_document(conn, id)?;
+    let editor_doc: EditorDocument = serde_json::from_str(&doc.content)?;
+    Ok(json!({
+        "id": doc.id,
+        "title": doc.title,
+        "time": doc.time,
+        "folder_id": doc.folder_id,
+        "markdown": document_to_markdown(&editor_doc),
+        "document": editor_doc,
+    }))
+}
+
+fn create_document(app: &AppHandle, conn: &Connection, input: DocumentInput) -> Result<i64, Failure> {
+    let doc = input.editor_document()?;
+    let id = save_document_in(conn, &doc, input.folder_id)?;
+    record_change(conn, Entity::Document, id, ChangeOp::Upsert)?;
+    let saved = load_document(conn, id)?;
+    events::document_created(app, events::DocumentCreated { id, title: saved.title, folder_id: saved.folder_id });
+    Ok(id)
+}
+
+fn replace_document(app: &AppHandle, conn: &Connection, id: i64, input: DocumentInput) -> Result<(), Failure> {
+    let existing = load_document(conn, id)?;
+    let doc = input.editor_document()?;
+    let doc_json = serde_json::to_string(&doc).map_err(AppError::from)?;
+    let title = summaries::generated_title(conn, id, &doc)
+        .unwrap_or_else(|| extract_title(&doc_json).unwrap_or_else(|| "Untitled".to_string()));
+    let db_doc = Document {
+        id,
+        title,
+        time: doc.time.to_string(),
+        content: doc_json,
+        folder_id: input.folder_id.or(existing.folder_id),
+    };
+    update_document(conn, id, &db_doc)?;
+    record_change(conn, Entity::Document, id, ChangeOp::Upsert)?;
+    collab::publish_local_update(app, id, &doc)?;
+    events::document_updated(app, events::DocumentUpdated {
+        id,
+        title: db_doc.title,
+        time: db_doc.time,
+        folder_id: db_doc.folder_id,
+        source_window: None,
+    });
+    if input.folder_id.is_some() && input.folder_id != existing.folder_id {
+        events::document_moved(app, events::DocumentMoved {
+            id,
+            from_folder_id: existing.folder_id,
+            to_folder_id: input.folder_id,
+        });
+    }
+    Ok(())
+}
+
+fn remove_document(app: &AppHandle, conn: &Connection, id: i64) -> Result<(), Failure> {
+    load_document(conn, id)?;
+    let folder_id = delete_document(conn, id)?;
+    record_change(conn, Entity::Document, id, ChangeOp::Delete)?;
+    events::document_deleted(app, events::DocumentDeleted { id, folder_id });
+    Ok(())
+}
+
+fn relocate_document(app: &AppHandle, conn: &Connection, id: i64, folder_id: Option<i64>) -> Result<(), Failure> {
+    load_document(conn, id)?;
+    let from_folder_id = move_document(conn, id, folder_id)?;
+    record_change(conn, Entity::Document, id, ChangeOp::Upsert)?;
+    events::document_moved(app, events::DocumentMoved { id, from_folder_id, to_folder_id: folder_id });
+    Ok(())
+}
+
+fn create_folder(app: &AppHandle, conn: &Connection, input: FolderInput) -> Result<i64, Failure> {
+    if input.name.trim().is_empty() {
+        return Err(Failure(400, "Folder name must not be empty".to_string()));
+    }
+    let id = insert_new_folder(conn, &input.name, input.parent_id)?;
+    record_change(conn, Entity::Folder, id, ChangeOp::Upsert)?;
+    events::folder_changed(app, events::FolderChanged {
+        id,
+        name: input.name,
+        parent_id: input.parent_id,
+        kind: events::FolderChangeKind::Created,
+    });
+    Ok(id)
+}
+
+fn rename(app: &AppHandle, conn: &Connection, id: i64, name: String) -> Result<(), Failure> {
+    let parent_id = rename_folder(conn, id, &name)?.ok_or_else(|| Failure(404, format!("No folder with id {}", id)))?;
+    record_change(conn, Entity::Folder, id, ChangeOp::Upsert)?;
+    events::folder_changed(app, events::FolderChanged { id, name, parent_id, kind: events::FolderChangeKind::Renamed });
+    Ok(())
+}
+
+fn remove_folder(app: &AppHandle, conn: &Connection, id: i64) -> Result<(), Failure> {
+    let (name, parent_id) = delete_folder(conn, id)?.ok_or_else(|| Failure(404, format!("No folder with id {}", id)))?;
+    record_change(conn, Entity::Folder, id, ChangeOp::Delete)?;
+    events::folder_changed(app, events::FolderChanged { id, name, parent_id, kind: events::FolderChangeKind::Deleted });
+    Ok(())
+}
+
+fn log_timer_session(app: &AppHandle, conn: &Connection, session: TimerSession) -> Result<i64, Failure> {
+    let id = save_timer_session(conn, &session)?;
+    record_change(conn, Entity::TimerSession, id, ChangeOp::Upsert)?;
+    events::timer_session_saved(app, events::TimerSessionSaved {
+        id,
+        work_duration: session.work_duration,
+        break_duration: session.break_duration,
+        start_time_work: session.start_time_work,
+    });
+    Ok(id)
+}
+
+fn route(app: &AppHandle, request: &mut tiny_http::Request, token: &str) -> Result<(u16, Value), Failure> {
+    let url = Url::parse(&format!("http://localhost{}", request.url()))
+        .map_err(|e| Failure(400, format!("Invalid URL: {}", e)))?;
+    let path = url.path().strip_prefix(PREFIX).ok_or_else(|| Failure(404, "Not found".to_string()))?;
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let query = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+    let id = |segment: &str| segment.parse::<i64>().map_err(|_| Failure(400, format!("Invalid id {}", segment)));
+    let method = request.method().as_str().to_uppercase();
+
+    if method == "GET" && segments == ["openapi.json"] {
+        return Ok((200, openapi()));
+    }
+    if header(request, "Authorization") != Some(format!("Bearer {}", token).as_str()) {
+        return Err(Failure(401, "Missing or invalid API token".to_string()));
+    }
+    vault::guard()?;
+    let conn = Connection::open(DB_PATH)?;
+
+    match (method.as_str(), segments.as_slice()) {
+        ("GET", ["documents"]) => {
+            let folder_id = query("folder_id").map(|f| id(&f)).transpose()?;
+            Ok((200, json!(list_documents(&conn, folder_id)?)))
+        }
+        ("POST", ["documents"]) => {
+            let input = body(request)?;
+            Ok((201, json!({ "id": create_document(app, &conn, input)? })))
+        }
+        ("GET", ["documents", doc]) => Ok((200, read_document(&conn, id(doc)?)?)),
+        ("PUT", ["documents", doc]) => {
+            let input = body(request)?;
+            replace_document(app, &conn, id(doc)?, input)?;
+            Ok((200, json!({ "id": id(doc)? })))
+        }
+        ("DELETE", ["documents", doc]) => {
+            remove_document(app, &conn, id(doc)?)?;
+            Ok((200, json!({ "id": id(doc)? })))
+        }
+        ("POST", ["documents", doc, "move"]) => {
+            #[derive(Deserialize)]
+            struct Move {
+                folder_id: Option<i64>,
+            }
+            let input: Move = body(request)?;
+            relocate_document(app, &conn, id(doc)?, input.folder_id)?;
+            Ok((200, json!({ "id": id(doc)? })))
+        }
+        ("GET", ["search"]) => {
+            let q = query("q").ok_or_else(|| Failure(400, "Missing query parameter `q`".to_string()))?;
+            let limit = query("limit").and_then(|l| l.parse().ok()).unwrap_or(20);
+            let filters = SearchFilters {
+                folder_id: query("folder_id").map(|f| id(&f)).transpose()?,
+                tags: url.query_pairs().filter(|(k, _)| k == "tag").map(|(_, v)| v.into_owned()).collect(),
+                ..SearchFilters::default()
+            };
+            let provider = ai::load_provider(&conn)?;
+            Ok((200, json!(hybrid_search(&conn, provider.as_ref(), &q, &filters, limit)?)))
+        }
+        ("GET", ["folders"]) => Ok((200, json!(load_folders(&conn)?))),
+        ("POST", ["folders"]) => {
+            let input = body(request)?;
+            Ok((201, json!({ "id": create_folder(app, &conn, input)? })))
+        }
+        ("PATCH", ["folders", folder]) => {
+            #[derive(Deserialize)]
+            struct Rename {
+                name: String,
+            }
+            let input: Rename = body(request)?;
+            rename(app, &conn, id(folder)?, input.name)?;
+            Ok((200, json!({ "id": id(folder)? })))
+        }
+        ("DELETE", ["folders", folder]) => {
+            remove_folder(app, &conn, id(folder)?)?;
+            Ok((200, json!({ "id": id(folder)? })))
+        }
+        ("POST", ["timer-sessions"]) => {
+            let session = body(request)?;
+            Ok((201, json!({ "id": log_timer_session(app, &conn, session)? })))
+        }
+        _ => Err(Failure(404, format!("No route for {} {}", method, url.path()))),
+    }
+}
+
+fn handle(app: &AppHandle, request: &mut tiny_http::Request, token: &str) -> Result<(u16, Value), Failure> {
+    let loopback_peer = request.remote_addr().map_or(false, |addr| addr.ip().is_loopback());
+    let loopback_host = header(request, "Host").map_or(false, is_loopback_host);
+    if !loopback_peer || !loopback_host {
+        return Err(Failure(403, "The API only accepts requests from this machine".to_string()));
+    }
+    route(app, request, token)
+}
+
+fn run_server(app: AppHandle, server: Arc<tiny_http::Server>, token: String) {
+    for mut request in server.incoming_requests() {
+        let (status, body) = match handle(&app, &mut request, &token) {
+            Ok(result) => result,
+            Err(Failure(status, message)) => {
+                eprintln!("API request {} {} failed: {}", request.method(), request.url(), message);
+                (status, json!({ "error": message }))
+            }
+        };
+        let response = tiny_http::Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header("Content-Type: application/json".parse::<tiny_http::Header>().unwrap());
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to answer API request: {}", e);
+        }
+    }
+}
+
+fn restart(app: &AppHandle, config: &ApiConfig) -> Result<(), AppError> {
+    let state = app.state::<ApiState>();
+    let mut running = state.server.lock().unwrap();
+    if let Some(server) = running.take() {
+        server.unblock();
+    }
+    let token = match (&config.token, config.enabled) {
+        (Some(token), true) => token.clone(),
+        _ => return Ok(()),
+    };
+    let server = tiny_http::Server::http(("127.0.0.1", config.port))
+        .map(Arc::new)
+        .map_err(|e| AppError::ServerError(format!("Failed to start API server: {}", e)))?;
+    println!("API listening on http://127.0.0.1:{}{}", config.port, PREFIX);
+    *running = Some(server.clone());
+    let handle = app.clone();
+    thread::spawn(move || run_server(handle, server, token));
+    Ok(())
+}
+
+pub fn start(app: AppHandle) {
+    let result = Connection::open(DB_PATH)
+        .map_err(AppError::from)
+        .and_then(|conn| load_config(&conn))
+        .and_then(|config| restart(&app, &config));
+    if let Err(e) = result {
+        eprintln!("API server setup failed: {}", e);
+    }
+}
+
+fn openapi() -> Value {
+    let id_param = |name: &str| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "integer" } });
+    let json_body = |schema: &str| {
+        json!({ "required": true, "content": { "application/json": { "schema": { "$ref": format!("#/components/schemas/{}", schema) } } } })
+    };
+    let ok = |description: &str| json!({ "200": { "description": description } });
+    let created = json!({ "201": { "description": "Created", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Id" } } } } });
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "j_desktop local API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Notes, folders and timer sessions of the running app. Only reachable from this machine."
+        },
+        "servers": [{ "url": PREFIX }],
+        "security": [{ "token": [] }],
+        "paths": {
+            "/documents": {
+                "get": {
+                    "summary": "List documents",
+                    "parameters": [{ "name": "folder_id", "in": "query", "schema": { "type": "integer" } }],
+                    "responses": { "200": { "description": "Documents without content", "content": { "application/json": { "schema": { "type": "array", "items": { "$ref": "#/components/schemas/DocumentSummary" } } } } } }
+                },
+                "post": { "summary": "Create a document", "requestBody": json_body("DocumentInput"), "responses": created.clone() }
+            },
+            "/documents/{id}": {
+                "parameters": [id_param("id")],
+                "get": { "summary": "Read a document as Editor.js JSON and Markdown", "responses": ok("The document") },
+                "put": { "summary": "Replace a document's content", "requestBody": json_body("DocumentInput"), "responses": ok("Updated") },
+                "delete": { "summary": "Delete a document", "responses": ok("Deleted") }
+            },
+            "/documents/{id}/move": {
+                "parameters": [id_param("id")],
+                "post": {
+                    "summary": "Move a document to another folder",
+                    "requestBody": { "required": true, "content": { "application/json": { "schema": { "type": "object", "properties": { "folder_id": { "type": "integer", "nullable": true } } } } } },
+                    "responses": ok("Moved")
+                }
+            },
+            "/search": {
+                "get": {
+                    "summary": "Hybrid keyword and semantic search",
+                    "parameters": [
+                        { "name": "q", "in": "query", "required": true, "schema": { "type": "string" } },
+                        { "name": "limit", "in": "query", "schema": { "type": "integer", "default": 20 } },
+                        { "name": "folder_id", "in": "query", "schema": { "type": "integer" } },
+                        { "name": "tag", "in": "query", "schema": { "type": "array", "items": { "type": "string" } }, "explode": true }
+                    ],
+                    "responses": ok("Results with match explanations")
+                }
+            },
+            "/folders": {
+                "get": { "summary": "List folders", "responses": ok("Folders with their document ids") },
+                "post": { "summary": "Create a folder", "requestBody": json_body("FolderInput"), "responses": created.clone() }
+            },
+            "/folders/{id}": {
+                "parameters": [id_param("id")],
+                "patch": {
+                    "summary": "Rename a folder",
+                    "requestBody": { "required": true, "content": { "application/json": { "schema": { "type": "object", "required": ["name"], "properties": { "name": { "type": "string" } } } } } },
+                    "responses": ok("Renamed")
+                },
+                "delete": { "summary": "Delete a folder; its documents and subfolders move to the parent", "responses": ok("Deleted") }
+            },
+            "/timer-sessions": {
+                "post": { "summary": "Log a timer session", "requestBody": json_body("TimerSession"), "responses": created }
+            }
+        },
+        "components": {
+            "securitySchemes": { "token": { "type": "http", "scheme": "bearer" } },
+            "schemas": {
+                "Id": { "type": "object", "properties": { "id": { "type": "integer" } } },
+                "DocumentSummary": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "title": { "type": "string" },
+                        "time": { "type": "string" },
+                        "folder_id": { "type": "integer", "nullable": true }
+                    }
+                },
+                "DocumentInput": {
+                    "type": "object",
+                    "description": "Either `document` (Editor.js JSON) or `markdown`",
+                    "properties": {
+                        "document": { "type": "object" },
+                        "markdown": { "type": "string" },
+                        "folder_id": { "type": "integer", "nullable": true }
+                    }
+                },
+                "FolderInput": {
+                    "type": "object",
+                    "required": ["name"],
+                    "properties": { "name": { "type": "string" }, "parent_id": { "type": "integer", "nullable": true } }
+                },
+                "TimerSession": {
+                    "type": "object",
+                    "required": ["work_duration", "break_duration", "start_time_work", "stop_time_work", "extended"],
+                    "properties": {
+                        "work_duration": { "type": "integer", "description": "Seconds" },
+                        "break_duration": { "type": "integer", "description": "Seconds" },
+                        "start_time_work": { "type": "string", "format": "date-time" },
+                        "stop_time_work": { "type": "string", "format": "date-time" },
+                        "start_time_break": { "type": "string", "format": "date-time", "nullable": true },
+                        "stop_time_break": { "type": "string", "format": "date-time", "nullable": true },
+                        "extended": { "type": "boolean" },
+                        "extended_start_time": { "type": "string", "format": "date-time", "nullable": true },
+                        "extended_stop_time": { "type": "string", "format": "date-time", "nullable": true }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[tauri::command]
+pub fn get_api_config_command() -> Result<ApiConfig, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    load_config(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_api_config_command(app: AppHandle, enabled: bool, port: u16) -> Result<ApiConfig, String> {
+    println!("set_api_config_command -> enabled: {}, port: {}", enabled, port);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let mut config = load_config(&conn).map_err(|e| e.to_string())?;
+    config.enabled = enabled;
+    config.port = port;
+    if config.token.is_none() {
+        config.token = Some(hex::encode(rand::random::<[u8; 32]>()));
+    }
+    set_json(&conn, CONFIG_KEY, &config).map_err(|e| e.to_string())?;
+    restart(&app, &config).map_err(|e| e.to_string())?;
+    Ok(config)
+}
+
+// Invalidates the current token; scripts need the new one
+#[tauri::command]
+pub fn regenerate_api_token_command(app: AppHandle) -> Result<ApiConfig, String> {
+    println!("regenerate_api_token_command");
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let mut config = load_config(&conn).map_err(|e| e.to_string())?;
+    config.token = Some(hex::encode(rand::random::<[u8; 32]>()));
+    set_json(&conn, CONFIG_KEY, &config).map_err(|e| e.to_string())?;
+    restart(&app, &config).map_err(|e| e.to_string())?;
+    Ok(config)
+}
@@ -0,0 +1,512 @@
+use std::collections::{HashMap, HashSet};
+
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager};
+
+use crate::credentials::{self, BackendCredentials};
+use crate::db::{create_python_document, load_document, load_documents, Document};
+use crate::error::AppError;
+use crate::settings::{get_setting, set_setting};
+use crate::outbox::{self, OutboxOp, OutboxState};
+use crate::DB_PATH;
+
+// Documents in the Python RAG backend, addressed through its `/files/` resource:
+// `POST /files/` creates a file and returns its id, `PUT /files/{id}` replaces the
+// content and re-embeds it, `DELETE /files/{id}` removes the file with its
+// embeddings and `GET /files/` lists everything the backend has.
+//
+// `backend_files` maps each sent document to its remote id together with a hash of
+// the last body sent, so unchanged documents are not uploaded again.
+//
+// Every document goes to one collection: its own binding if it has one, otherwise
+// the binding of the nearest folder up the tree, otherwise the default collection.
+// Collections themselves are managed through `GET`/`POST /collections/`.
+
+const DEFAULT_COLLECTION_KEY: &str = "backend.default_collection";
+const DEFAULT_COLLECTION: &str = "my_notes";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteFile {
+    pub id: String,
+    #[serde(default)]
+    pub filename: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Collection {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BindingTarget {
+    Folder,
+    Document,
+}
+
+impl BindingTarget {
+    fn as_str(self) -> &'static str {
+        match self {
+            BindingTarget::Folder => "folder",
+            BindingTarget::Document => "document",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CollectionBinding {
+    pub target: BindingTarget,
+    pub target_id: i64,
+    pub collection: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ReconcileReport {
+    // Local documents the backend does not have, queued for creation
+    pub missing_remote: usize,
+    // Documents whose content changed since they were last sent, queued for update
+    pub stale: usize,
+    // Remote files of deleted documents, queued for deletion
+    pub deleted_locally: usize,
+    // Remote files no local document points to
+    pub orphaned: Vec<RemoteFile>,
+    pub removed_orphans: usize,
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    // No foreign key on purpose: the row has to outlive the document so the delete
+    // can still find the remote id
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS backend_files (
+            document_id INTEGER PRIMARY KEY,
+            remote_id TEXT NOT NULL,
+            content_hash TEXT NOT NULL,
+            synced_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS collection_bindings (
+            target TEXT NOT NULL,
+            target_id INTEGER NOT NULL,
+            collection TEXT NOT NULL,
+            PRIMARY KEY (target, target_id)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn binding(conn: &Connection, target: BindingTarget, target_id: i64) -> Result<Option<String>, AppError> {
+    let collection = conn
+        .query_row(
+            "SELECT collection FROM collection_bindings WHERE target = ? AND target_id = ?",
+            params![target.as_str(), target_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(collection)
+}
+
+pub fn default_collection(conn: &Connection) -> Result<String, AppError> {
+    Ok(get_setting(conn, DEFAULT_COLLECTION_KEY)?.unwrap_or_else(|| DEFAULT_COLLECTION.to_string()))
+}
+
+pub fn collection_for(conn: &Connection, doc: &Document) -> Result<String, AppError> {
+    if let Some(collection) = binding(conn, BindingTarget::Document, doc.id)? {
+        return Ok(collection);
+    }
+    let mut folder = doc.folder_id;
+    let mut depth = 0;
+    // Bounded walk in case of a parent cycle
+    while let Some(id) = folder.filter(|_| depth < 32) {
+        if let Some(collection) = binding(conn, BindingTarget::Folder, id)? {
+            return Ok(collection);
+        }
+        folder = conn
+            .query_row("SELECT parent_id FROM folders WHERE id = ?", [id], |row| row.get(0))
+            .optional()?
+            .flatten();
+        depth += 1;
+    }
+    default_collection(conn)
+}
+
+// Chroma-style names: 3 to 63 characters of letters, digits, `.`, `_` and `-`
+fn validate_collection_name(name: &str) -> Result<(), String> {
+    let valid_chars = name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if (3..=63).contains(&name.len()) && valid_chars {
+        Ok(())
+    } else {
+        Err(format!("Invalid collection name '{}': use 3-63 letters, digits, '.', '_' or '-'", name))
+    }
+}
+
+fn remote_id(conn: &Connection, document_id: i64) -> Result<Option<String>, AppError> {
+    let id = conn
+        .query_row("SELECT remote_id FROM backend_files WHERE document_id = ?", [document_id], |row| row.get(0))
+        .optional()?;
+    Ok(id)
+}
+
+fn stored_hash(conn: &Connection, document_id: i64) -> Result<Option<String>, AppError> {
+    let hash = conn
+        .query_row("SELECT content_hash FROM backend_files WHERE document_id = ?", [document_id], |row| row.get(0))
+        .optional()?;
+    Ok(hash)
+}
+
+pub fn tracked_documents(conn: &Connection) -> Result<Vec<i64>, AppError> {
+    let mut stmt = conn.prepare("SELECT document_id FROM backend_files")?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+    Ok(ids)
+}
+
+pub fn is_tracked(conn: &Connection, document_id: i64) -> Result<bool, AppError> {
+    Ok(remote_id(conn, document_id)?.is_some())
+}
+
+// Folder names from the root down to `folder_id`, joined with "/"
+pub fn folder_path(conn: &Connection, folder_id: Option<i64>) -> Result<String, AppError> {
+    let mut names = Vec::new();
+    let mut folder = folder_id;
+    while let Some(id) = folder.filter(|_| names.len() < 32) {
+        let row: Option<(String, Option<i64>)> = conn
+            .query_row("SELECT name, parent_id FROM folders WHERE id = ?", [id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+        match row {
+            Some((name, parent_id)) => {
+                names.push(name);
+                folder = parent_id;
+            }
+            None => break,
+        }
+    }
+    names.reverse();
+    Ok(names.join("/"))
+}
+
+fn body_for(conn: &Connection, doc: &Document) -> Result<Value, AppError> {
+    let collection = collection_for(conn, doc)?;
+    let path = folder_path(conn, doc.folder_id)?;
+    Ok(json!(create_python_document(doc, &collection, &path)?))
+}
+
+fn body_hash(body: &Value) -> String {
+    hex::encode(Sha256::digest(body.to_string().as_bytes()))
+}
+
+fn file_id(response: &Value) -> Option<String> {
+    match response.get("id")? {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+fn create_remote(backend: &BackendCredentials, body: &Value) -> Result<Option<String>, AppError> {
+    let response: Value = Client::new()
+        .post(backend.endpoint("files/"))
+        .bearer_auth(&backend.token)
+        .json(body)
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(file_id(&response))
+}
+
+// Sends the current content of a document, creating the remote file when the
+// backend does not know it yet
+pub fn upsert_document(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    let doc = match load_document(conn, document_id) {
+        Ok(doc) => doc,
+        // Deleted in the meantime, possibly by a peer sync that emits no delete event
+        Err(AppError::SqliteError(rusqlite::Error::QueryReturnedNoRows)) => return delete_document(conn, document_id),
+        Err(e) => return Err(e),
+    };
+    let body = body_for(conn, &doc)?;
+    let hash = body_hash(&body);
+    if stored_hash(conn, document_id)?.as_deref() == Some(hash.as_str()) {
+        return Ok(());
+    }
+
+    let backend = credentials::backend(credentials::PYTHON_BACKEND)?;
+    let id = match remote_id(conn, document_id)? {
+        Some(id) => {
+            let res = Client::new()
+                .put(backend.endpoint(&format!("files/{}", id)))
+                .bearer_auth(&backend.token)
+                .json(&body)
+                .send()?;
+            if res.status() == StatusCode::NOT_FOUND {
+                // Removed on the backend side, e.g. by a reset
+                create_remote(&backend, &body)?
+            } else {
+                res.error_for_status()?;
+                Some(id)
+            }
+        }
+        None => create_remote(&backend, &body)?,
+    };
+
+    match id {
+        Some(id) => {
+            conn.execute(
+                "INSERT INTO backend_files (document_id, remote_id, content_hash) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(document_id) DO UPDATE SET
+                    remote_id = excluded.remote_id,
+                    content_hash = excluded.content_hash,
+                    synced_at = datetime('now')",
+                params![document_id, id, hash],
+            )?;
+        }
+        None => eprintln!("Backend returned no file id for document {}; it will not be updated", document_id),
+    }
+    Ok(())
+}
+
+pub fn delete_document(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    let id = match remote_id(conn, document_id)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let backend = credentials::backend(credentials::PYTHON_BACKEND)?;
+    let res = Client::new()
+        .delete(backend.endpoint(&format!("files/{}", id)))
+        .bearer_auth(&backend.token)
+        .send()?;
+    if res.status() != StatusCode::NOT_FOUND {
+        res.error_for_status()?;
+    }
+    conn.execute("DELETE FROM backend_files WHERE document_id = ?", [document_id])?;
+    Ok(())
+}
+
+fn list_remote_files(backend: &BackendCredentials) -> Result<Vec<RemoteFile>, AppError> {
+    let files: Vec<Value> = Client::new()
+        .get(backend.endpoint("files/"))
+        .bearer_auth(&backend.token)
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(files
+        .iter()
+        .filter_map(|file| {
+            Some(RemoteFile {
+                id: file_id(file)?,
+                filename: file.get("filename").and_then(Value::as_str).map(str::to_string),
+            })
+        })
+        .collect())
+}
+
+// Compares local documents with the backend's file list and queues whatever is
+// needed to bring them in line. Orphaned remote files are only deleted on request,
+// since the backend may hold files that did not come from this app.
+pub fn reconcile(conn: &Connection, remove_orphans: bool) -> Result<ReconcileReport, AppError> {
+    let backend = credentials::backend(credentials::PYTHON_BACKEND)?;
+    let remote: HashMap<String, RemoteFile> =
+        list_remote_files(&backend)?.into_iter().map(|file| (file.id.clone(), file)).collect();
+
+    let tracked: HashMap<i64, String> = {
+        let mut stmt = conn.prepare("SELECT document_id, remote_id FROM backend_files")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<i64, String>, rusqlite::Error>>()?;
+        rows
+    };
+
+    let mut report = ReconcileReport::default();
+    let mut local_ids = HashSet::new();
+    for doc in load_documents(conn)? {
+        local_ids.insert(doc.id);
+        match tracked.get(&doc.id) {
+            Some(id) if remote.contains_key(id) => {
+                let hash = match body_for(conn, &doc) {
+                    Ok(body) => body_hash(&body),
+                    Err(e) => {
+                        eprintln!("Skipping document {} in reconcile: {}", doc.id, e);
+                        continue;
+                    }
+                };
+                if stored_hash(conn, doc.id)?.as_deref() != Some(hash.as_str()) {
+                    outbox::enqueue(conn, OutboxOp::UpdateDocument, doc.id)?;
+                    report.stale += 1;
+                }
+            }
+            Some(_) => {
+                // The backend lost the file; forget the id so it is created again
+                conn.execute("DELETE FROM backend_files WHERE document_id = ?", [doc.id])?;
+                outbox::enqueue(conn, OutboxOp::CreateDocument, doc.id)?;
+                report.missing_remote += 1;
+            }
+            None => {
+                outbox::enqueue(conn, OutboxOp::CreateDocument, doc.id)?;
+                report.missing_remote += 1;
+            }
+        }
+    }
+
+    for (document_id, id) in &tracked {
+        if local_ids.contains(document_id) {
+            continue;
+        }
+        if remote.contains_key(id) {
+            outbox::enqueue(conn, OutboxOp::DeleteDocument, *document_id)?;
+            report.deleted_locally += 1;
+        } else {
+            conn.execute("DELETE FROM backend_files WHERE document_id = ?", [document_id])?;
+        }
+    }
+
+    let known: HashSet<&String> = tracked.values().collect();
+    report.orphaned = remote.values().filter(|file| !known.contains(&file.id)).cloned().collect();
+    if remove_orphans {
+        for file in &report.orphaned {
+            Client::new()
+                .delete(backend.endpoint(&format!("files/{}", file.id)))
+                .bearer_auth(&backend.token)
+                .send()?
+                .error_for_status()?;
+            report.removed_orphans += 1;
+        }
+    }
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn reconcile_backend_command(app: AppHandle, remove_orphans: bool) -> Result<ReconcileReport, String> {
+    println!("reconcile_backend_command -> remove_orphans: {}", remove_orphans);
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        let conn = Connection::open(DB_PATH)?;
+        reconcile(&conn, remove_orphans)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())?;
+    app.state::<OutboxState>().notify();
+    Ok(report)
+}
+
+// Queues an update for every document the backend has; only those whose
+// collection actually changed are sent
+fn requeue_tracked(app: &AppHandle, conn: &Connection) -> Result<(), AppError> {
+    for id in tracked_documents(conn)? {
+        outbox::enqueue(conn, OutboxOp::UpdateDocument, id)?;
+    }
+    app.state::<OutboxState>().notify();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_backend_collections_command() -> Result<Vec<Collection>, String> {
+    tauri::async_runtime::spawn_blocking(|| {
+        let backend = credentials::backend(credentials::PYTHON_BACKEND)?;
+        let collections: Vec<Collection> = Client::new()
+            .get(backend.endpoint("collections/"))
+            .bearer_auth(&backend.token)
+            .send()?
+            .error_for_status()?
+            .json()?;
+        Ok(collections)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+#[tauri::command]
+pub async fn create_backend_collection_command(name: String, description: Option<String>) -> Result<Collection, String> {
+    println!("create_backend_collection_command -> name: {}", name);
+    validate_collection_name(&name)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let backend = credentials::backend(credentials::PYTHON_BACKEND)?;
+        let collection = Collection { name, description };
+        Client::new()
+            .post(backend.endpoint("collections/"))
+            .bearer_auth(&backend.token)
+            .json(&collection)
+            .send()?
+            .error_for_status()?;
+        Ok(collection)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+#[tauri::command]
+pub fn collection_bindings_command() -> Result<Vec<CollectionBinding>, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("SELECT target, target_id, collection FROM collection_bindings ORDER BY target, target_id")
+        .map_err(|e| e.to_string())?;
+    let bindings = stmt
+        .query_map([], |row| {
+            let target: String = row.get(0)?;
+            Ok(CollectionBinding {
+                target: if target == "document" { BindingTarget::Document } else { BindingTarget::Folder },
+                target_id: row.get(1)?,
+                collection: row.get(2)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<CollectionBinding>, rusqlite::Error>>()
+        .map_err(|e| e.to_string())?;
+    Ok(bindings)
+}
+
+// Binds a folder (and everything below it) or a single document to a collection;
+// `None` removes the binding
+#[tauri::command]
+pub fn bind_collection_command(
+    app: AppHandle,
+    target: BindingTarget,
+    target_id: i64,
+    collection: Option<String>,
+) -> Result<(), String> {
+    println!("bind_collection_command -> {} {}: {:?}", target.as_str(), target_id, collection);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    match collection {
+        Some(collection) => {
+            validate_collection_name(&collection)?;
+            conn.execute(
+                "INSERT INTO collection_bindings (target, target_id, collection) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(target, target_id) DO UPDATE SET collection = excluded.collection",
+                params![target.as_str(), target_id, collection],
+            )
+        }
+        None => conn.execute(
+            "DELETE FROM collection_bindings WHERE target = ? AND target_id = ?",
+            params![target.as_str(), target_id],
+        ),
+    }
+    .map_err(|e| e.to_string())?;
+    requeue_tracked(&app, &conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_default_collection_command() -> Result<String, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    default_collection(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_default_collection_command(app: AppHandle, name: String) -> Result<(), String> {
+    println!("set_default_collection_command -> name: {}", name);
+    validate_collection_name(&name)?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    set_setting(&conn, DEFAULT_COLLECTION_KEY, &name).map_err(|e| e.to_string())?;
+    requeue_tracked(&app, &conn).map_err(|e| e.to_string())
+}
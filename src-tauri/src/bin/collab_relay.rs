@@ -0,0 +1,14 @@
+// Standalone relay for collaborative editing. Like the relay in the app it only
+// listens on loopback by default; pass an address to open it to the LAN:
+//
+//     cargo run --bin collab_relay -- 0.0.0.0:9473
+use app_lib::collab_relay;
+
+#[tokio::main]
+async fn main() {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:9473".to_string());
+    if let Err(e) = collab_relay::run(&addr).await {
+        eprintln!("Collab relay stopped: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,400 @@
+// Command-line access to a vault without the app.
+//
+//     cargo run --bin jd -- --vault ~/notes.db list --json
+//     jd search "reading list" --tag books --limit 5
+//     jd create meeting.md --folder 3
+//
+// Reuses the app's modules, so writes go through the same sync change log and an
+// encrypted vault is unlocked with the passphrase in `J_DESKTOP_PASSPHRASE`. The
+// running app doesn't get change events from here; it sees the changes when it
+// reloads the affected documents, and the next sync passes them to peers.
+//
+// With `--json` every command prints one JSON value; without it, records are
+// printed one per line with tab-separated fields. Errors go to stderr with exit
+// status 1 (2 for usage errors).
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::json;
+
+use app_lib::{ai, backend, db, error, git_mirror, markdown, search, sync, vault, DB_PATH};
+use db::{load_document, load_documents, move_document, save_document_in, EditorDocument};
+use error::AppError;
+use git_mirror::MirrorFormat;
+use search::{hybrid_search, SearchFilters};
+use sync::{record_change, ChangeOp, Entity};
+
+const USAGE: &str = "Usage: jd [--vault PATH] [--json] <command> [arguments]
+
+Commands:
+  list [--folder ID]                        Documents with id, title, folder and time
+  show ID [--format markdown|json]          A document as Markdown or Editor.js JSON
+  search QUERY [--limit N] [--folder ID] [--tag TAG]...
+                                            Keyword and semantic search
+  create FILE [--folder ID]                 New document from a .md or .json file, - for stdin
+  move ID FOLDER_ID|root                    Move a document to another folder
+  export DIR [--format markdown|json]       Write every document into a folder tree
+  import DIR                                Read a tree written by export back in
+  timer-stats [--since DATE] [--until DATE] Totals of timer sessions, per day
+  backup FILE                               Encrypted copy of the vault, passphrase
+                                            from J_DESKTOP_BACKUP_PASSPHRASE";
+
+enum Failure {
+    // Wrong invocation, as opposed to a failing command
+    Usage(String),
+    App(AppError),
+}
+
+impl From<AppError> for Failure {
+    fn from(e: AppError) -> Self {
+        Failure::App(e)
+    }
+}
+
+impl From<rusqlite::Error> for Failure {
+    fn from(e: rusqlite::Error) -> Self {
+        Failure::App(e.into())
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Self {
+        Failure::App(e.into())
+    }
+}
+
+impl From<serde_json::Error> for Failure {
+    fn from(e: serde_json::Error) -> Self {
+        Failure::App(e.into())
+    }
+}
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, Vec<String>>,
+    json: bool,
+}
+
+impl Args {
+    fn parse(mut raw: impl Iterator<Item = String>) -> Result<Args, Failure> {
+        let mut args = Args { positional: Vec::new(), options: HashMap::new(), json: false };
+        while let Some(arg) = raw.next() {
+            match arg.strip_prefix("--") {
+                Some("json") => args.json = true,
+                Some("help") => return Err(Failure::Usage(String::new())),
+                Some(name) => {
+                    let value = raw.next().ok_or_else(|| Failure::Usage(format!("--{} needs a value", name)))?;
+                    args.options.entry(name.to_string()).or_default().push(value);
+                }
+                None => args.positional.push(arg),
+            }
+        }
+        Ok(args)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|values| values.last()).map(String::as_str)
+    }
+
+    fn id_option(&self, name: &str) -> Result<Option<i64>, Failure> {
+        self.option(name).map(parse_id).transpose()
+    }
+
+    fn argument(&self, index: usize, name: &str) -> Result<&str, Failure> {
+        self.positional.get(index).map(String::as_str).ok_or_else(|| Failure::Usage(format!("Missing {}", name)))
+    }
+}
+
+fn parse_id(value: &str) -> Result<i64, Failure> {
+    value.parse().map_err(|_| Failure::Usage(format!("Not an id: {}", value)))
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), Failure> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+// Tabs and newlines inside a field would break the one-record-per-line output
+fn field(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
+
+fn open_vault(args: &Args) -> Result<Connection, Failure> {
+    // Defaults to the database the app opens
+    let path = args
+        .option("vault")
+        .map(str::to_string)
+        .or_else(|| std::env::var("J_DESKTOP_VAULT").ok())
+        .unwrap_or_else(|| DB_PATH.to_string());
+    // Opening a missing path would create an empty database
+    if !Path::new(&path).is_file() {
+        return Err(Failure::Usage(format!("No vault at {}", path)));
+    }
+    let conn = Connection::open(&path)?;
+    vault::init(&conn)?;
+    if vault::is_locked() {
+        let passphrase = std::env::var("J_DESKTOP_PASSPHRASE").map_err(|_| AppError::VaultLocked)?;
+        vault::unlock(&conn, &passphrase)?;
+    }
+    Ok(conn)
+}
+
+fn list(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let folder_id = args.id_option("folder")?;
+    let mut rows = Vec::new();
+    for doc in load_documents(conn)? {
+        if folder_id.is_some() && doc.folder_id != folder_id {
+            continue;
+        }
+        let title = markdown::inline_to_text(&doc.title);
+        let folder_path = backend::folder_path(conn, doc.folder_id)?;
+        rows.push(json!({
+            "id": doc.id,
+            "title": title,
+            "folder_id": doc.folder_id,
+            "folder_path": folder_path,
+            "time": doc.time,
+        }));
+    }
+    if args.json {
+        return print_json(&rows);
+    }
+    for row in rows {
+        println!(
+            "{}\t{}\t{}\t{}",
+            row["id"],
+            field(row["title"].as_str().unwrap_or_default()),
+            field(row["folder_path"].as_str().unwrap_or_default()),
+            row["time"].as_str().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn show(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let id = parse_id(args.argument(1, "document id")?)?;
+    let doc = load_document(conn, id)?;
+    let editor_doc: EditorDocument = serde_json::from_str(&doc.content)?;
+    let markdown = markdown::document_to_markdown(&editor_doc);
+    match (args.json, args.option("format").unwrap_or("markdown")) {
+        (true, _) => print_json(&json!({
+            "id": doc.id,
+            "title": markdown::inline_to_text(&doc.title),
+            "folder_id": doc.folder_id,
+            "time": doc.time,
+            "markdown": markdown,
+            "document": editor_doc,
+        })),
+        (false, "json") => print_json(&editor_doc),
+        (false, "markdown") => {
+            println!("{}", markdown);
+            Ok(())
+        }
+        (false, other) => Err(Failure::Usage(format!("Unknown format {}", other))),
+    }
+}
+
+fn search(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let query = args.argument(1, "search query")?;
+    let limit = match args.option("limit") {
+        Some(limit) => limit.parse().map_err(|_| Failure::Usage(format!("Not a number: {}", limit)))?,
+        None => 20,
+    };
+    let filters = SearchFilters {
+        folder_id: args.id_option("folder")?,
+        tags: args.options.get("tag").cloned().unwrap_or_default(),
+        ..SearchFilters::default()
+    };
+    // A vault last opened by an older app has no keyword index yet
+    search::create_tables(conn)?;
+    let provider = ai::load_provider(conn)?;
+    // The app indexes in the background; unchanged chunks keep their vectors here too
+    ai::index_all(conn, provider.as_ref())?;
+    let results = hybrid_search(conn, provider.as_ref(), query, &filters, limit)?;
+    if args.json {
+        return print_json(&results);
+    }
+    for result in results {
+        println!(
+            "{}\t{:.4}\t{}\t{}\t{}",
+            result.document_id,
+            result.score,
+            field(&result.title),
+            field(&result.folder_path),
+            field(&result.snippet)
+        );
+    }
+    Ok(())
+}
+
+fn create(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let file = args.argument(1, "file")?;
+    let text = if file == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(file)?
+    };
+    let doc: EditorDocument = if file.ends_with(".json") {
+        serde_json::from_str(&text)?
+    } else {
+        markdown::markdown_to_document(&text, chrono::Utc::now().timestamp_millis())
+    };
+    let id = save_document_in(conn, &doc, args.id_option("folder")?)?;
+    record_change(conn, Entity::Document, id, ChangeOp::Upsert)?;
+    if args.json {
+        return print_json(&json!({ "id": id }));
+    }
+    println!("{}", id);
+    Ok(())
+}
+
+fn move_to_folder(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let id = parse_id(args.argument(1, "document id")?)?;
+    let folder_id = match args.argument(2, "folder id")? {
+        "root" => None,
+        folder => Some(parse_id(folder)?),
+    };
+    load_document(conn, id)?;
+    let from_folder_id = move_document(conn, id, folder_id)?;
+    record_change(conn, Entity::Document, id, ChangeOp::Upsert)?;
+    if args.json {
+        return print_json(&json!({ "id": id, "from_folder_id": from_folder_id, "to_folder_id": folder_id }));
+    }
+    Ok(())
+}
+
+fn export(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let dir = args.argument(1, "directory")?;
+    let format = match args.option("format").unwrap_or("markdown") {
+        "markdown" => MirrorFormat::Markdown,
+        "json" => MirrorFormat::Json,
+        other => return Err(Failure::Usage(format!("Unknown format {}", other))),
+    };
+    fs::create_dir_all(dir)?;
+    git_mirror::export_tree(conn, Path::new(dir), format)?;
+    if args.json {
+        return print_json(&json!({ "path": dir }));
+    }
+    Ok(())
+}
+
+fn import(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let dir = args.argument(1, "directory")?;
+    if !Path::new(dir).is_dir() {
+        return Err(Failure::Usage(format!("Not a directory: {}", dir)));
+    }
+    // Files are left in place; a re-import creates their documents again
+    let report = git_mirror::import_tree(conn, Path::new(dir), None, false)?;
+    if args.json {
+        return print_json(&report);
+    }
+    println!("created\t{}\nupdated\t{}\nunchanged\t{}", report.created, report.updated, report.unchanged);
+    Ok(())
+}
+
+#[derive(Serialize, Default)]
+struct TimerTotals {
+    date: String,
+    sessions: i64,
+    work_seconds: i64,
+    break_seconds: i64,
+    extended: i64,
+}
+
+// Dates are compared on the `YYYY-MM-DD` prefix of the stored ISO 8601 start time
+fn timer_stats(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let mut stmt = conn.prepare(
+        "SELECT substr(start_time_work, 1, 10) AS day, COUNT(*), SUM(work_duration), SUM(break_duration), SUM(extended)
+         FROM timer_sessions
+         WHERE (?1 IS NULL OR day >= ?1) AND (?2 IS NULL OR day <= ?2)
+         GROUP BY day ORDER BY day",
+    )?;
+    let days = stmt
+        .query_map([args.option("since"), args.option("until")], |row| {
+            Ok(TimerTotals {
+                date: row.get(0)?,
+                sessions: row.get(1)?,
+                work_seconds: row.get(2)?,
+                break_seconds: row.get(3)?,
+                extended: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    let total = days.iter().fold(TimerTotals::default(), |total, day| TimerTotals {
+        date: String::new(),
+        sessions: total.sessions + day.sessions,
+        work_seconds: total.work_seconds + day.work_seconds,
+        break_seconds: total.break_seconds + day.break_seconds,
+        extended: total.extended + day.extended,
+    });
+    if args.json {
+        return print_json(&json!({
+            "sessions": total.sessions,
+            "work_seconds": total.work_seconds,
+            "break_seconds": total.break_seconds,
+            "extended": total.extended,
+            "days": days,
+        }));
+    }
+    for day in days.iter().chain(std::iter::once(&TimerTotals { date: "total".to_string(), ..total })) {
+        println!("{}\t{}\t{}\t{}\t{}", day.date, day.sessions, day.work_seconds, day.break_seconds, day.extended);
+    }
+    Ok(())
+}
+
+fn backup(conn: &Connection, args: &Args) -> Result<(), Failure> {
+    let file = args.argument(1, "backup file")?;
+    let passphrase = std::env::var("J_DESKTOP_BACKUP_PASSPHRASE")
+        .map_err(|_| Failure::Usage("Set J_DESKTOP_BACKUP_PASSPHRASE to the backup passphrase".to_string()))?;
+    vault::write_backup(conn, file, &passphrase)?;
+    if args.json {
+        return print_json(&json!({ "path": file }));
+    }
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<(), Failure> {
+    let command = args.argument(0, "command")?;
+    let handler = match command {
+        "list" => list,
+        "show" => show,
+        "search" => search,
+        "create" => create,
+        "move" => move_to_folder,
+        "export" => export,
+        "import" => import,
+        "timer-stats" => timer_stats,
+        "backup" => backup,
+        other => return Err(Failure::Usage(format!("Unknown command {}", other))),
+    };
+    let conn = open_vault(args)?;
+    handler(&conn, args)
+}
+
+fn main() {
+    let result = Args::parse(std::env::args().skip(1)).and_then(|args| run(&args));
+    match result {
+        Ok(()) => {}
+        Err(Failure::Usage(message)) => {
+            if !message.is_empty() {
+                eprintln!("{}\n", message);
+            }
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+        Err(Failure::App(e)) => {
+            // The display of wrapped errors ("SQLite error") hides what went wrong
+            match std::error::Error::source(&e) {
+                Some(source) => eprintln!("{}: {}", e, source),
+                None => eprintln!("{}", e),
+            }
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::{
+    AppHandle, CustomMenuItem, GlobalShortcutManager, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu,
+    SystemTrayMenuItem, WindowBuilder, WindowUrl,
+};
+
+use crate::db::{insert_new_folder, load_document, save_document};
+use crate::error::AppError;
+use crate::markdown::markdown_to_document;
+use crate::settings::{get_json, set_json};
+use crate::sync::{record_change, ChangeOp, Entity};
+use crate::{events, vault, DB_PATH};
+
+// Quick capture: a global shortcut and a tray entry open a small window whose text
+// is saved as a new note in the Inbox folder, which is created on first use.
+// Scripts can post captures to an optional HTTP endpoint on localhost:
+//
+//     curl -H "Authorization: Bearer <token>" -d "Buy milk" http://127.0.0.1:48721/capture
+//
+// The body is Markdown, or JSON `{"text": "...", "title": "..."}` with a
+// `Content-Type: application/json` header.
+
+const CONFIG_KEY: &str = "capture";
+const WINDOW_LABEL: &str = "capture";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CaptureConfig {
+    pub inbox_folder: String,
+    pub shortcut: String,
+    pub http_enabled: bool,
+    pub http_port: u16,
+    // Generated when the endpoint is first enabled
+    pub http_token: Option<String>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            inbox_folder: "Inbox".to_string(),
+            shortcut: "CmdOrCtrl+Shift+Space".to_string(),
+            http_enabled: false,
+            http_port: 48721,
+            http_token: None,
+        }
+    }
+}
+
+// Managed Tauri state: what is currently registered, so a config change can undo it
+#[derive(Default)]
+pub struct CaptureState {
+    shortcut: Mutex<Option<String>>,
+    server: Mutex<Option<Arc<tiny_http::Server>>>,
+}
+
+#[derive(Deserialize)]
+struct CaptureRequest {
+    text: String,
+    title: Option<String>,
+}
+
+fn load_config(conn: &Connection) -> Result<CaptureConfig, AppError> {
+    get_json(conn, CONFIG_KEY, CaptureConfig::default())
+}
+
+// Id of the top-level folder called `name`, created if missing; also used for the
+// daily notes folder
+pub fn top_level_folder(app: &AppHandle, conn: &Connection, name: &str) -> Result<i64, AppError> {
+    let existing: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM folders WHERE name = ? AND parent_id IS NULL ORDER BY id LIMIT 1",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+    let id = insert_new_folder(conn, name, None)?;
+    record_change(conn, Entity::Folder, id, ChangeOp::Upsert)?;
+    events::folder_changed(app, events::FolderChanged {
+        id,
+        name: name.to_string(),
+        parent_id: None,
+        kind: events::FolderChangeKind::Created,
+    });
+    Ok(id)
+}
+
+pub fn capture(app: &AppHandle, text: &str, title: Option<&str>) -> Result<i64, AppError> {
+    vault::guard()?;
+    if text.trim().is_empty() {
+        return Err(AppError::InvalidInput("Nothing to capture".to_string()));
+    }
+    let conn = Connection::open(DB_PATH)?;
+    let config = load_config(&conn)?;
+    let folder_id = top_level_folder(app, &conn, &config.inbox_folder)?;
+
+    let markdown = match title.map(str::trim).filter(|t| !t.is_empty()) {
+        Some(title) => format!("# {}\n\n{}", title, text),
+        None => text.to_string(),
+    };
+    let doc = markdown_to_document(&markdown, chrono::Utc::now().timestamp_millis());
+    let id = save_document(&conn, &doc, &folder_id)?;
+    record_change(&conn, Entity::Document, id, ChangeOp::Upsert)?;
+    let saved = load_document(&conn, id)?;
+    println!("Captured document {} into folder {}", id, folder_id);
+
+    events::document_created(app, events::DocumentCreated { id, title: saved.title, folder_id: saved.folder_id });
+    Ok(id)
+}
+
+pub fn open_capture_window(app: &AppHandle) -> Result<(), AppError> {
+    let result = match app.get_window(WINDOW_LABEL) {
+        Some(window) => window.show().and_then(|_| window.set_focus()),
+        None => WindowBuilder::new(app, WINDOW_LABEL, WindowUrl::App("index.html?capture=1".into()))
+            .title("Quick capture")
+            .inner_size(480.0, 220.0)
+            .resizable(false)
+            .always_on_top(true)
+            .decorations(false)
+            .center()
+            .focused(true)
+            .build()
+            .map(|_| ()),
+    };
+    result.map_err(|e| AppError::WindowError(format!("Failed to open capture window: {}", e)))
+}
+
+pub fn tray() -> SystemTray {
+    let menu = SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new("capture", "Quick capture"))
+        .add_item(CustomMenuItem::new("show", "Show j_desktop"))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new("quit", "Quit"));
+    SystemTray::new().with_menu(menu)
+}
+
+fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_window("main") {
+        if let Err(e) = window.show().and_then(|_| window.set_focus()) {
+            eprintln!("Failed to show main window: {}", e);
+        }
+    }
+}
+
+pub fn on_tray_event(app: &AppHandle, event: SystemTrayEvent) {
+    match event {
+        SystemTrayEvent::LeftClick { .. } => show_main_window(app),
+        SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
+            "capture" => {
+                if let Err(e) = open_capture_window(app) {
+                    eprintln!("{}", e);
+                }
+            }
+            "show" => show_main_window(app),
+            "quit" => app.exit(0),
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+fn register_shortcut(app: &AppHandle, shortcut: &str) -> Result<(), AppError> {
+    let state = app.state::<CaptureState>();
+    let mut registered = state.shortcut.lock().unwrap();
+    let mut manager = app.global_shortcut_manager();
+    if let Some(previous) = registered.take() {
+        if let Err(e) = manager.unregister(&previous) {
+            eprintln!("Failed to unregister shortcut {}: {}", previous, e);
+        }
+    }
+    if shortcut.trim().is_empty() {
+        return Ok(());
+    }
+    let handle = app.clone();
+    manager
+        .register(shortcut, move || {
+            if let Err(e) = open_capture_window(&handle) {
+                eprintln!("{}", e);
+            }
+        })
+        .map_err(|e| AppError::WindowError(format!("Failed to register shortcut {}: {}", shortcut, e)))?;
+    *registered = Some(shortcut.to_string());
+    Ok(())
+}
+
+fn handle_request(app: &AppHandle, request: &mut tiny_http::Request, token: &str) -> Result<i64, AppError> {
+    let authorized = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Authorization") && h.value.as_str() == format!("Bearer {}", token));
+    if !authorized {
+        return Err(AppError::CredentialsError("Invalid capture token".to_string()));
+    }
+    if request.method() != &tiny_http::Method::Post || request.url() != "/capture" {
+        return Err(AppError::NotFound(format!("capture endpoint {} {}", request.method(), request.url())));
+    }
+    let is_json = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"));
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+    if is_json {
+        let capture_request: CaptureRequest = serde_json::from_str(&body)?;
+        capture(app, &capture_request.text, capture_request.title.as_deref())
+    } else {
+        capture(app, &body, None)
+    }
+}
+
+fn run_server(app: AppHandle, server: Arc<tiny_http::Server>, token: String) {
+    for mut request in server.incoming_requests() {
+        let response = match handle_request(&app, &mut request, &token) {
+            Ok(id) => tiny_http::Response::from_string(json!({ "id": id }).to_string()).with_status_code(201),
+            Err(e) => {
+                eprintln!("Capture request failed: {}", e);
+                let status = match e {
+                    AppError::CredentialsError(_) => 401,
+                    AppError::NotFound(_) => 404,
+                    AppError::VaultLocked => 423,
+                    _ => 400,
+                };
+                tiny_http::Response::from_string(json!({ "error": e.to_string() }).to_string()).with_status_code(status)
+            }
+        };
+        let response = response.with_header("Content-Type: application/json".parse::<tiny_http::Header>().unwrap());
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to answer capture request: {}", e);
+        }
+    }
+}
+
+fn restart_server(app: &AppHandle, config: &CaptureConfig) -> Result<(), AppError> {
+    let state = app.state::<CaptureState>();
+    let mut running = state.server.lock().unwrap();
+    if let Some(server) = running.take() {
+        server.unblock();
+    }
+    let token = match (&config.http_token, config.http_enabled) {
+        (Some(token), true) => token.clone(),
+        _ => return Ok(()),
+    };
+    // Localhost only; captures never come from the network
+    let server = tiny_http::Server::http(("127.0.0.1", config.http_port))
+        .map(Arc::new)
+        .map_err(|e| AppError::ServerError(format!("Failed to start capture endpoint: {}", e)))?;
+    println!("Capture endpoint listening on 127.0.0.1:{}", config.http_port);
+    *running = Some(server.clone());
+    let handle = app.clone();
+    thread::spawn(move || run_server(handle, server, token));
+    Ok(())
+}
+
+fn apply(app: &AppHandle, config: &CaptureConfig) -> Result<(), AppError> {
+    register_shortcut(app, &config.shortcut)?;
+    restart_server(app, config)
+}
+
+pub fn start(app: AppHandle) {
+    let result = Connection::open(DB_PATH)
+        .map_err(AppError::from)
+        .and_then(|conn| load_config(&conn))
+        .and_then(|config| apply(&app, &config));
+    if let Err(e) = result {
+        eprintln!("Quick capture setup failed: {}", e);
+    }
+}
+
+#[tauri::command]
+pub fn capture_command(app: AppHandle, text: String, title: Option<String>) -> Result<i64, String> {
+    println!("capture_command");
+    capture(&app, &text, title.as_deref()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn open_capture_window_command(app: AppHandle) -> Result<(), String> {
+    open_capture_window(&app).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_capture_config_command() -> Result<CaptureConfig, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    load_config(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_capture_config_command(app: AppHandle, config: CaptureConfig) -> Result<CaptureConfig, String> {
+    println!("set_capture_config_command -> shortcut: {}, http: {}", config.shortcut, config.http_enabled);
+    if config.inbox_folder.trim().is_empty() {
+        return Err("Inbox folder name must not be empty".to_string());
+    }
+    let mut config = config;
+    if config.http_enabled && config.http_token.as_deref().map_or(true, str::is_empty) {
+        config.http_token = Some(hex::encode(rand::random::<[u8; 32]>()));
+    }
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    set_json(&conn, CONFIG_KEY, &config).map_err(|e| e.to_string())?;
+    apply(&app, &config).map_err(|e| e.to_string())?;
+    Ok(config)
+}
+
+// Called by the capture window after saving or on Escape
+#[tauri::command]
+pub fn close_capture_window_command(app: AppHandle) -> Result<(), String> {
+    if let Some(window) = app.get_window(WINDOW_LABEL) {
+        window.close().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::db::EditorDocument;
+use crate::markdown::{block_to_markdown, inline_to_text};
+
+// Splits a document into the pieces the RAG backend embeds.
+//
+// A chunk is the Markdown of one header section: the headers above it, so every
+// chunk reads with its context, followed by the section's blocks. Sections longer
+// than `MAX_CHUNK_CHARS` continue in further chunks under the same headers. The
+// metadata points back at the exact blocks so search results can jump to them.
+
+const MAX_CHUNK_CHARS: usize = 2000;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ChunkMetadata {
+    pub document_id: i64,
+    pub chunk_index: usize,
+    pub block_ids: Vec<String>,
+    // Header texts from the outermost level down to the section's own header
+    pub headings: Vec<String>,
+    pub folder_path: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Chunk {
+    pub text: String,
+    pub metadata: ChunkMetadata,
+}
+
+// `#tag` words in text, lowercased. A tag starts after whitespace (or at the start)
+// and needs at least one letter, so `# Header` and `#1` are not tags.
+pub fn extract_tags(text: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut prev = ' ';
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '#' && prev.is_whitespace() {
+            let tag: String = chars[i + 1..]
+                .iter()
+                .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '/'))
+                .collect();
+            let tag = tag.trim_end_matches(['-', '/']);
+            if tag.chars().any(char::is_alphabetic) {
+                tags.push(tag.to_lowercase());
+            }
+            i += 1 + tag.chars().count();
+            prev = '#';
+            continue;
+        }
+        prev = chars[i];
+        i += 1;
+    }
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+struct Section {
+    blocks: Vec<String>,
+    block_ids: Vec<String>,
+    len: usize,
+}
+
+impl Section {
+    fn new() -> Self {
+        Section { blocks: Vec::new(), block_ids: Vec::new(), len: 0 }
+    }
+}
+
+struct Chunker<'a> {
+    document_id: i64,
+    folder_path: &'a str,
+    // (level, text) of the headers the current position is under
+    headings: Vec<(usize, String)>,
+    section: Section,
+    chunks: Vec<Chunk>,
+}
+
+impl<'a> Chunker<'a> {
+    fn flush(&mut self) {
+        let section = std::mem::replace(&mut self.section, Section::new());
+        if section.blocks.is_empty() {
+            return;
+        }
+        let mut parts: Vec<String> = self
+            .headings
+            .iter()
+            .map(|(level, text)| format!("{} {}", "#".repeat(*level), text))
+            .collect();
+        parts.extend(section.blocks);
+        let text = parts.join("\n\n");
+        self.chunks.push(Chunk {
+            metadata: ChunkMetadata {
+                document_id: self.document_id,
+                chunk_index: self.chunks.len(),
+                block_ids: section.block_ids,
+                headings: self.headings.iter().map(|(_, text)| text.clone()).collect(),
+                folder_path: self.folder_path.to_string(),
+                tags: extract_tags(&text),
+            },
+            text,
+        });
+    }
+
+    fn push_header(&mut self, level: usize, text: String, block_id: &str) {
+        self.flush();
+        while self.headings.last().map_or(false, |(l, _)| *l >= level) {
+            self.headings.pop();
+        }
+        self.headings.push((level, text));
+        // The header is rendered with the chunk; its id still belongs to the section
+        self.section.block_ids.push(block_id.to_string());
+    }
+
+    fn push_block(&mut self, markdown: String, block_id: &str) {
+        if self.section.len > 0 && self.section.len + markdown.len() > MAX_CHUNK_CHARS {
+            self.flush();
+        }
+        self.section.len += markdown.len();
+        self.section.blocks.push(markdown);
+        self.section.block_ids.push(block_id.to_string());
+    }
+}
+
+pub fn chunk_document(doc: &EditorDocument, document_id: i64, folder_path: &str) -> Vec<Chunk> {
+    let mut chunker = Chunker {
+        document_id,
+        folder_path,
+        headings: Vec::new(),
+        section: Section::new(),
+        chunks: Vec::new(),
+    };
+    for block in &doc.blocks {
+        if block.r#type == "header" {
+            let level = block.data.get("level").and_then(Value::as_u64).unwrap_or(1).clamp(1, 6) as usize;
+            let text = inline_to_text(block.data.get("text").and_then(Value::as_str).unwrap_or(""));
+            chunker.push_header(level, text.trim().to_string(), &block.id);
+            continue;
+        }
+        let markdown = block_to_markdown(block);
+        if !markdown.trim().is_empty() {
+            chunker.push_block(markdown, &block.id);
+        }
+    }
+    chunker.flush();
+    chunker.chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Block;
+    use serde_json::json;
+
+    fn header(id: &str, level: u64, text: &str) -> Block {
+        Block { id: id.to_string(), r#type: "header".to_string(), data: json!({ "text": text, "level": level }) }
+    }
+
+    fn paragraph(id: &str, text: &str) -> Block {
+        Block { id: id.to_string(), r#type: "paragraph".to_string(), data: json!({ "text": text }) }
+    }
+
+    fn chunk(blocks: Vec<Block>) -> Vec<Chunk> {
+        chunk_document(&EditorDocument { time: 1, blocks, version: "2.30.5".to_string() }, 7, "Work/Projects")
+    }
+
+    #[test]
+    fn tags_start_a_word_and_need_a_letter() {
+        assert_eq!(
+            extract_tags("#Rust and #web-dev/ but not # Header, #1, a#b or (#x); #rust again #proj/alpha"),
+            vec!["proj/alpha", "rust", "web-dev"]
+        );
+        assert!(extract_tags("## Heading\n#2024").is_empty());
+    }
+
+    #[test]
+    fn chunks_follow_header_sections() {
+        let chunks = chunk(vec![
+            paragraph("intro", "Before any header"),
+            header("a", 1, "Garden"),
+            paragraph("p1", "Beds"),
+            header("b", 2, "<b>Tomatoes</b>"),
+            paragraph("p2", "Water daily #summer"),
+            header("c", 2, "Beans"),
+            paragraph("p3", "Climbing"),
+            header("d", 1, "Kitchen"),
+            paragraph("p4", "Knives"),
+        ]);
+
+        let headings: Vec<Vec<String>> = chunks.iter().map(|c| c.metadata.headings.clone()).collect();
+        assert_eq!(
+            headings,
+            vec![vec![], vec!["Garden"], vec!["Garden", "Tomatoes"], vec!["Garden", "Beans"], vec!["Kitchen"]]
+        );
+        assert_eq!(chunks[0].text, "Before any header");
+        assert_eq!(chunks[2].text, "# Garden\n\n## Tomatoes\n\nWater daily #summer");
+        assert_eq!(chunks[2].metadata.block_ids, vec!["b", "p2"]);
+        assert_eq!(chunks[2].metadata.tags, vec!["summer"]);
+        assert!(chunks.iter().enumerate().all(|(i, c)| c.metadata.chunk_index == i));
+        assert!(chunks.iter().all(|c| c.metadata.document_id == 7 && c.metadata.folder_path == "Work/Projects"));
+    }
+
+    #[test]
+    fn empty_sections_and_blocks_make_no_chunks() {
+        let chunks = chunk(vec![
+            header("a", 1, "Empty"),
+            header("b", 1, "Full"),
+            paragraph("blank", "   "),
+            paragraph("p", "Text"),
+        ]);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].metadata.headings, vec!["Full"]);
+        assert_eq!(chunks[0].metadata.block_ids, vec!["b", "p"]);
+        assert!(chunk(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn long_sections_continue_under_the_same_headers() {
+        let long = "x".repeat(900);
+        let chunks = chunk(vec![
+            header("h", 1, "Notes"),
+            paragraph("p1", &long),
+            paragraph("p2", &long),
+            paragraph("p3", &long),
+            paragraph("huge", &"y".repeat(MAX_CHUNK_CHARS * 2)),
+        ]);
+
+        let block_ids: Vec<Vec<String>> = chunks.iter().map(|c| c.metadata.block_ids.clone()).collect();
+        assert_eq!(block_ids, vec![vec!["h", "p1", "p2"], vec!["p3"], vec!["huge"]]);
+        assert!(chunks.iter().all(|c| c.metadata.headings == vec!["Notes"] && c.text.starts_with("# Notes\n\n")));
+        // A single block is never cut
+        assert!(chunks[2].text.ends_with(&"y".repeat(MAX_CHUNK_CHARS * 2)));
+    }
+}
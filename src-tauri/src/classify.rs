@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::AppHandle;
+
+use crate::ai;
+use crate::backend;
+use crate::chunking::extract_tags;
+use crate::db::{load_document, load_documents, load_folders, move_document, update_document, Block, Document, EditorDocument};
+use crate::error::AppError;
+use crate::markdown::{document_to_markdown, inline_to_text, new_block_id};
+use crate::sync::{record_change, ChangeOp, Entity};
+use crate::{events, vault, DB_PATH};
+
+// Folder and tag suggestions for notes that were saved without a real folder.
+//
+// Every note becomes a TF-IDF vector of its words. A note's suggestions come from
+// its `NEIGHBORS` most similar filed (or tagged) notes: each neighbor votes for its
+// folder and tags with its similarity, and the confidence is the share of the vote.
+// The model is rebuilt on every call, which is cheap at the size of a notes vault
+// and means it always reflects the latest manual filing.
+
+const NEIGHBORS: usize = 10;
+const MIN_FOLDER_CONFIDENCE: f64 = 0.3;
+const MIN_TAG_CONFIDENCE: f64 = 0.3;
+const MAX_TAGS: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FolderSuggestion {
+    pub folder_id: i64,
+    pub path: String,
+    pub confidence: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TagSuggestion {
+    pub tag: String,
+    pub confidence: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Suggestion {
+    pub document_id: i64,
+    pub title: String,
+    pub folder: Option<FolderSuggestion>,
+    pub tags: Vec<TagSuggestion>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AcceptedSuggestion {
+    pub document_id: i64,
+    pub folder_id: Option<i64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AcceptReport {
+    pub moved: usize,
+    pub tagged: usize,
+}
+
+struct Note {
+    title: String,
+    folder_id: Option<i64>,
+    tags: HashSet<String>,
+    vector: HashMap<String, f64>,
+}
+
+struct Model {
+    notes: HashMap<i64, Note>,
+    folders: HashSet<i64>,
+}
+
+impl Model {
+    fn is_filed(&self, note: &Note) -> bool {
+        note.folder_id.map_or(false, |id| self.folders.contains(&id))
+    }
+}
+
+fn cosine(a: &HashMap<String, f64>, b: &HashMap<String, f64>) -> f64 {
+    let (small, large) = if a.len() < b.len() { (a, b) } else { (b, a) };
+    small.iter().filter_map(|(term, weight)| large.get(term).map(|other| weight * other)).sum()
+}
+
+fn build_model(conn: &Connection) -> Result<Model, AppError> {
+    let folders: HashSet<i64> = load_folders(conn)?.into_iter().map(|f| f.id).collect();
+    let mut counts: Vec<(Document, HashSet<String>, HashMap<String, f64>)> = Vec::new();
+    for doc in load_documents(conn)? {
+        let editor_doc: EditorDocument = match serde_json::from_str(&doc.content) {
+            Ok(editor_doc) => editor_doc,
+            Err(_) => continue,
+        };
+        let markdown = document_to_markdown(&editor_doc);
+        let mut tf: HashMap<String, f64> = HashMap::new();
+        for word in ai::words(&format!("{}\n{}", inline_to_text(&doc.title), markdown)) {
+            // Numbers and single letters say little about the topic
+            if word.chars().count() > 2 && !word.chars().all(|c| c.is_numeric()) {
+                *tf.entry(word).or_default() += 1.0;
+            }
+        }
+        counts.push((doc, extract_tags(&markdown).into_iter().collect(), tf));
+    }
+
+    let n = counts.len() as f64;
+    let mut doc_freq: HashMap<String, f64> = HashMap::new();
+    for (_, _, tf) in &counts {
+        for term in tf.keys() {
+            *doc_freq.entry(term.clone()).or_default() += 1.0;
+        }
+    }
+
+    let notes = counts
+        .into_iter()
+        .map(|(doc, tags, tf)| {
+            let mut vector: HashMap<String, f64> = tf
+                .into_iter()
+                .map(|(term, count)| {
+                    let idf = (n / doc_freq[&term]).ln() + 1.0;
+                    (term, (1.0 + count.ln()) * idf)
+                })
+                .collect();
+            let norm = vector.values().map(|w| w * w).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                vector.values_mut().for_each(|w| *w /= norm);
+            }
+            (doc.id, Note { title: inline_to_text(&doc.title), folder_id: doc.folder_id, tags, vector })
+        })
+        .collect();
+    Ok(Model { notes, folders })
+}
+
+fn suggest(conn: &Connection, model: &Model, document_id: i64) -> Result<Option<Suggestion>, AppError> {
+    let note = match model.notes.get(&document_id) {
+        Some(note) => note,
+        None => return Ok(None),
+    };
+    let mut neighbors: Vec<(&Note, f64)> = model
+        .notes
+        .iter()
+        .filter(|(id, _)| **id != document_id)
+        .map(|(_, other)| (other, cosine(&note.vector, &other.vector)))
+        .filter(|(_, similarity)| *similarity > 0.0)
+        .collect();
+    neighbors.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    let folder = if model.is_filed(note) {
+        None
+    } else {
+        let filed: Vec<&(&Note, f64)> = neighbors.iter().filter(|(n, _)| model.is_filed(n)).take(NEIGHBORS).collect();
+        let total: f64 = filed.iter().map(|(_, s)| s).sum();
+        let mut votes: HashMap<i64, f64> = HashMap::new();
+        for (neighbor, similarity) in &filed {
+            *votes.entry(neighbor.folder_id.unwrap_or_default()).or_default() += similarity;
+        }
+        match votes.into_iter().max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal)) {
+            Some((folder_id, vote)) if vote / total >= MIN_FOLDER_CONFIDENCE => Some(FolderSuggestion {
+                folder_id,
+                path: backend::folder_path(conn, Some(folder_id))?,
+                confidence: vote / total,
+            }),
+            _ => None,
+        }
+    };
+
+    let tagged: Vec<&(&Note, f64)> = neighbors.iter().filter(|(n, _)| !n.tags.is_empty()).take(NEIGHBORS).collect();
+    let total: f64 = tagged.iter().map(|(_, s)| s).sum();
+    let mut votes: HashMap<&String, f64> = HashMap::new();
+    for (neighbor, similarity) in &tagged {
+        for tag in &neighbor.tags {
+            *votes.entry(tag).or_default() += similarity;
+        }
+    }
+    let mut tags: Vec<TagSuggestion> = votes
+        .into_iter()
+        .filter(|(tag, _)| !note.tags.contains(*tag))
+        .map(|(tag, vote)| TagSuggestion { tag: tag.clone(), confidence: vote / total })
+        .filter(|t| t.confidence >= MIN_TAG_CONFIDENCE)
+        .collect();
+    tags.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal));
+    tags.truncate(MAX_TAGS);
+
+    Ok(Some(Suggestion { document_id, title: note.title.clone(), folder, tags }))
+}
+
+// Appends the tags as a paragraph of `#tag` words; tags are plain text in notes
+fn add_tags(app: &AppHandle, conn: &Connection, document_id: i64, tags: &[String]) -> Result<bool, AppError> {
+    let doc = load_document(conn, document_id)?;
+    let mut editor_doc: EditorDocument = serde_json::from_str(&doc.content)?;
+    let present: HashSet<String> = extract_tags(&document_to_markdown(&editor_doc)).into_iter().collect();
+    let new: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().trim_start_matches('#').to_lowercase())
+        .filter(|tag| !tag.is_empty() && !present.contains(tag))
+        .collect();
+    if new.is_empty() {
+        return Ok(false);
+    }
+    let text = new.iter().map(|tag| format!("#{}", tag)).collect::<Vec<_>>().join(" ");
+    editor_doc.blocks.push(Block { id: new_block_id(), r#type: "paragraph".to_string(), data: json!({ "text": text }) });
+
+    let updated = Document { content: serde_json::to_string(&editor_doc)?, ..doc };
+    update_document(conn, document_id, &updated)?;
+    record_change(conn, Entity::Document, document_id, ChangeOp::Upsert)?;
+    events::document_updated(app, events::DocumentUpdated {
+        id: document_id,
+        title: updated.title,
+        time: updated.time,
+        folder_id: updated.folder_id,
+        source_window: None,
+    });
+    Ok(true)
+}
+
+#[tauri::command]
+pub fn suggest_classification_command(id: i64) -> Result<Option<Suggestion>, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    println!("suggest_classification_command -> id: {}", id);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let model = build_model(&conn).map_err(|e| e.to_string())?;
+    suggest(&conn, &model, id).map_err(|e| e.to_string())
+}
+
+// Suggestions for every note outside a folder (including the editor's default folder 0)
+#[tauri::command]
+pub fn unfiled_suggestions_command() -> Result<Vec<Suggestion>, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    println!("unfiled_suggestions_command");
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let model = build_model(&conn).map_err(|e| e.to_string())?;
+    let mut unfiled: Vec<i64> = model.notes.iter().filter(|(_, n)| !model.is_filed(n)).map(|(id, _)| *id).collect();
+    unfiled.sort();
+    let mut suggestions = Vec::new();
+    for id in unfiled {
+        if let Some(suggestion) = suggest(&conn, &model, id).map_err(|e| e.to_string())? {
+            if suggestion.folder.is_some() || !suggestion.tags.is_empty() {
+                suggestions.push(suggestion);
+            }
+        }
+    }
+    Ok(suggestions)
+}
+
+#[tauri::command]
+pub fn accept_suggestions_command(app: AppHandle, accepted: Vec<AcceptedSuggestion>) -> Result<AcceptReport, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    println!("accept_suggestions_command -> {} documents", accepted.len());
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let mut report = AcceptReport::default();
+    for suggestion in accepted {
+        let id = suggestion.document_id;
+        if let Some(folder_id) = suggestion.folder_id {
+            let from_folder_id = move_document(&conn, id, Some(folder_id)).map_err(|e| e.to_string())?;
+            record_change(&conn, Entity::Document, id, ChangeOp::Upsert).map_err(|e| e.to_string())?;
+            events::document_moved(&app, events::DocumentMoved { id, from_folder_id, to_folder_id: Some(folder_id) });
+            report.moved += 1;
+        }
+        if add_tags(&app, &conn, id, &suggestion.tags).map_err(|e| e.to_string())? {
+            report.tagged += 1;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{insert_new_folder, save_document_in};
+
+    fn note(conn: &Connection, text: &str, folder_id: Option<i64>) -> i64 {
+        let doc = EditorDocument {
+            time: 1,
+            blocks: vec![Block { id: "p".to_string(), r#type: "paragraph".to_string(), data: json!({ "text": text }) }],
+            version: "2.30.5".to_string(),
+        };
+        save_document_in(conn, &doc, folder_id).unwrap()
+    }
+
+    fn vector(terms: &[(&str, f64)]) -> HashMap<String, f64> {
+        terms.iter().map(|(term, weight)| (term.to_string(), *weight)).collect()
+    }
+
+    #[test]
+    fn cosine_sums_shared_terms() {
+        let a = vector(&[("garden", 0.6), ("compost", 0.8)]);
+        assert!((cosine(&a, &a) - 1.0).abs() < 1e-9);
+        assert!((cosine(&a, &vector(&[("garden", 1.0)])) - 0.6).abs() < 1e-9);
+        assert_eq!(cosine(&a, &vector(&[("budget", 1.0)])), 0.0);
+        assert_eq!(cosine(&a, &HashMap::new()), 0.0);
+    }
+
+    #[test]
+    fn unfiled_notes_get_the_folder_and_tags_of_similar_notes() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn).unwrap();
+        let garden = insert_new_folder(&conn, "Garden", None).unwrap();
+        let work = insert_new_folder(&conn, "Work", None).unwrap();
+        let projects = insert_new_folder(&conn, "Projects", Some(work)).unwrap();
+
+        let filed = note(&conn, "Tomatoes need watering and compost in the garden #plants", Some(garden));
+        note(&conn, "Compost the garden beds before planting tomatoes #plants #outdoor", Some(garden));
+        note(&conn, "Quarterly report deadline for the project budget #work", Some(projects));
+        let tomatoes = note(&conn, "Watering tomatoes and compost for the garden beds #plants", None);
+        let budget = note(&conn, "Budget numbers for the quarterly project report", None);
+        let model = build_model(&conn).unwrap();
+
+        let suggestion = suggest(&conn, &model, tomatoes).unwrap().unwrap();
+        let folder = suggestion.folder.unwrap();
+        assert_eq!((folder.folder_id, folder.path.as_str()), (garden, "Garden"));
+        assert!(folder.confidence >= MIN_FOLDER_CONFIDENCE && folder.confidence <= 1.0);
+        // Tags the note already has are not suggested again
+        let tags: Vec<&str> = suggestion.tags.iter().map(|t| t.tag.as_str()).collect();
+        assert_eq!(tags, vec!["outdoor"]);
+
+        let suggestion = suggest(&conn, &model, budget).unwrap().unwrap();
+        assert_eq!(suggestion.folder.unwrap().path, "Work/Projects");
+        assert_eq!(suggestion.tags.iter().map(|t| t.tag.as_str()).collect::<Vec<_>>(), vec!["work"]);
+
+        // Filed notes keep their folder but still get tags
+        assert!(suggest(&conn, &model, filed).unwrap().unwrap().folder.is_none());
+        assert!(suggest(&conn, &model, 999).unwrap().is_none());
+    }
+}
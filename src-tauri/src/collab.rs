@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use futures_util::{Sink, SinkExt, StreamExt};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::crdt::{CrdtDocument, Op};
+use crate::db::{extract_title, load_document, save_document, update_document, Document, EditorDocument};
+use crate::error::AppError;
+use crate::{collab_relay, events, sync, vault, DB_PATH};
+
+// Wire protocol spoken with other j_desktop instances or the relay, one JSON text
+// frame per message. A `hello` carries the sender's version vector; the receiver
+// answers with the operations the sender is missing and, unless the hello was
+// itself a reply, with its own hello.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyncMessage {
+    Hello { room: String, site: u64, vector: HashMap<u64, u64>, reply: bool },
+    Ops { room: String, site: u64, ops: Vec<Op> },
+}
+
+// Managed Tauri state: open sessions by document id. `lock` serializes every
+// read-modify-write of a stored CRDT.
+#[derive(Default)]
+pub struct CollabState {
+    sessions: Mutex<HashMap<i64, UnboundedSender<Vec<Op>>>>,
+    lock: Mutex<()>,
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS document_crdt (
+            document_id INTEGER PRIMARY KEY,
+            room TEXT NOT NULL UNIQUE,
+            state TEXT NOT NULL,
+            FOREIGN KEY(document_id) REFERENCES documents(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn load_crdt(conn: &Connection, document_id: i64) -> Result<Option<(String, CrdtDocument)>, AppError> {
+    let row = conn.query_row(
+        "SELECT room, state FROM document_crdt WHERE document_id = ?",
+        [document_id],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+    );
+    match row {
+        Ok((room, state)) => Ok(Some((room, CrdtDocument::from_json(&vault::open_text(&state)?)?))),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn store_crdt(conn: &Connection, document_id: i64, room: &str, doc: &CrdtDocument) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO document_crdt (document_id, room, state) VALUES (?1, ?2, ?3)
+         ON CONFLICT(document_id) DO UPDATE SET state = excluded.state",
+        params![document_id, room, vault::seal_text(&doc.to_json()?)?],
+    )?;
+    Ok(())
+}
+
+fn new_room() -> String {
+    format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>())
+}
+
+// Records a save from the editor as CRDT operations and forwards them to the open
+// session, if any. Documents that were never shared are left alone.
+pub fn publish_local_update(app: &AppHandle, document_id: i64, doc: &EditorDocument) -> Result<(), AppError> {
+    let state = app.state::<CollabState>();
+    let _guard = state.lock.lock().unwrap();
+
+    let conn = Connection::open(DB_PATH)?;
+    let (room, mut crdt) = match load_crdt(&conn, document_id)? {
+        Some(found) => found,
+        None => return Ok(()),
+    };
+    let ops = crdt.apply_editor_document(doc);
+    if ops.is_empty() {
+        return Ok(());
+    }
+    store_crdt(&conn, document_id, &room, &crdt)?;
+
+    if let Some(session) = state.sessions.lock().unwrap().get(&document_id) {
+        // A closed channel means the session just ended; the ops go out on reconnect
+        let _ = session.send(ops);
+    }
+    Ok(())
+}
+
+fn apply_remote_ops(app: &AppHandle, document_id: i64, ops: Vec<Op>) -> Result<(), AppError> {
+    let state = app.state::<CollabState>();
+    let _guard = state.lock.lock().unwrap();
+
+    let conn = Connection::open(DB_PATH)?;
+    let (room, mut crdt) = match load_crdt(&conn, document_id)? {
+        Some(found) => found,
+        None => return Ok(()),
+    };
+    if !crdt.apply_remote(ops) {
+        return Ok(());
+    }
+    store_crdt(&conn, document_id, &room, &crdt)?;
+
+    // Materialize back into `documents.content` so the editor and every other
+    // command keep working on plain block JSON
+    let doc = crdt.to_editor_document();
+    let content = serde_json::to_string(&doc)?;
+    let existing = load_document(&conn, document_id)?;
+    let db_doc = Document {
+        id: document_id,
+        title: extract_title(&content).unwrap_or_else(|| "Untitled".to_string()),
+        time: doc.time.to_string(),
+        content,
+        folder_id: existing.folder_id,
+    };
+    update_document(&conn, document_id, &db_doc)?;
+    sync::record_change(&conn, sync::Entity::Document, document_id, sync::ChangeOp::Upsert)?;
+
+    events::document_updated(app, events::DocumentUpdated {
+        id: document_id,
+        title: db_doc.title,
+        time: db_doc.time,
+        folder_id: db_doc.folder_id,
+        source_window: None,
+    });
+    Ok(())
+}
+
+fn handle_message(app: &AppHandle, document_id: i64, room: &str, site: u64, msg: SyncMessage) -> Result<Vec<SyncMessage>, AppError> {
+    let mut replies = Vec::new();
+    match msg {
+        SyncMessage::Hello { room: their_room, site: their_site, vector, reply } => {
+            if their_room != room || their_site == site {
+                return Ok(replies);
+            }
+            let conn = Connection::open(DB_PATH)?;
+            if let Some((_, crdt)) = load_crdt(&conn, document_id)? {
+                let missing = crdt.ops_since(&vector);
+                if !missing.is_empty() {
+                    replies.push(SyncMessage::Ops { room: room.to_string(), site, ops: missing });
+                }
+                if !reply {
+                    replies.push(SyncMessage::Hello { room: room.to_string(), site, vector: crdt.vector(), reply: true });
+                }
+            }
+        }
+        SyncMessage::Ops { room: their_room, site: their_site, ops } => {
+            if their_room == room && their_site != site {
+                apply_remote_ops(app, document_id, ops)?;
+            }
+        }
+    }
+    Ok(replies)
+}
+
+async fn send<S>(write: &mut S, msg: &SyncMessage) -> Result<(), String>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: Display,
+{
+    let text = serde_json::to_string(msg).map_err(|e| e.to_string())?;
+    write.send(Message::Text(text)).await.map_err(|e| e.to_string())
+}
+
+async fn run_session(app: &AppHandle, document_id: i64, url: &str, mut outgoing: UnboundedReceiver<Vec<Op>>) -> Result<(), String> {
+    let (room, site, vector) = {
+        let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+        let (room, crdt) = load_crdt(&conn, document_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Document {} is not shared", document_id))?;
+        (room, crdt.site, crdt.vector())
+    };
+
+    let (ws, _) = tokio_tungstenite::connect_async(url).await.map_err(|e| e.to_string())?;
+    println!("Collab session for document {} connected to {}", document_id, url);
+    let (mut write, mut read) = ws.split();
+    send(&mut write, &SyncMessage::Hello { room: room.clone(), site, vector, reply: false }).await?;
+
+    loop {
+        tokio::select! {
+            ops = outgoing.recv() => match ops {
+                Some(ops) => send(&mut write, &SyncMessage::Ops { room: room.clone(), site, ops }).await?,
+                // Sender dropped by `disconnect_collab_command`
+                None => break,
+            },
+            incoming = read.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let msg: SyncMessage = match serde_json::from_str(&text) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            eprintln!("Ignoring malformed collab message: {}", e);
+                            continue;
+                        }
+                    };
+                    for reply in handle_message(app, document_id, &room, site, msg).map_err(|e| e.to_string())? {
+                        send(&mut write, &reply).await?;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.to_string()),
+            },
+        }
+    }
+
+    println!("Collab session for document {} closed", document_id);
+    Ok(())
+}
+
+fn spawn_session(app: &AppHandle, document_id: i64, url: String) {
+    let (tx, rx) = unbounded_channel();
+    app.state::<CollabState>().sessions.lock().unwrap().insert(document_id, tx);
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = run_session(&app, document_id, &url, rx).await {
+            eprintln!("Collab session for document {} failed: {}", document_id, e);
+        }
+        // Only drop our own sender, a reconnect may already have replaced it
+        app.state::<CollabState>()
+            .sessions
+            .lock()
+            .unwrap()
+            .retain(|id, tx| *id != document_id || !tx.is_closed());
+    });
+}
+
+// Starts tracking a document as a CRDT and returns the room id peers join with
+#[tauri::command]
+pub fn enable_collab_command(state: State<CollabState>, id: i64) -> Result<String, String> {
+    println!("enable_collab_command -> id: {}", id);
+    vault::guard().map_err(|e| e.to_string())?;
+    let _guard = state.lock.lock().unwrap();
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+
+    if let Some((room, _)) = load_crdt(&conn, id).map_err(|e| e.to_string())? {
+        return Ok(room);
+    }
+
+    let doc = load_document(&conn, id).map_err(|e| e.to_string())?;
+    let editor_doc: EditorDocument = serde_json::from_str(&doc.content).map_err(|e| e.to_string())?;
+    let mut crdt = CrdtDocument::new(rand::random());
+    crdt.apply_editor_document(&editor_doc);
+
+    let room = new_room();
+    store_crdt(&conn, id, &room, &crdt).map_err(|e| e.to_string())?;
+    Ok(room)
+}
+
+// Creates a local copy of a document shared by a peer and starts syncing it
+#[tauri::command]
+pub fn join_collab_command(app: AppHandle, url: String, room: String, folder_id: i64) -> Result<i64, String> {
+    // The room id is the secret that grants access to the document, keep it out of logs
+    println!("join_collab_command -> url: {}", url);
+    vault::guard().map_err(|e| e.to_string())?;
+    if !collab_relay::is_room_secret(&room) {
+        return Err("Not a room id shared by j_desktop".to_string());
+    }
+    let id = {
+        let state = app.state::<CollabState>();
+        let _guard = state.lock.lock().unwrap();
+        let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+
+        let empty = EditorDocument { time: 0, blocks: Vec::new(), version: String::new() };
+        let id = save_document(&conn, &empty, &folder_id).map_err(|e| e.to_string())?;
+        sync::record_change(&conn, sync::Entity::Document, id, sync::ChangeOp::Upsert).map_err(|e| e.to_string())?;
+        store_crdt(&conn, id, &room, &CrdtDocument::new(rand::random())).map_err(|e| e.to_string())?;
+        id
+    };
+
+    events::document_created(&app, events::DocumentCreated {
+        id,
+        title: "Untitled".to_string(),
+        folder_id: Some(folder_id),
+    });
+    spawn_session(&app, id, url);
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn connect_collab_command(app: AppHandle, id: i64, url: String) -> Result<(), String> {
+    println!("connect_collab_command -> id: {}, url: {}", id, url);
+    vault::guard().map_err(|e| e.to_string())?;
+    spawn_session(&app, id, url);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn disconnect_collab_command(state: State<CollabState>, id: i64) -> Result<(), String> {
+    println!("disconnect_collab_command -> id: {}", id);
+    state.sessions.lock().unwrap().remove(&id);
+    Ok(())
+}
+
+// Ids of documents with an open collab session
+#[tauri::command]
+pub fn collab_status_command(state: State<CollabState>) -> Result<Vec<i64>, String> {
+    Ok(state.sessions.lock().unwrap().keys().copied().collect())
+}
+
+// Runs the relay inside the app. It only listens on loopback unless `lan` is set, so
+// another instance on the LAN can connect directly; the room id is then the only
+// thing keeping other hosts out of a document.
+#[tauri::command]
+pub fn start_collab_relay_command(port: u16, lan: Option<bool>) -> Result<(), String> {
+    println!("start_collab_relay_command -> port: {}, lan: {:?}", port, lan);
+    let host = if lan.unwrap_or(false) { "0.0.0.0" } else { "127.0.0.1" };
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = collab_relay::run(&format!("{}:{}", host, port)).await {
+            eprintln!("Collab relay stopped: {}", e);
+        }
+    });
+    Ok(())
+}
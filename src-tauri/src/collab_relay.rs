@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+// Relay for collaborative editing. Every text frame is forwarded to the other
+// connections that joined the same room; the relay never looks at the operations
+// themselves, peers answer each other's `hello` with whatever the other side is missing.
+//
+// There are no accounts: a room id is 128 random bits and works as the shared secret.
+// Frames for anything shorter are dropped so a guessable room can't be joined.
+
+const ROOM_SECRET_LEN: usize = 32;
+
+#[derive(Clone)]
+struct Frame {
+    from: u64,
+    room: String,
+    text: String,
+}
+
+pub fn is_room_secret(room: &str) -> bool {
+    room.len() >= ROOM_SECRET_LEN && room.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub async fn run(addr: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Collab relay listening on {}", addr);
+
+    let (tx, _) = broadcast::channel::<Frame>(1024);
+    let mut next_id: u64 = 0;
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        next_id += 1;
+        let id = next_id;
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, id, tx).await {
+                eprintln!("Relay connection {} closed with error: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, id: u64, tx: broadcast::Sender<Frame>) -> Result<(), WsError> {
+    let ws = tokio_tungstenite::accept_async(stream).await?;
+    let (mut write, mut read) = ws.split();
+    let mut rx = tx.subscribe();
+    let mut rooms: HashSet<String> = HashSet::new();
+
+    loop {
+        tokio::select! {
+            incoming = read.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let room = serde_json::from_str::<Value>(&text)
+                        .ok()
+                        .and_then(|v| v.get("room").and_then(Value::as_str).map(str::to_string))
+                        .filter(|room| is_room_secret(room));
+                    if let Some(room) = room {
+                        rooms.insert(room.clone());
+                        // Sending only fails when nobody else is connected
+                        let _ = tx.send(Frame { from: id, room, text });
+                    }
+                }
+                Some(Ok(Message::Ping(payload))) => write.send(Message::Pong(payload)).await?,
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+            },
+            frame = rx.recv() => match frame {
+                Ok(frame) => {
+                    if frame.from != id && rooms.contains(&frame.room) {
+                        write.send(Message::Text(frame.text)).await?;
+                    }
+                }
+                // Peers catch up on the next handshake, so dropped frames are only logged
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    eprintln!("Relay connection {} dropped {} frames", id, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
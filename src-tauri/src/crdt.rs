@@ -0,0 +1,627 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::db::{Block, EditorDocument};
+
+// CRDT representation of an `EditorDocument`.
+//
+// The block list and the `text` field of every block are RGA sequences, every other
+// field in a block's `data` is a last-writer-wins register. Operations are identified
+// by a Lamport counter plus the site that created them, which also gives the total
+// order used to break ties between concurrent edits. Each site also numbers its own
+// operations without gaps; the version vector counts those, since a site's Lamport
+// counter jumps whenever it sees a remote edit.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct OpId {
+    pub counter: u64,
+    pub site: u64,
+    pub seq: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    InsertBlock { id: OpId, after: Option<OpId>, block_id: String, block_type: String, has_text: bool },
+    DeleteBlock { id: OpId, target: OpId },
+    SetField { id: OpId, block: OpId, key: String, value: Value },
+    InsertText { id: OpId, block: OpId, after: Option<OpId>, token: String },
+    DeleteText { id: OpId, block: OpId, target: OpId },
+    SetMeta { id: OpId, time: i64, version: String },
+}
+
+impl Op {
+    pub fn id(&self) -> OpId {
+        match self {
+            Op::InsertBlock { id, .. }
+            | Op::DeleteBlock { id, .. }
+            | Op::SetField { id, .. }
+            | Op::InsertText { id, .. }
+            | Op::DeleteText { id, .. }
+            | Op::SetMeta { id, .. } => *id,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Elem<T> {
+    id: OpId,
+    value: T,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Sequence<T> {
+    elems: Vec<Elem<T>>,
+}
+
+impl<T> Sequence<T> {
+    fn new() -> Self {
+        Sequence { elems: Vec::new() }
+    }
+
+    fn position(&self, id: OpId) -> Option<usize> {
+        self.elems.iter().position(|e| e.id == id)
+    }
+
+    // RGA integration: place the element right after its origin, skipping over any
+    // concurrent inserts with a greater id. Returns false if the origin is unknown yet.
+    fn insert(&mut self, id: OpId, after: Option<OpId>, value: T) -> bool {
+        if self.position(id).is_some() {
+            return true;
+        }
+        let mut idx = match after {
+            None => 0,
+            Some(origin) => match self.position(origin) {
+                Some(pos) => pos + 1,
+                None => return false,
+            },
+        };
+        while idx < self.elems.len() && self.elems[idx].id > id {
+            idx += 1;
+        }
+        self.elems.insert(idx, Elem { id, value, deleted: false });
+        true
+    }
+
+    fn delete(&mut self, target: OpId) -> bool {
+        match self.position(target) {
+            Some(pos) => {
+                self.elems[pos].deleted = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn get_mut(&mut self, id: OpId) -> Option<&mut T> {
+        self.elems.iter_mut().find(|e| e.id == id).map(|e| &mut e.value)
+    }
+
+    fn visible(&self) -> impl Iterator<Item = &Elem<T>> {
+        self.elems.iter().filter(|e| !e.deleted)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct BlockState {
+    block_id: String,
+    block_type: String,
+    fields: BTreeMap<String, (OpId, Value)>,
+    text: Option<Sequence<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredCrdt {
+    site: u64,
+    clock: u64,
+    ops: Vec<Op>,
+}
+
+pub struct CrdtDocument {
+    pub site: u64,
+    clock: u64,
+    meta: Option<(OpId, i64, String)>,
+    blocks: Sequence<BlockState>,
+    log: Vec<Op>,
+    pending: Vec<Op>,
+    seen: HashSet<OpId>,
+    // Applied operations past a gap in their site's sequence, by (site, seq)
+    ahead: BTreeSet<(u64, u64)>,
+    vector: HashMap<u64, u64>,
+}
+
+// Splits Editor.js inline HTML into tokens so tags and entities are never split by a
+// concurrent edit: `<b>`, `&nbsp;` and single characters each become one element.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let end = match chars[i] {
+            '<' => chars[i..].iter().position(|&c| c == '>'),
+            '&' => chars[i + 1..]
+                .iter()
+                .take(10)
+                .position(|&c| c == ';' || !(c.is_ascii_alphanumeric() || c == '#'))
+                .filter(|&p| chars[i + 1 + p] == ';')
+                .map(|p| p + 1),
+            _ => None,
+        };
+        let len = end.map_or(1, |end| end + 1);
+        tokens.push(chars[i..i + len].iter().collect());
+        i += len;
+    }
+    tokens
+}
+
+impl CrdtDocument {
+    pub fn new(site: u64) -> Self {
+        CrdtDocument {
+            site,
+            clock: 0,
+            meta: None,
+            blocks: Sequence::new(),
+            log: Vec::new(),
+            pending: Vec::new(),
+            seen: HashSet::new(),
+            ahead: BTreeSet::new(),
+            vector: HashMap::new(),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let stored: StoredCrdt = serde_json::from_str(json)?;
+        let mut doc = CrdtDocument::new(stored.site);
+        doc.apply_remote(stored.ops);
+        doc.clock = doc.clock.max(stored.clock);
+        Ok(doc)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&StoredCrdt {
+            site: self.site,
+            clock: self.clock,
+            ops: self.log.clone(),
+        })
+    }
+
+    // Per site, the sequence number up to which every operation has been applied,
+    // exchanged in the sync handshake. An operation applied past a gap (its
+    // predecessor is parked or never arrived) doesn't advance it, so the peer keeps
+    // sending the missing ones.
+    pub fn vector(&self) -> HashMap<u64, u64> {
+        self.vector.clone()
+    }
+
+    // Every applied operation the peer with the given vector may not have seen yet
+    pub fn ops_since(&self, vector: &HashMap<u64, u64>) -> Vec<Op> {
+        self.log
+            .iter()
+            .filter(|op| {
+                let id = op.id();
+                id.seq > vector.get(&id.site).copied().unwrap_or(0)
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Local operations are applied as soon as they are created, so the site's own
+    // sequence has no gaps and the next number follows the vector
+    fn next_id(&mut self) -> OpId {
+        self.clock += 1;
+        let seq = self.vector.get(&self.site).copied().unwrap_or(0) + 1;
+        OpId { counter: self.clock, site: self.site, seq }
+    }
+
+    fn mark_applied(&mut self, id: OpId) {
+        self.seen.insert(id);
+        self.ahead.insert((id.site, id.seq));
+        let contiguous = self.vector.entry(id.site).or_insert(0);
+        while self.ahead.remove(&(id.site, *contiguous + 1)) {
+            *contiguous += 1;
+        }
+    }
+
+    fn apply(&mut self, op: &Op) -> bool {
+        let applied = match op {
+            Op::InsertBlock { id, after, block_id, block_type, has_text } => self.blocks.insert(
+                *id,
+                *after,
+                BlockState {
+                    block_id: block_id.clone(),
+                    block_type: block_type.clone(),
+                    fields: BTreeMap::new(),
+                    text: if *has_text { Some(Sequence::new()) } else { None },
+                },
+            ),
+            Op::DeleteBlock { target, .. } => self.blocks.delete(*target),
+            Op::SetField { id, block, key, value } => match self.blocks.get_mut(*block) {
+                Some(state) => {
+                    let newer = state.fields.get(key).map_or(true, |(current, _)| current < id);
+                    if newer {
+                        state.fields.insert(key.clone(), (*id, value.clone()));
+                    }
+                    true
+                }
+                None => false,
+            },
+            Op::InsertText { id, block, after, token } => match self.blocks.get_mut(*block) {
+                Some(state) => state
+                    .text
+                    .get_or_insert_with(Sequence::new)
+                    .insert(*id, *after, token.clone()),
+                None => false,
+            },
+            Op::DeleteText { block, target, .. } => match self.blocks.get_mut(*block) {
+                Some(state) => state.text.as_mut().map_or(false, |text| text.delete(*target)),
+                None => false,
+            },
+            Op::SetMeta { id, time, version } => {
+                if self.meta.as_ref().map_or(true, |(current, _, _)| current < id) {
+                    self.meta = Some((*id, *time, version.clone()));
+                }
+                true
+            }
+        };
+
+        if applied {
+            let id = op.id();
+            self.clock = self.clock.max(id.counter);
+            self.mark_applied(id);
+            self.log.push(op.clone());
+        }
+        applied
+    }
+
+    fn is_known(&self, op: &Op) -> bool {
+        self.seen.contains(&op.id())
+    }
+
+    // Applies operations from a peer. Operations whose dependencies have not arrived
+    // yet are parked and retried whenever something else is applied.
+    pub fn apply_remote(&mut self, ops: Vec<Op>) -> bool {
+        let mut changed = false;
+        for op in ops {
+            let parked = self.pending.iter().any(|p| p.id() == op.id());
+            if !self.is_known(&op) && !parked {
+                self.pending.push(op);
+            }
+        }
+        loop {
+            let pending = std::mem::take(&mut self.pending);
+            let before = pending.len();
+            for op in pending {
+                if self.is_known(&op) {
+                    continue;
+                }
+                if self.apply(&op) {
+                    changed = true;
+                } else {
+                    self.pending.push(op);
+                }
+            }
+            if self.pending.is_empty() || self.pending.len() == before {
+                break;
+            }
+        }
+        changed
+    }
+
+    fn push_local(&mut self, op: Op, ops: &mut Vec<Op>) {
+        if self.apply(&op) {
+            ops.push(op);
+        }
+    }
+
+    fn insert_text(&mut self, block: OpId, mut after: Option<OpId>, tokens: &[String], ops: &mut Vec<Op>) {
+        for token in tokens {
+            let id = self.next_id();
+            self.push_local(Op::InsertText { id, block, after, token: token.clone() }, ops);
+            after = Some(id);
+        }
+    }
+
+    fn insert_block(&mut self, after: Option<OpId>, block: &Block, ops: &mut Vec<Op>) -> OpId {
+        let id = self.next_id();
+        let text = block.data.get("text").and_then(Value::as_str).map(tokenize);
+        self.push_local(
+            Op::InsertBlock {
+                id,
+                after,
+                block_id: block.id.clone(),
+                block_type: block.r#type.clone(),
+                has_text: text.is_some(),
+            },
+            ops,
+        );
+        if let Some(data) = block.data.as_object() {
+            for (key, value) in data {
+                if key == "text" && value.is_string() {
+                    continue;
+                }
+                let field_id = self.next_id();
+                self.push_local(Op::SetField { id: field_id, block: id, key: key.clone(), value: value.clone() }, ops);
+            }
+        }
+        if let Some(tokens) = text {
+            self.insert_text(id, None, &tokens, ops);
+        }
+        id
+    }
+
+    fn diff_block(&mut self, block_op: OpId, block: &Block, ops: &mut Vec<Op>) {
+        let (fields, current_text) = match self.blocks.elems.iter().find(|e| e.id == block_op) {
+            Some(elem) => (
+                elem.value.fields.clone(),
+                elem.value
+                    .text
+                    .as_ref()
+                    .map(|t| t.visible().map(|e| (e.id, e.value.clone())).collect::<Vec<_>>()),
+            ),
+            None => return,
+        };
+        let empty = Map::new();
+        let data = block.data.as_object().unwrap_or(&empty);
+
+        for (key, value) in data {
+            if key == "text" && value.is_string() && current_text.is_some() {
+                continue;
+            }
+            if fields.get(key).map(|(_, v)| v) != Some(value) {
+                let id = self.next_id();
+                self.push_local(Op::SetField { id, block: block_op, key: key.clone(), value: value.clone() }, ops);
+            }
+        }
+        for (key, (_, value)) in &fields {
+            if !data.contains_key(key) && !value.is_null() {
+                let id = self.next_id();
+                self.push_local(Op::SetField { id, block: block_op, key: key.clone(), value: Value::Null }, ops);
+            }
+        }
+
+        let current = match current_text {
+            Some(current) => current,
+            None => return,
+        };
+        let wanted = tokenize(data.get("text").and_then(Value::as_str).unwrap_or(""));
+        let prefix = current.iter().zip(&wanted).take_while(|(a, b)| &a.1 == *b).count();
+        let suffix = current[prefix..]
+            .iter()
+            .rev()
+            .zip(wanted[prefix..].iter().rev())
+            .take_while(|(a, b)| &a.1 == *b)
+            .count();
+
+        for (target, _) in &current[prefix..current.len() - suffix] {
+            let id = self.next_id();
+            self.push_local(Op::DeleteText { id, block: block_op, target: *target }, ops);
+        }
+        let after = if prefix == 0 { None } else { Some(current[prefix - 1].0) };
+        self.insert_text(block_op, after, &wanted[prefix..wanted.len() - suffix], ops);
+    }
+
+    // Turns a document saved by the editor into operations against the current state.
+    // Blocks are matched by their Editor.js id; a block that changed type or moved
+    // relative to its neighbours is deleted and re-inserted.
+    pub fn apply_editor_document(&mut self, doc: &EditorDocument) -> Vec<Op> {
+        let mut ops = Vec::new();
+        let meta_changed = self
+            .meta
+            .as_ref()
+            .map_or(true, |(_, time, version)| *time != doc.time || version != &doc.version);
+        if meta_changed {
+            let id = self.next_id();
+            self.push_local(Op::SetMeta { id, time: doc.time, version: doc.version.clone() }, &mut ops);
+        }
+
+        let current: Vec<(OpId, String, String)> = self
+            .blocks
+            .visible()
+            .map(|e| (e.id, e.value.block_id.clone(), e.value.block_type.clone()))
+            .collect();
+
+        let mut last_kept: Option<usize> = None;
+        let mut keep: HashMap<String, OpId> = HashMap::new();
+        for block in &doc.blocks {
+            if let Some(idx) = current.iter().position(|(_, id, ty)| id == &block.id && ty == &block.r#type) {
+                if last_kept.map_or(true, |last| idx > last) {
+                    last_kept = Some(idx);
+                    keep.insert(block.id.clone(), current[idx].0);
+                }
+            }
+        }
+
+        for (op_id, block_id, _) in &current {
+            if keep.get(block_id) != Some(op_id) {
+                let id = self.next_id();
+                self.push_local(Op::DeleteBlock { id, target: *op_id }, &mut ops);
+            }
+        }
+
+        let mut prev: Option<OpId> = None;
+        for block in &doc.blocks {
+            let block_op = match keep.get(&block.id) {
+                Some(&existing) => {
+                    self.diff_block(existing, block, &mut ops);
+                    existing
+                }
+                None => self.insert_block(prev, block, &mut ops),
+            };
+            prev = Some(block_op);
+        }
+
+        ops
+    }
+
+    // Materializes the CRDT back into the block JSON the editor loads
+    pub fn to_editor_document(&self) -> EditorDocument {
+        let blocks = self
+            .blocks
+            .visible()
+            .map(|elem| {
+                let state = &elem.value;
+                let mut data = Map::new();
+                for (key, (_, value)) in &state.fields {
+                    if !value.is_null() {
+                        data.insert(key.clone(), value.clone());
+                    }
+                }
+                if let Some(text) = &state.text {
+                    let text: String = text.visible().map(|e| e.value.as_str()).collect();
+                    data.insert("text".to_string(), Value::String(text));
+                }
+                Block {
+                    id: state.block_id.clone(),
+                    r#type: state.block_type.clone(),
+                    data: Value::Object(data),
+                }
+            })
+            .collect();
+
+        let (time, version) = match &self.meta {
+            Some((_, time, version)) => (*time, version.clone()),
+            None => (0, String::new()),
+        };
+        EditorDocument { time, blocks, version }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn paragraph(id: &str, text: &str) -> Block {
+        Block { id: id.to_string(), r#type: "paragraph".to_string(), data: json!({ "text": text }) }
+    }
+
+    fn document(time: i64, blocks: Vec<Block>) -> EditorDocument {
+        EditorDocument { time, blocks, version: "2.28.0".to_string() }
+    }
+
+    fn texts(doc: &CrdtDocument) -> Vec<String> {
+        doc.to_editor_document()
+            .blocks
+            .iter()
+            .map(|block| format!("{}:{}", block.id, block.data["text"].as_str().unwrap_or("")))
+            .collect()
+    }
+
+    // One handshake in both directions, as a collab session does on connect
+    fn sync(a: &mut CrdtDocument, b: &mut CrdtDocument) {
+        b.apply_remote(a.ops_since(&b.vector()));
+        a.apply_remote(b.ops_since(&a.vector()));
+    }
+
+    fn shared(text: &str) -> (CrdtDocument, CrdtDocument) {
+        let mut a = CrdtDocument::new(1);
+        let mut b = CrdtDocument::new(2);
+        b.apply_remote(a.apply_editor_document(&document(1, vec![paragraph("p1", text)])));
+        (a, b)
+    }
+
+    #[test]
+    fn tokenize_keeps_tags_and_entities_whole() {
+        assert_eq!(tokenize("a<b>c</b>&nbsp;&x"), vec!["a", "<b>", "c", "</b>", "&nbsp;", "&", "x"]);
+    }
+
+    #[test]
+    fn reversed_delivery_converges() {
+        let mut a = CrdtDocument::new(1);
+        let mut ops = a.apply_editor_document(&document(1, vec![paragraph("p1", "hello"), paragraph("p2", "world")]));
+        ops.reverse();
+        let mut b = CrdtDocument::new(2);
+        b.apply_remote(ops);
+        assert_eq!(texts(&b), vec!["p1:hello", "p2:world"]);
+        assert_eq!(a.vector(), b.vector());
+    }
+
+    #[test]
+    fn concurrent_text_edits_converge() {
+        let (mut a, mut b) = shared("cat");
+        a.apply_editor_document(&document(2, vec![paragraph("p1", "cart")]));
+        b.apply_editor_document(&document(3, vec![paragraph("p1", "coat")]));
+        sync(&mut a, &mut b);
+        assert_eq!(texts(&a), texts(&b));
+        assert_eq!(texts(&a), vec!["p1:coart"]);
+    }
+
+    #[test]
+    fn concurrent_inserts_at_same_position_converge() {
+        let (mut a, mut b) = shared("");
+        let from_a = a.apply_editor_document(&document(2, vec![paragraph("p1", ""), paragraph("a", "from a")]));
+        let from_b = b.apply_editor_document(&document(2, vec![paragraph("p1", ""), paragraph("b", "from b")]));
+        a.apply_remote(from_b);
+        b.apply_remote(from_a);
+        assert_eq!(texts(&a), texts(&b));
+        assert_eq!(texts(&a).len(), 3);
+    }
+
+    #[test]
+    fn delete_concurrent_with_edit_converges() {
+        let (mut a, mut b) = shared("draft");
+        a.apply_editor_document(&document(2, vec![]));
+        b.apply_editor_document(&document(2, vec![paragraph("p1", "draft two")]));
+        sync(&mut a, &mut b);
+        assert_eq!(texts(&a), texts(&b));
+        assert!(texts(&a).is_empty());
+    }
+
+    #[test]
+    fn concurrent_text_deletes_converge() {
+        let (mut a, mut b) = shared("abcdef");
+        a.apply_editor_document(&document(2, vec![paragraph("p1", "aef")]));
+        b.apply_editor_document(&document(2, vec![paragraph("p1", "abcXf")]));
+        sync(&mut a, &mut b);
+        assert_eq!(texts(&a), texts(&b));
+        assert_eq!(texts(&a), vec!["p1:aXf"]);
+    }
+
+    // An operation parked for a missing dependency must not count as seen when a
+    // later, independent operation from the same site is applied
+    #[test]
+    fn parked_operation_is_not_skipped_by_later_ones() {
+        let (mut a, mut b) = shared("one");
+        let block_ops = a.ops_since(&HashMap::new());
+        let mut edits = b.apply_editor_document(&document(2, vec![paragraph("p1", "one two")]));
+        edits.extend(b.apply_editor_document(&document(3, vec![paragraph("p1", "one two")])));
+
+        let mut c = CrdtDocument::new(3);
+        c.apply_remote(edits);
+        assert!(b.ops_since(&c.vector()).iter().any(|op| matches!(op, Op::InsertText { .. })));
+
+        c.apply_remote(block_ops);
+        sync(&mut b, &mut c);
+        assert_eq!(texts(&c), vec!["p1:one two"]);
+        sync(&mut a, &mut c);
+        assert_eq!(texts(&a), texts(&c));
+    }
+
+    #[test]
+    fn missing_operation_is_sent_again() {
+        let (mut a, mut b) = shared("x");
+        let first = a.apply_editor_document(&document(2, vec![paragraph("p1", "xy")]));
+        a.apply_editor_document(&document(3, vec![paragraph("p1", "xy"), paragraph("p2", "z")]));
+        // The frame carrying `first` was lost; later operations arrive on their own
+        let later: Vec<Op> = a
+            .ops_since(&b.vector())
+            .into_iter()
+            .filter(|op| !first.iter().any(|f| f.id() == op.id()))
+            .collect();
+        b.apply_remote(later);
+        assert!(a.ops_since(&b.vector()).iter().any(|op| op.id() == first[0].id()));
+        sync(&mut a, &mut b);
+        assert_eq!(texts(&a), texts(&b));
+    }
+
+    #[test]
+    fn stored_state_round_trips() {
+        let (a, _) = shared("saved");
+        let restored = CrdtDocument::from_json(&a.to_json().unwrap()).unwrap();
+        assert_eq!(texts(&restored), texts(&a));
+        assert_eq!(restored.vector(), a.vector());
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, KEY_LEN};
+use crate::error::AppError;
+use crate::DB_PATH;
+
+// URLs and tokens of the backends the app talks to, kept out of the binary.
+//
+// They live in `credentials.enc` next to the database, sealed with a random key
+// from `credentials.key` that only the current user can read. For development,
+// `<SERVICE>_URL` and `<SERVICE>_TOKEN` from the environment or a `.env` file
+// take precedence, e.g. `PYTHON_BACKEND_URL`. Tokens are never printed: the
+// `Debug` impl redacts them and the commands only report whether one is set.
+//
+// Other features keep their secrets (remote storage passwords and keys) in the same
+// file as named values, see `secret` and `set_secret`.
+
+pub const PYTHON_BACKEND: &str = "python_backend";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BackendCredentials {
+    pub url: String,
+    pub token: String,
+}
+
+impl fmt::Debug for BackendCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackendCredentials")
+            .field("url", &self.url)
+            .field("token", &"<redacted>")
+            .finish()
+    }
+}
+
+impl BackendCredentials {
+    pub fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), path.trim_start_matches('/'))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialSource {
+    Environment,
+    File,
+}
+
+// What the frontend gets to see of a credential
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CredentialsSummary {
+    pub service: String,
+    pub url: Option<String>,
+    pub has_token: bool,
+    pub token_source: Option<CredentialSource>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CredentialsTest {
+    pub reachable: bool,
+    pub authorized: bool,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+// Backends by service name next to the named secrets. Files written before secrets
+// existed hold only the backends, which still parse through `flatten`.
+#[derive(Serialize, Deserialize, Default)]
+struct Store {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    secrets: HashMap<String, String>,
+    #[serde(flatten)]
+    backends: HashMap<String, BackendCredentials>,
+}
+
+#[derive(Deserialize)]
+struct ApiKeyResponse {
+    api_key: Option<String>,
+}
+
+fn store_path() -> PathBuf {
+    PathBuf::from(DB_PATH).with_file_name("credentials.enc")
+}
+
+fn key_path() -> PathBuf {
+    PathBuf::from(DB_PATH).with_file_name("credentials.key")
+}
+
+// Development overrides from `.env`; a missing file is fine
+pub fn load_env() {
+    match dotenv::dotenv() {
+        Ok(path) => println!("Loaded environment from {}", path.display()),
+        Err(e) if e.not_found() => {}
+        Err(e) => eprintln!("Failed to load .env: {}", e),
+    }
+}
+
+fn env_var(service: &str, field: &str) -> Option<String> {
+    std::env::var(format!("{}_{}", service.to_uppercase(), field))
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+fn write_private(path: &PathBuf, bytes: &[u8]) -> Result<(), AppError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(bytes)?;
+    Ok(())
+}
+
+fn store_key() -> Result<[u8; KEY_LEN], AppError> {
+    let path = key_path();
+    if path.exists() {
+        return crypto::key_from_hex(&fs::read_to_string(&path)?);
+    }
+    let key = crypto::generate_key();
+    write_private(&path, hex::encode(key).as_bytes())?;
+    Ok(key)
+}
+
+fn load_store() -> Result<Store, AppError> {
+    let path = store_path();
+    if !path.exists() {
+        return Ok(Store::default());
+    }
+    let plaintext = crypto::open(&store_key()?, &fs::read(path)?)?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+fn save_store(store: &Store) -> Result<(), AppError> {
+    let sealed = crypto::seal(&store_key()?, &serde_json::to_vec(store)?)?;
+    write_private(&store_path(), &sealed)
+}
+
+fn token_source(service: &str, stored: Option<&BackendCredentials>) -> Option<CredentialSource> {
+    if env_var(service, "TOKEN").is_some() {
+        Some(CredentialSource::Environment)
+    } else if stored.map_or(false, |c| !c.token.is_empty()) {
+        Some(CredentialSource::File)
+    } else {
+        None
+    }
+}
+
+// Credentials for `service` with environment overrides applied
+pub fn backend(service: &str) -> Result<BackendCredentials, AppError> {
+    resolve(service, load_store()?.backends.remove(service))
+}
+
+fn resolve(service: &str, stored: Option<BackendCredentials>) -> Result<BackendCredentials, AppError> {
+    let url = env_var(service, "URL").or_else(|| stored.as_ref().map(|c| c.url.clone()));
+    let token = env_var(service, "TOKEN").or_else(|| stored.as_ref().map(|c| c.token.clone()));
+    match (url, token) {
+        (Some(url), Some(token)) if !url.is_empty() => Ok(BackendCredentials { url, token }),
+        _ => Err(AppError::CredentialsError(format!("No credentials configured for {}", service))),
+    }
+}
+
+// URL alone, for callers that need no token (e.g. health checks)
+pub fn backend_url(service: &str) -> Option<String> {
+    env_var(service, "URL").or_else(|| {
+        load_store()
+            .ok()?
+            .backends
+            .remove(service)
+            .map(|c| c.url)
+            .filter(|url| !url.is_empty())
+    })
+}
+
+pub fn secret(name: &str) -> Result<Option<String>, AppError> {
+    Ok(load_store()?.secrets.remove(name))
+}
+
+// Stores a secret, or removes it when `value` is `None`
+pub fn set_secret(name: &str, value: Option<&str>) -> Result<(), AppError> {
+    let mut store = load_store()?;
+    let changed = match value {
+        Some(value) => store.secrets.insert(name.to_string(), value.to_string()).as_deref() != Some(value),
+        None => store.secrets.remove(name).is_some(),
+    };
+    if changed {
+        save_store(&store)?;
+    }
+    Ok(())
+}
+
+async fn check(credentials: &BackendCredentials) -> CredentialsTest {
+    let result = Client::new()
+        .get(credentials.endpoint("api/v1/auths/"))
+        .bearer_auth(&credentials.token)
+        .send()
+        .await;
+    match result {
+        Ok(res) => CredentialsTest {
+            reachable: true,
+            authorized: res.status().is_success(),
+            status: Some(res.status().as_u16()),
+            error: None,
+        },
+        Err(e) => CredentialsTest { reachable: false, authorized: false, status: None, error: Some(e.to_string()) },
+    }
+}
+
+#[tauri::command]
+pub fn get_backend_credentials_command(service: String) -> Result<CredentialsSummary, String> {
+    let store = load_store().map_err(|e| e.to_string())?;
+    let stored = store.backends.get(&service);
+    Ok(CredentialsSummary {
+        url: env_var(&service, "URL").or_else(|| stored.map(|c| c.url.clone())),
+        has_token: token_source(&service, stored).is_some(),
+        token_source: token_source(&service, stored),
+        service,
+    })
+}
+
+#[tauri::command]
+pub fn set_backend_credentials_command(service: String, url: String, token: String) -> Result<(), String> {
+    println!("set_backend_credentials_command -> service: {}, url: {}", service, url);
+    if url.trim().is_empty() {
+        return Err("Backend URL must not be empty".to_string());
+    }
+    let mut store = load_store().map_err(|e| e.to_string())?;
+    store
+        .backends
+        .insert(service, BackendCredentials { url: url.trim().to_string(), token: token.trim().to_string() });
+    save_store(&store).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn delete_backend_credentials_command(service: String) -> Result<(), String> {
+    println!("delete_backend_credentials_command -> service: {}", service);
+    let mut store = load_store().map_err(|e| e.to_string())?;
+    store.backends.remove(&service);
+    save_store(&store).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn test_backend_credentials_command(service: String) -> Result<CredentialsTest, String> {
+    println!("test_backend_credentials_command -> service: {}", service);
+    let credentials = backend(&service).map_err(|e| e.to_string())?;
+    Ok(check(&credentials).await)
+}
+
+// Replaces the stored token. Without `new_token` the backend issues a fresh API key
+// for the current one; a given token is only stored after it authenticates.
+#[tauri::command]
+pub async fn rotate_backend_token_command(service: String, new_token: Option<String>) -> Result<(), String> {
+    println!("rotate_backend_token_command -> service: {}", service);
+    if env_var(&service, "TOKEN").is_some() {
+        return Err(format!(
+            "The token is set by {}_TOKEN in the environment; change it there",
+            service.to_uppercase()
+        ));
+    }
+    let current = backend(&service).map_err(|e| e.to_string())?;
+
+    let token = match new_token {
+        Some(token) => token.trim().to_string(),
+        None => {
+            let res = Client::new()
+                .post(current.endpoint("api/v1/auths/api_key"))
+                .bearer_auth(&current.token)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if res.status() == StatusCode::UNAUTHORIZED {
+                return Err("The current token was rejected by the backend".to_string());
+            }
+            let res = res.error_for_status().map_err(|e| e.to_string())?;
+            res.json::<ApiKeyResponse>()
+                .await
+                .map_err(|e| e.to_string())?
+                .api_key
+                .ok_or("The backend did not return a new API key")?
+        }
+    };
+
+    let rotated = BackendCredentials { url: current.url, token };
+    let test = check(&rotated).await;
+    if !test.authorized {
+        return Err(match test.error {
+            Some(error) => format!("New token could not be verified: {}", error),
+            None => format!("New token was rejected with status {}", test.status.unwrap_or_default()),
+        });
+    }
+
+    let mut store = load_store().map_err(|e| e.to_string())?;
+    store.backends.insert(service, rotated);
+    save_store(&store).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(url: &str, token: &str) -> BackendCredentials {
+        BackendCredentials { url: url.to_string(), token: token.to_string() }
+    }
+
+    #[test]
+    fn reads_files_written_before_named_secrets() {
+        let old = r#"{"python_backend":{"url":"http://localhost:8080","token":"abc"}}"#;
+        let store: Store = serde_json::from_str(old).unwrap();
+        assert!(store.secrets.is_empty());
+        assert_eq!(store.backends[PYTHON_BACKEND].url, "http://localhost:8080");
+        assert_eq!(store.backends[PYTHON_BACKEND].token, "abc");
+        // Written back in the same shape while there are no secrets
+        assert_eq!(serde_json::to_string(&store).unwrap(), old);
+
+        let mut store = store;
+        store.secrets.insert("remote.s3_secret_key".to_string(), "s3".to_string());
+        let reread: Store = serde_json::from_str(&serde_json::to_string(&store).unwrap()).unwrap();
+        assert_eq!(reread.secrets["remote.s3_secret_key"], "s3");
+        assert_eq!(reread.backends.len(), 1);
+        assert!(!reread.backends.contains_key("secrets"));
+    }
+
+    #[test]
+    fn environment_overrides_stored_values() {
+        // A service of its own so no other test sees these variables
+        let service = "credentials_test_override";
+        let stored = credentials("http://stored:8080", "stored-token");
+        assert_eq!(resolve(service, Some(stored.clone())).unwrap().url, "http://stored:8080");
+        assert_eq!(token_source(service, Some(&stored)), Some(CredentialSource::File));
+        assert!(matches!(resolve(service, None), Err(AppError::CredentialsError(_))));
+
+        std::env::set_var("CREDENTIALS_TEST_OVERRIDE_TOKEN", "env-token");
+        let resolved = resolve(service, Some(stored.clone())).unwrap();
+        assert_eq!((resolved.url.as_str(), resolved.token.as_str()), ("http://stored:8080", "env-token"));
+        assert_eq!(token_source(service, Some(&stored)), Some(CredentialSource::Environment));
+
+        std::env::set_var("CREDENTIALS_TEST_OVERRIDE_URL", "http://env:9090");
+        let resolved = resolve(service, None).unwrap();
+        assert_eq!((resolved.url.as_str(), resolved.token.as_str()), ("http://env:9090", "env-token"));
+
+        // Blank variables count as unset
+        std::env::set_var("CREDENTIALS_TEST_OVERRIDE_URL", " ");
+        assert_eq!(resolve(service, Some(stored)).unwrap().url, "http://stored:8080");
+    }
+
+    #[test]
+    fn missing_tokens_have_no_source() {
+        let service = "credentials_test_source";
+        assert_eq!(token_source(service, None), None);
+        assert_eq!(token_source(service, Some(&credentials("http://stored:8080", ""))), None);
+        assert!(resolve(service, Some(credentials("", "token"))).is_err());
+    }
+
+    #[test]
+    fn endpoints_join_with_one_slash() {
+        assert_eq!(credentials("http://host:8080", "t").endpoint("api/v1/auths/"), "http://host:8080/api/v1/auths/");
+        assert_eq!(credentials("http://host:8080/", "t").endpoint("/api/v1"), "http://host:8080/api/v1");
+        assert_eq!(credentials("http://host/base//", "t").endpoint("//api"), "http://host/base/api");
+    }
+
+    #[test]
+    fn debug_output_never_shows_the_token() {
+        let debug = format!("{:?}", credentials("http://host:8080", "secret-token"));
+        assert!(debug.contains("http://host:8080"));
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains("secret-token"));
+    }
+}
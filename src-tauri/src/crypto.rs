@@ -0,0 +1,94 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::error::AppError;
+
+// Authenticated encryption shared by everything that writes secrets or vault data.
+// Output layout: 24 byte random nonce followed by the XChaCha20-Poly1305 ciphertext.
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+pub fn generate_key() -> [u8; KEY_LEN] {
+    rand::random()
+}
+
+pub fn seal(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<Vec<u8>, AppError> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), plaintext)
+        .map_err(|_| AppError::CryptoError("Encryption failed".to_string()))?;
+
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub fn open(key: &[u8; KEY_LEN], sealed: &[u8]) -> Result<Vec<u8>, AppError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(AppError::CryptoError("Ciphertext too short".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| AppError::CryptoError("Wrong key or corrupted data".to_string()))
+}
+
+pub fn key_from_hex(value: &str) -> Result<[u8; KEY_LEN], AppError> {
+    let bytes = hex::decode(value.trim()).map_err(|e| AppError::CryptoError(e.to_string()))?;
+    bytes
+        .try_into()
+        .map_err(|_| AppError::CryptoError(format!("Key must be {} bytes", KEY_LEN)))
+}
+
+// Compares secrets without stopping at the first differing byte
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_data_opens_with_the_same_key_only() {
+        let key = generate_key();
+        let sealed = seal(&key, b"secret").unwrap();
+        assert_eq!(sealed.len(), NONCE_LEN + b"secret".len() + 16);
+        assert_eq!(open(&key, &sealed).unwrap(), b"secret");
+        assert!(open(&generate_key(), &sealed).is_err());
+        assert!(open(&key, &sealed[..NONCE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let key = generate_key();
+        let mut sealed = seal(&key, b"secret").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open(&key, &sealed).is_err());
+
+        let mut sealed = seal(&key, b"secret").unwrap();
+        sealed[0] ^= 1;
+        assert!(open(&key, &sealed).is_err());
+    }
+
+    #[test]
+    fn hex_keys_must_be_32_bytes() {
+        let key = generate_key();
+        assert_eq!(key_from_hex(&format!(" {}\n", hex::encode(key))).unwrap(), key);
+        assert!(key_from_hex(&hex::encode(&key[..31])).is_err());
+        assert!(key_from_hex(&hex::encode([0u8; 33])).is_err());
+        assert!(key_from_hex("not hex").is_err());
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_values() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+    }
+}
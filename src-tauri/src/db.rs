@@ -1,9 +1,11 @@
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use crate::error::AppError;
+use crate::chunking::{self, Chunk};
+use crate::markdown;
+use crate::vault;
 // use chrono::{DateTime, Utc};
-use std::time::{Instant};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct EditorDocument {
@@ -42,7 +44,11 @@ pub struct PythonBackendDocument {
     pub name: String,
     pub title: String,
     pub filename: String,
-    pub content: String,
+    pub content: String,                // Whole document as Markdown
+    pub document_id: i64,
+    pub folder_path: String,            // Folder names from the root, joined with "/"
+    pub tags: Vec<String>,              // Hashtags found anywhere in the document
+    pub chunks: Vec<Chunk>,             // What the backend embeds, one per header section
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -58,11 +64,75 @@ pub struct TimerSession {
     pub extended_stop_time: Option<String>,  // Optional ISO 8601 stop time for the extension
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FlashcardReview {
+    pub document_id: i64,
+    pub block_id: String,               // Editor.js id of the `flashcard` block
+    pub card_path: String,              // Index path of the card in the nested `items`, e.g. "1.0"
+    pub grade: i32,                     // Recall grade given by the user (0-5)
+    pub reviewed_at: String,            // ISO 8601 review time
+}
+
 // #[derive(Serialize, Deserialize, Debug)]
 // pub struct Calendar {
 
 // }
 
+// Tables of the core entities; every feature module adds its own with `create_tables`
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS folders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            parent_id INTEGER
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS documents (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            time TEXT NOT NULL,
+            content TEXT NOT NULL,
+            folder_id INTEGER,
+            FOREIGN KEY(folder_id) REFERENCES folders(id) ON DELETE SET NULL
+        )",
+        [],
+    )?;
+
+    // Create a table for timer sessions
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS timer_sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            work_duration INTEGER NOT NULL,
+            break_duration INTEGER NOT NULL,
+            start_time_work TEXT NOT NULL,
+            stop_time_work TEXT NOT NULL,
+            start_time_break TEXT,
+            stop_time_break TEXT,
+            extended BOOLEAN NOT NULL,
+            extended_start_time TEXT,
+            extended_stop_time TEXT
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS flashcard_reviews (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            document_id INTEGER NOT NULL,
+            block_id TEXT NOT NULL,
+            card_path TEXT NOT NULL,
+            grade INTEGER NOT NULL,
+            reviewed_at TEXT NOT NULL,
+            FOREIGN KEY(document_id) REFERENCES documents(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
 pub fn extract_title(doc: &str) -> Option<String> {
     let json: Value = serde_json::from_str(doc).ok()?; // Parse JSON
     let first_block = json.get("blocks")?.get(0)?; // Get first block (index 0)
@@ -73,57 +143,72 @@ pub fn extract_title(doc: &str) -> Option<String> {
 
 
 
-pub fn create_python_document(doc: &Document) -> PythonBackendDocument {
-    PythonBackendDocument {
-        collection_name: "my_notes".to_string(), // You can adjust this to be dynamic if needed
-        name: doc.title.clone(), // Use the title of the document as the name
-        title: doc.title.clone(), // Use the title of the document
-        filename: format!("{}.json", doc.title), // Create a filename based on the title
-        content: doc.content.clone(), // Use the content directly from the Document
-    }
+pub fn create_python_document(doc: &Document, collection_name: &str, folder_path: &str) -> Result<PythonBackendDocument, AppError> {
+    let editor_doc: EditorDocument = serde_json::from_str(&doc.content)?;
+    let content = markdown::document_to_markdown(&editor_doc);
+    let title = markdown::inline_to_text(&doc.title);
+    Ok(PythonBackendDocument {
+        collection_name: collection_name.to_string(), // Resolved from the folder or document binding
+        name: title.clone(), // Use the title of the document as the name
+        title, // Plain text, without the inline HTML of the header block
+        filename: format!("note-{}.md", doc.id), // Ids are unique and survive renames
+        tags: chunking::extract_tags(&content),
+        chunks: chunking::chunk_document(&editor_doc, doc.id, folder_path),
+        content,
+        document_id: doc.id,
+        folder_path: folder_path.to_string(),
+    })
 }
 
 
-pub fn save_document(conn: &Connection, doc: &EditorDocument, folderId: &i64) -> Result<(), AppError> {
+pub fn save_document(conn: &Connection, doc: &EditorDocument, folder_id: &i64) -> Result<i64, AppError> {
+    save_document_in(conn, doc, Some(*folder_id))
+}
+
+// Like `save_document`; without a folder the document is created at the top level
+pub fn save_document_in(conn: &Connection, doc: &EditorDocument, folder_id: Option<i64>) -> Result<i64, AppError> {
     let doc_json = serde_json::to_string(&doc)?;
     // TODO call a get title fucntion fr
-    println!("The fodler id : {}", folderId);
-    println!("{}", doc_json);
+    eprintln!("The fodler id : {:?}", folder_id);
+    eprintln!("Document length: {} bytes", doc_json.len());
     let title = extract_title(&doc_json);
     let title_str = match title {
         Some(t) => t, // Extract the value
-        none => "No title found".to_string(), // Provide a default string
+        None => "No title found".to_string(), // Provide a default string
     };
-    println!("Found title: {:?}", &title_str);
+    eprintln!("Found title: {:?}", &title_str);
     conn.execute(
         "INSERT INTO documents (title, time, content, folder_id) VALUES (?, ?, ?, ?)",
-        params![&title_str, &doc.time, &doc_json, &folderId],
+        params![&title_str, &doc.time, &vault::seal_text(&doc_json)?, &folder_id],
     )?;
-    println!("Saved doc");
-    Ok(())
+    eprintln!("Saved doc");
+    Ok(conn.last_insert_rowid())
 }
 
 pub fn load_document_for_editor(conn: &Connection, id: i64) -> Result<EditorDocument, AppError> {
-    println!("Loading Doc with id: {} ...", id);
+    eprintln!("Loading Doc with id: {} ...", id);
     // Prepare the statement
     let mut stmt = conn.prepare("SELECT id, title, time, content, folder_id FROM documents WHERE id = ?1")?;
     
     // Execute query and retrieve the row
     let doc: EditorDocument = stmt.query_row(params![id], |row| {
-        let content_json: String = row.get(3)?;
-        println!("Got follwing data from db {}", content_json);
+        let stored: String = row.get(3)?;
+        let content_json = vault::open_text(&stored).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e))
+        })?;
+        eprintln!("Got {} bytes of content from db", content_json.len());
         
         // Deserialize JSON string into EditorDocument
         let content: EditorDocument = serde_json::from_str(&content_json).map_err(|e| {
             rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
         })?;
-        println!("Returning data from load command ");
+        eprintln!("Returning data from load command ");
         Ok(content)
     }).map_err(|e| {
-        println!("Error executing query: {}", e);
+        eprintln!("Error executing query: {}", e);
         AppError::SqliteError(e)
     })?;
-    println!("Returned data to load command ");
+    eprintln!("Returned data to load command ");
     // Return the document
     Ok(doc)
 }
@@ -132,7 +217,9 @@ pub fn load_documents(conn: &Connection) -> Result<Vec<Document>, AppError> {
     let mut stmt = conn.prepare("SELECT id, title, time, content, folder_id FROM documents")?;
     let docs = stmt.query_map([], |row| {
         // Assuming the Document struct fields align with the query results
-        let content_json: String = row.get(3)?;
+        let content_json = vault::open_text(&row.get::<_, String>(3)?).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e))
+        })?;
         Ok(Document {
             id: row.get(0)?,
             title: row.get(1)?,
@@ -151,7 +238,9 @@ pub fn load_document(conn: &Connection, id: i64) -> Result<Document, AppError> {
     let mut stmt = conn.prepare("SELECT id, title, time, content, folder_id FROM documents WHERE id = ?1")?;
     
     let doc = stmt.query_row([id], |row| {
-        let content_json: String = row.get(3)?;
+        let content_json = vault::open_text(&row.get::<_, String>(3)?).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e))
+        })?;
         Ok(Document {
             id: row.get(0)?,
             title: row.get(1)?,
@@ -177,7 +266,7 @@ pub fn load_folders(conn: &Connection) -> Result<Vec<Folder>, rusqlite::Error> {
             // Fetch document IDs associated with this folder
             let mut doc_stmt = conn.prepare("SELECT id FROM documents WHERE folder_id = ?")?;
             let document_ids = doc_stmt
-                .query_map([id], |doc_row| Ok(doc_row.get(0)?))?
+                .query_map([id], |doc_row| doc_row.get(0))?
                 .collect::<Result<Vec<i64>, rusqlite::Error>>()?;
 
             Ok(Folder {
@@ -195,7 +284,9 @@ pub fn load_folders(conn: &Connection) -> Result<Vec<Folder>, rusqlite::Error> {
 pub fn gen_side_bar_list(conn: &Connection) -> Result<Vec<Document>, AppError> {
     let mut stmt = conn.prepare("SELECT id, title, time, content, folder_id FROM documents")?;
     let docs = stmt.query_map([], |row| {
-        let content_json: String = row.get(3)?;
+        let content_json = vault::open_text(&row.get::<_, String>(3)?).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e))
+        })?;
         Ok(Document {
             id: row.get(0)?,
             title: row.get(1)?,
@@ -211,14 +302,14 @@ pub fn gen_side_bar_list(conn: &Connection) -> Result<Vec<Document>, AppError> {
 
 pub fn update_document(conn: &Connection, id: i64, new_doc: &Document) -> Result<(), AppError> {
     // Print the new document content for debugging
-    println!("Updating document with ID: {}", id);
-    println!("New title: {}", &new_doc.title);
-    println!("New time: {}", &new_doc.time);
-    println!("New content: {}", &new_doc.content);
-    println!("New folder_id: {:?}", &new_doc.folder_id);
+    eprintln!("Updating document with ID: {}", id);
+    eprintln!("New title: {}", &new_doc.title);
+    eprintln!("New time: {}", &new_doc.time);
+    eprintln!("New content: {} bytes", new_doc.content.len());
+    eprintln!("New folder_id: {:?}", &new_doc.folder_id);
 
     // Convert folder_id properly for SQLite (if present)
-    let folder_id_value = new_doc.folder_id.map(|v| v as i64);
+    let folder_id_value = new_doc.folder_id;
 
     let sql_query = if folder_id_value.is_some() {
         // If `folder_id` is `Some`, include it in the update
@@ -228,22 +319,24 @@ pub fn update_document(conn: &Connection, id: i64, new_doc: &Document) -> Result
         "UPDATE documents SET title = ?, time = ?, content = ? WHERE id = ?"
     };
 
+    let content = vault::seal_text(&new_doc.content)?;
+
     let rows_affected = if let Some(folder_id) = folder_id_value {
         // Execute query with folder_id when it's Some(value)
-        conn.execute(sql_query, params![&new_doc.title, &new_doc.time, &new_doc.content, folder_id, &id])?
+        conn.execute(sql_query, params![&new_doc.title, &new_doc.time, &content, folder_id, &id])?
     } else {
         // Execute query without folder_id when it's None
-        conn.execute(sql_query, params![&new_doc.title, &new_doc.time, &new_doc.content, &id])?
+        conn.execute(sql_query, params![&new_doc.title, &new_doc.time, &content, &id])?
     };
 
     // Log the number of rows affected
-    println!("Rows affected: {}", rows_affected);
+    eprintln!("Rows affected: {}", rows_affected);
 
     // Check if the update was successful
     if rows_affected == 0 {
-        println!("Warning: No document found with ID: {}", id);
+        eprintln!("Warning: No document found with ID: {}", id);
     } else {
-        println!("Document updated successfully.");
+        eprintln!("Document updated successfully.");
     }
 
     Ok(())
@@ -251,16 +344,66 @@ pub fn update_document(conn: &Connection, id: i64, new_doc: &Document) -> Result
 
 
 
-// Function to insert a new folder with an optional parent_id
-pub fn insert_new_folder(conn: &Connection, name: &str, parent_id: Option<i64>) -> Result<(), rusqlite::Error> {
+// Function to insert a new folder with an optional parent_id, returns the new folder id
+pub fn insert_new_folder(conn: &Connection, name: &str, parent_id: Option<i64>) -> Result<i64, rusqlite::Error> {
     conn.execute(
         "INSERT INTO folders (name, parent_id) VALUES (?, ?)",
         params![name, parent_id], // Corrected query
     )?;
-    Ok(())
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn rename_folder(conn: &Connection, id: i64, name: &str) -> Result<Option<Option<i64>>, rusqlite::Error> {
+    let rows_affected = conn.execute("UPDATE folders SET name = ? WHERE id = ?", params![name, id])?;
+    if rows_affected == 0 {
+        return Ok(None);
+    }
+    let parent_id: Option<i64> = conn.query_row("SELECT parent_id FROM folders WHERE id = ?", [id], |row| row.get(0))?;
+    Ok(Some(parent_id))
 }
 
-pub fn save_timer_session(conn: &Connection, session: &TimerSession) -> Result<(), AppError> {
+// Deletes a folder; its documents and subfolders are moved up to the parent folder
+pub fn delete_folder(conn: &Connection, id: i64) -> Result<Option<(String, Option<i64>)>, rusqlite::Error> {
+    let folder = conn.query_row(
+        "SELECT name, parent_id FROM folders WHERE id = ?",
+        [id],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?)),
+    );
+    let (name, parent_id) = match folder {
+        Ok(folder) => folder,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    conn.execute("UPDATE documents SET folder_id = ? WHERE folder_id = ?", params![parent_id, id])?;
+    conn.execute("UPDATE folders SET parent_id = ? WHERE parent_id = ?", params![parent_id, id])?;
+    conn.execute("DELETE FROM folders WHERE id = ?", [id])?;
+    Ok(Some((name, parent_id)))
+}
+
+// Moves a document into another folder, returns the folder it was moved out of
+pub fn move_document(conn: &Connection, id: i64, folder_id: Option<i64>) -> Result<Option<i64>, AppError> {
+    let from_folder_id: Option<i64> = conn
+        .query_row("SELECT folder_id FROM documents WHERE id = ?", [id], |row| row.get(0))
+        .optional()?
+        .ok_or_else(|| AppError::NotFound(format!("document {}", id)))?;
+    conn.execute("UPDATE documents SET folder_id = ? WHERE id = ?", params![folder_id, id])?;
+    eprintln!("Moved document {} from {:?} to {:?}", id, from_folder_id, folder_id);
+    Ok(from_folder_id)
+}
+
+// Deletes a document, returns the folder it lived in
+pub fn delete_document(conn: &Connection, id: i64) -> Result<Option<i64>, AppError> {
+    let folder_id: Option<i64> = conn
+        .query_row("SELECT folder_id FROM documents WHERE id = ?", [id], |row| row.get(0))
+        .optional()?
+        .ok_or_else(|| AppError::NotFound(format!("document {}", id)))?;
+    conn.execute("DELETE FROM documents WHERE id = ?", [id])?;
+    eprintln!("Deleted document {}", id);
+    Ok(folder_id)
+}
+
+pub fn save_timer_session(conn: &Connection, session: &TimerSession) -> Result<i64, AppError> {
     conn.execute(
         "INSERT INTO timer_sessions (
             work_duration, 
@@ -286,6 +429,108 @@ pub fn save_timer_session(conn: &Connection, session: &TimerSession) -> Result<(
         ],
     ).map_err(AppError::SqliteError)?;
 
-    println!("Timer session saved successfully.");
-    Ok(())
-}
\ No newline at end of file
+    eprintln!("Timer session saved successfully.");
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn save_flashcard_review(conn: &Connection, review: &FlashcardReview) -> Result<i64, AppError> {
+    conn.execute(
+        "INSERT INTO flashcard_reviews (document_id, block_id, card_path, grade, reviewed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            review.document_id,
+            review.block_id,
+            review.card_path,
+            review.grade,
+            review.reviewed_at
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vault() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    fn note(conn: &Connection, title: &str, folder_id: Option<i64>) -> i64 {
+        let doc = EditorDocument {
+            time: 1,
+            blocks: vec![Block { id: "h".to_string(), r#type: "header".to_string(), data: json!({ "text": title, "level": 1 }) }],
+            version: "2.30.5".to_string(),
+        };
+        save_document_in(conn, &doc, folder_id).unwrap()
+    }
+
+    fn folder_of(conn: &Connection, id: i64) -> Option<i64> {
+        conn.query_row("SELECT folder_id FROM documents WHERE id = ?", [id], |row| row.get(0)).unwrap()
+    }
+
+    fn parent_of(conn: &Connection, id: i64) -> Option<i64> {
+        conn.query_row("SELECT parent_id FROM folders WHERE id = ?", [id], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn renaming_a_folder_reports_its_parent() {
+        let conn = vault();
+        let parent = insert_new_folder(&conn, "Projects", None).unwrap();
+        let child = insert_new_folder(&conn, "Garden", Some(parent)).unwrap();
+
+        assert_eq!(rename_folder(&conn, child, "Allotment").unwrap(), Some(Some(parent)));
+        assert_eq!(rename_folder(&conn, parent, "Work").unwrap(), Some(None));
+        assert_eq!(rename_folder(&conn, 99, "Missing").unwrap(), None);
+        let names: Vec<String> = load_folders(&conn).unwrap().into_iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["Work", "Allotment"]);
+    }
+
+    #[test]
+    fn deleting_a_folder_moves_its_contents_up() {
+        let conn = vault();
+        let root = insert_new_folder(&conn, "Projects", None).unwrap();
+        let middle = insert_new_folder(&conn, "Garden", Some(root)).unwrap();
+        let leaf = insert_new_folder(&conn, "Seeds", Some(middle)).unwrap();
+        let inside = note(&conn, "Beds", Some(middle));
+        let deeper = note(&conn, "Tomatoes", Some(leaf));
+
+        assert_eq!(delete_folder(&conn, middle).unwrap(), Some(("Garden".to_string(), Some(root))));
+        assert_eq!(folder_of(&conn, inside), Some(root));
+        assert_eq!(folder_of(&conn, deeper), Some(leaf));
+        assert_eq!(parent_of(&conn, leaf), Some(root));
+
+        // Top-level folders hand their contents to the top level
+        assert_eq!(delete_folder(&conn, root).unwrap(), Some(("Projects".to_string(), None)));
+        assert_eq!(folder_of(&conn, inside), None);
+        assert_eq!(parent_of(&conn, leaf), None);
+        assert_eq!(delete_folder(&conn, root).unwrap(), None);
+    }
+
+    #[test]
+    fn moving_a_document_reports_where_it_came_from() {
+        let conn = vault();
+        let folder = insert_new_folder(&conn, "Inbox", None).unwrap();
+        let id = note(&conn, "Loose", None);
+
+        assert_eq!(move_document(&conn, id, Some(folder)).unwrap(), None);
+        assert_eq!(folder_of(&conn, id), Some(folder));
+        assert_eq!(move_document(&conn, id, None).unwrap(), Some(folder));
+        assert_eq!(folder_of(&conn, id), None);
+        assert!(matches!(move_document(&conn, 99, None), Err(AppError::NotFound(_))));
+    }
+
+    #[test]
+    fn deleting_a_document_reports_its_folder() {
+        let conn = vault();
+        let folder = insert_new_folder(&conn, "Inbox", None).unwrap();
+        let id = note(&conn, "Draft", Some(folder));
+
+        assert_eq!(delete_document(&conn, id).unwrap(), Some(folder));
+        assert!(matches!(load_document(&conn, id), Err(AppError::SqliteError(_))));
+        assert!(matches!(delete_document(&conn, id), Err(AppError::NotFound(_))));
+    }
+}
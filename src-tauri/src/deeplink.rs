@@ -0,0 +1,389 @@
+use std::sync::Mutex;
+
+use reqwest::Url;
+use rusqlite::{params, Connection, OptionalExtension};
+use tauri::{AppHandle, Manager, Window, WindowBuilder, WindowUrl};
+
+use crate::db::load_document;
+use crate::error::AppError;
+use crate::markdown::inline_to_text;
+use crate::{events, vault, DB_PATH};
+
+// `j_desktop://` links to documents, blocks and folders:
+//
+//     j_desktop://doc/12?title=Meeting%20notes#blk-3f9a
+//     j_desktop://folder/4?title=Projects
+//
+// The OS hands links to the app through tauri-plugin-deep-link: on Windows and Linux
+// a second instance forwards the URL to the running one and exits, the first launch
+// gets it as its argument; on macOS the scheme is declared in `Info.plist`.
+//
+// The title is only there for links whose target is gone. A missing document is then
+// looked up by the block in the link (blocks keep their ids when they move between
+// notes) and by title (a note deleted and re-imported gets a new id); only when both
+// fail does the main window get a `missing` link to explain it.
+//
+// An underscore is not allowed in a URL scheme, so `Url` can't parse these links
+// directly; they are parsed and written as `http://` URLs with the scheme swapped.
+
+pub const SCHEME: &str = "j_desktop";
+const MAIN_WINDOW: &str = "main";
+
+// Managed Tauri state: a link that arrived while the vault was locked or before the
+// frontend was listening, taken by the main window once it is ready
+#[derive(Default)]
+pub struct DeepLinkState {
+    pending: Mutex<Option<String>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Target {
+    Document { id: i64, block_id: Option<String> },
+    Folder { id: i64 },
+}
+
+#[derive(Debug)]
+struct Link {
+    target: Target,
+    title: Option<String>,
+}
+
+fn invalid(url: &str) -> AppError {
+    AppError::InvalidInput(format!("Not a {} link: {}", SCHEME, url))
+}
+
+fn parse(url: &str) -> Result<Link, AppError> {
+    let rest = url
+        .get(..SCHEME.len() + 3)
+        .filter(|prefix| prefix.eq_ignore_ascii_case(&format!("{}://", SCHEME)))
+        .map(|prefix| &url[prefix.len()..])
+        .ok_or_else(|| invalid(url))?;
+    let parsed = Url::parse(&format!("http://{}", rest)).map_err(|_| invalid(url))?;
+    let id: i64 = parsed
+        .path()
+        .trim_matches('/')
+        .parse()
+        .map_err(|_| invalid(url))?;
+    let title = parsed.query_pairs().find(|(k, _)| k == "title").map(|(_, v)| v.into_owned());
+    let target = match parsed.host_str() {
+        Some("doc") | Some("document") => Target::Document {
+            id,
+            // Block ids are short alphanumeric strings; they end up in a window URL
+            block_id: parsed
+                .fragment()
+                .filter(|f| !f.is_empty() && f.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                .map(str::to_string),
+        },
+        Some("folder") => Target::Folder { id },
+        _ => return Err(invalid(url)),
+    };
+    Ok(Link { target, title })
+}
+
+fn format_link(kind: &str, id: i64, title: &str, block_id: Option<&str>) -> String {
+    let mut url = Url::parse(&format!("http://{}/{}", kind, id)).expect("static link base");
+    if !title.is_empty() {
+        url.query_pairs_mut().append_pair("title", title);
+    }
+    url.set_fragment(block_id);
+    format!("{}://{}", SCHEME, &url.as_str()["http://".len()..])
+}
+
+pub fn document_link(conn: &Connection, id: i64, block_id: Option<&str>) -> Result<String, AppError> {
+    let doc = load_document(conn, id)?;
+    Ok(format_link("doc", id, &inline_to_text(&doc.title), block_id))
+}
+
+pub fn folder_link(conn: &Connection, id: i64) -> Result<String, AppError> {
+    let name: String = conn.query_row("SELECT name FROM folders WHERE id = ?", [id], |row| row.get(0))?;
+    Ok(format_link("folder", id, &name, None))
+}
+
+// Opens a document in its own window, or focuses the window if it is already open.
+// The frontend reads the `doc` and `block` query parameters and loads the document
+// via `load_document_command`; an open window gets the block as a `deep-link` event.
+pub fn open_document_window(app: &AppHandle, id: i64, block_id: Option<&str>) -> Result<(), AppError> {
+    let label = format!("doc-{}", id);
+    let window_error = |e: tauri::Error| AppError::WindowError(format!("Failed to open document window: {}", e));
+
+    if let Some(window) = app.get_window(&label) {
+        window.show().and_then(|_| window.set_focus()).map_err(window_error)?;
+        if block_id.is_some() {
+            events::deep_link(&window, events::DeepLink {
+                url: format_link("doc", id, "", block_id),
+                document_id: Some(id),
+                block_id: block_id.map(str::to_string),
+                folder_id: None,
+                missing: false,
+                title: None,
+            });
+        }
+        return Ok(());
+    }
+
+    let conn = Connection::open(DB_PATH)?;
+    let doc = load_document(&conn, id)?;
+    let mut url = format!("index.html?doc={}", id);
+    if let Some(block_id) = block_id {
+        url.push_str(&format!("&block={}", block_id));
+    }
+    WindowBuilder::new(app, label, WindowUrl::App(url.into()))
+        .title(format!("{} - j_desktop", doc.title))
+        .inner_size(800.0, 600.0)
+        .resizable(true)
+        .build()
+        .map_err(window_error)?;
+    Ok(())
+}
+
+fn main_window(app: &AppHandle) -> Option<Window> {
+    let window = app.get_window(MAIN_WINDOW)?;
+    if let Err(e) = window.show().and_then(|_| window.set_focus()) {
+        eprintln!("Failed to show main window: {}", e);
+    }
+    Some(window)
+}
+
+// Where a document that no longer exists went: the note now holding the linked
+// block, or else the newest note with the same title
+fn relocated_document(conn: &Connection, block_id: Option<&str>, title: Option<&str>) -> Result<Option<i64>, AppError> {
+    if let Some(block_id) = block_id {
+        // `block_ids` is a JSON array of strings
+        let by_block: Option<i64> = conn
+            .query_row(
+                "SELECT c.document_id FROM embedding_chunks c, json_each(c.block_ids) b WHERE b.value = ? LIMIT 1",
+                params![block_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if by_block.is_some() {
+            return Ok(by_block);
+        }
+    }
+    let title = match title {
+        Some(title) => title,
+        None => return Ok(None),
+    };
+    // Stored titles may carry inline markup, links carry the plain text
+    let mut stmt = conn.prepare("SELECT id, title FROM documents ORDER BY id DESC")?;
+    let by_title = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?
+        .into_iter()
+        .find(|(_, stored)| inline_to_text(stored) == title)
+        .map(|(id, _)| id);
+    Ok(by_title)
+}
+
+fn open_link(app: &AppHandle, url: &str) -> Result<(), AppError> {
+    let link = parse(url)?;
+    if vault::is_locked() {
+        *app.state::<DeepLinkState>().pending.lock().unwrap() = Some(url.to_string());
+        main_window(app);
+        return Ok(());
+    }
+    let conn = Connection::open(DB_PATH)?;
+    let missing = |document_id: Option<i64>, block_id: Option<String>, folder_id: Option<i64>| {
+        if let Some(window) = main_window(app) {
+            events::deep_link(&window, events::DeepLink {
+                url: url.to_string(),
+                document_id,
+                block_id,
+                folder_id,
+                missing: true,
+                title: link.title.clone(),
+            });
+        }
+    };
+
+    match &link.target {
+        Target::Document { id, block_id } => {
+            let exists: Option<i64> =
+                conn.query_row("SELECT id FROM documents WHERE id = ?", [id], |row| row.get(0)).optional()?;
+            let found = match exists {
+                Some(id) => Some(id),
+                None => relocated_document(&conn, block_id.as_deref(), link.title.as_deref())?,
+            };
+            match found {
+                Some(found) => {
+                    if found != *id {
+                        println!("Deep link to missing document {} resolved to {}", id, found);
+                    }
+                    open_document_window(app, found, block_id.as_deref())
+                }
+                None => {
+                    missing(Some(*id), block_id.clone(), None);
+                    Ok(())
+                }
+            }
+        }
+        Target::Folder { id } => {
+            let exists: Option<i64> =
+                conn.query_row("SELECT id FROM folders WHERE id = ?", [id], |row| row.get(0)).optional()?;
+            match (exists, main_window(app)) {
+                (Some(_), Some(window)) => events::deep_link(&window, events::DeepLink {
+                    url: url.to_string(),
+                    document_id: None,
+                    block_id: None,
+                    folder_id: Some(*id),
+                    missing: false,
+                    title: link.title.clone(),
+                }),
+                (None, _) => missing(None, None, Some(*id)),
+                (Some(_), None) => {}
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn handle(app: &AppHandle, url: &str) {
+    println!("Opening deep link {}", url);
+    if let Err(e) = open_link(app, url) {
+        eprintln!("Failed to open deep link {}: {}", url, e);
+    }
+}
+
+pub fn start(app: AppHandle) {
+    let handle_app = app.clone();
+    if let Err(e) = tauri_plugin_deep_link::register(SCHEME, move |url| handle(&handle_app, &url)) {
+        eprintln!("Failed to register {}:// links: {}", SCHEME, e);
+    }
+    // macOS delivers the launch link through the handler above
+    #[cfg(not(target_os = "macos"))]
+    if let Some(url) = std::env::args().nth(1).filter(|arg| arg.to_lowercase().starts_with(SCHEME)) {
+        *app.state::<DeepLinkState>().pending.lock().unwrap() = Some(url);
+    }
+}
+
+#[tauri::command]
+pub fn document_link_command(id: i64, block_id: Option<String>) -> Result<String, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    document_link(&conn, id, block_id.as_deref()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn folder_link_command(id: i64) -> Result<String, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    folder_link(&conn, id).map_err(|e| e.to_string())
+}
+
+// For links pasted inside the app, which the OS never sees
+#[tauri::command]
+pub fn open_deep_link_command(app: AppHandle, url: String) -> Result<(), String> {
+    println!("open_deep_link_command -> {}", url);
+    open_link(&app, &url).map_err(|e| e.to_string())
+}
+
+// Called by the main window once it listens for `deep-link` and after unlocking;
+// opens the link that arrived before that
+#[tauri::command]
+pub fn open_pending_deep_link_command(app: AppHandle, state: tauri::State<DeepLinkState>) -> Result<(), String> {
+    if vault::is_locked() {
+        return Ok(());
+    }
+    let pending = state.pending.lock().unwrap().take();
+    match pending {
+        Some(url) => open_link(&app, &url).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{save_document_in, Block, EditorDocument};
+    use serde_json::json;
+
+    fn document(url: &str) -> (i64, Option<String>, Option<String>) {
+        let link = parse(url).unwrap();
+        match link.target {
+            Target::Document { id, block_id } => (id, block_id, link.title),
+            other => panic!("expected a document link, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_document_and_folder_links() {
+        assert_eq!(
+            document("j_desktop://doc/12?title=Meeting%20notes#blk-3f9a"),
+            (12, Some("blk-3f9a".to_string()), Some("Meeting notes".to_string()))
+        );
+        assert_eq!(document("j_desktop://document/12/"), (12, None, None));
+        assert_eq!(document("J_DESKTOP://Doc/7"), (7, None, None));
+        assert_eq!(document("J_Desktop://doc/7#"), (7, None, None));
+
+        let folder = parse("j_desktop://folder/4?title=Projects#ignored").unwrap();
+        assert_eq!(folder.target, Target::Folder { id: 4 });
+        assert_eq!(folder.title.as_deref(), Some("Projects"));
+    }
+
+    #[test]
+    fn rejects_other_schemes_hosts_and_ids() {
+        for url in [
+            "j_desktop://doc/abc",
+            "j_desktop://doc/",
+            "j_desktop://doc/12/34",
+            "j_desktop://note/12",
+            "j_desktop:/doc/12",
+            "https://doc/12",
+            "j_desktop",
+            "",
+        ] {
+            assert!(matches!(parse(url), Err(AppError::InvalidInput(_))), "accepted {}", url);
+        }
+    }
+
+    #[test]
+    fn drops_block_ids_with_other_characters() {
+        assert_eq!(document("j_desktop://doc/1#blk_3-F9a").1, Some("blk_3-F9a".to_string()));
+        assert_eq!(document("j_desktop://doc/1#blk%203f").1, None);
+        assert_eq!(document("j_desktop://doc/1#<script>").1, None);
+        assert_eq!(document("j_desktop://doc/1#a/b").1, None);
+    }
+
+    #[test]
+    fn formatted_links_parse_back() {
+        let title = "Plans & notes: 50% done #2 ✓";
+        let url = format_link("doc", 12, title, Some("blk-3f9a"));
+        assert!(url.starts_with("j_desktop://doc/12?title="));
+        assert!(!url.contains(' '));
+        assert_eq!(document(&url), (12, Some("blk-3f9a".to_string()), Some(title.to_string())));
+
+        assert_eq!(format_link("doc", 3, "", None), "j_desktop://doc/3");
+        let folder = parse(&format_link("folder", 4, "Projects/2024", None)).unwrap();
+        assert_eq!(folder.target, Target::Folder { id: 4 });
+        assert_eq!(folder.title.as_deref(), Some("Projects/2024"));
+    }
+
+    #[test]
+    fn relocates_documents_by_exact_block_id_then_title() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn).unwrap();
+        crate::ai::create_tables(&conn).unwrap();
+        let doc = EditorDocument {
+            time: 1,
+            blocks: vec![Block { id: "h".to_string(), r#type: "header".to_string(), data: json!({ "text": "Plan", "level": 1 }) }],
+            version: "2.30.5".to_string(),
+        };
+        let longer = save_document_in(&conn, &doc, None).unwrap();
+        let exact = save_document_in(&conn, &doc, None).unwrap();
+        for (document_id, block_ids) in [(longer, r#"["xblk-1", "blk-10"]"#), (exact, r#"["blk-1"]"#)] {
+            conn.execute(
+                "INSERT INTO embedding_chunks (document_id, chunk_index, block_ids, headings, text, content_hash, model, vector)
+                 VALUES (?, 0, ?, '[]', '', '', 'test', x'')",
+                params![document_id, block_ids],
+            )
+            .unwrap();
+        }
+        conn.execute("UPDATE documents SET title = '<b>Old plan</b>' WHERE id = ?", [longer]).unwrap();
+
+        assert_eq!(relocated_document(&conn, Some("blk-1"), None).unwrap(), Some(exact));
+        assert_eq!(relocated_document(&conn, Some("blk-10"), None).unwrap(), Some(longer));
+        assert_eq!(relocated_document(&conn, Some("blk"), None).unwrap(), None);
+        // Titles compare as plain text
+        assert_eq!(relocated_document(&conn, Some("gone"), Some("Old plan")).unwrap(), Some(longer));
+        assert_eq!(relocated_document(&conn, None, Some("Missing")).unwrap(), None);
+    }
+}
@@ -10,4 +10,34 @@ pub enum AppError {
 
     #[error("IO error")]
     IoError(#[from] std::io::Error),
+
+    #[error("HTTP error")]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("Sync error: {0}")]
+    SyncError(String),
+
+    #[error("Crypto error: {0}")]
+    CryptoError(String),
+
+    #[error("Credentials error: {0}")]
+    CredentialsError(String),
+
+    #[error("Vault is locked")]
+    VaultLocked,
+
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("AI error: {0}")]
+    AiError(String),
+
+    #[error("Window error: {0}")]
+    WindowError(String),
+
+    #[error("Server error: {0}")]
+    ServerError(String),
 }
\ No newline at end of file
@@ -0,0 +1,180 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Window};
+
+// Event names the frontend listens on (`listen("document-updated", ...)`)
+pub const DOCUMENT_CREATED: &str = "document-created";
+pub const DOCUMENT_UPDATED: &str = "document-updated";
+pub const DOCUMENT_MOVED: &str = "document-moved";
+pub const DOCUMENT_DELETED: &str = "document-deleted";
+pub const FOLDER_CHANGED: &str = "folder-changed";
+pub const TIMER_SESSION_SAVED: &str = "timer-session-saved";
+pub const VAULT_SYNCED: &str = "vault-synced";
+pub const VAULT_LOCKED: &str = "vault-locked";
+pub const VAULT_UNLOCKED: &str = "vault-unlocked";
+pub const CHAT_TOKEN: &str = "chat-token";
+pub const DEEP_LINK: &str = "deep-link";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DocumentCreated {
+    pub id: i64,
+    pub title: String,
+    pub folder_id: Option<i64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DocumentUpdated {
+    pub id: i64,
+    pub title: String,
+    pub time: String,
+    pub folder_id: Option<i64>,
+    // Label of the window that made the change so it can ignore its own echo
+    pub source_window: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DocumentMoved {
+    pub id: i64,
+    pub from_folder_id: Option<i64>,
+    pub to_folder_id: Option<i64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DocumentDeleted {
+    pub id: i64,
+    pub folder_id: Option<i64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderChangeKind {
+    Created,
+    Renamed,
+    Deleted,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FolderChanged {
+    pub id: i64,
+    pub name: String,
+    pub parent_id: Option<i64>,
+    pub kind: FolderChangeKind,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TimerSessionSaved {
+    pub id: i64,
+    pub work_duration: i32,
+    pub break_duration: i32,
+    pub start_time_work: String,
+}
+
+// Sent after changes from a peer were applied; windows should reload their lists
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VaultSynced {
+    pub peer: String,
+    pub pulled: usize,
+    pub pushed: usize,
+    pub conflicts: usize,
+}
+
+// Sent when the vault key was dropped; windows should show the unlock screen
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VaultLocked {
+    pub auto: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VaultUnlocked {}
+
+// A piece of an assistant answer while it is generated
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ChatToken {
+    pub conversation_id: i64,
+    pub token: String,
+}
+
+// A `j_desktop://` link was opened; the receiving window shows the document or
+// folder and scrolls to the block
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DeepLink {
+    pub url: String,
+    pub document_id: Option<i64>,
+    pub block_id: Option<String>,
+    pub folder_id: Option<i64>,
+    // The target no longer exists; `title` (from the link) is all that is known
+    pub missing: bool,
+    pub title: Option<String>,
+}
+
+// Broadcast to every open window and to Rust listeners registered with `listen`.
+// A failed emit only means no window is listening, so it is logged and never turned
+// into a command error.
+fn emit<P: Serialize + Clone>(app: &AppHandle, event: &str, payload: P) {
+    app.trigger_global(event, serde_json::to_string(&payload).ok());
+    if let Err(e) = app.emit_all(event, payload) {
+        eprintln!("Failed to emit {}: {}", event, e);
+    }
+}
+
+// Subscribes backend services to change events with the typed payload
+pub fn listen<P, F>(app: &AppHandle, event: &'static str, handler: F)
+where
+    P: DeserializeOwned,
+    F: Fn(P) + Send + 'static,
+{
+    app.listen_global(event, move |e| {
+        match e.payload().map(serde_json::from_str::<P>) {
+            Some(Ok(payload)) => handler(payload),
+            Some(Err(err)) => eprintln!("Malformed {} payload: {}", event, err),
+            None => {}
+        }
+    });
+}
+
+pub fn document_created(app: &AppHandle, payload: DocumentCreated) {
+    emit(app, DOCUMENT_CREATED, payload);
+}
+
+pub fn document_updated(app: &AppHandle, payload: DocumentUpdated) {
+    emit(app, DOCUMENT_UPDATED, payload);
+}
+
+pub fn document_moved(app: &AppHandle, payload: DocumentMoved) {
+    emit(app, DOCUMENT_MOVED, payload);
+}
+
+pub fn document_deleted(app: &AppHandle, payload: DocumentDeleted) {
+    emit(app, DOCUMENT_DELETED, payload);
+}
+
+pub fn folder_changed(app: &AppHandle, payload: FolderChanged) {
+    emit(app, FOLDER_CHANGED, payload);
+}
+
+pub fn timer_session_saved(app: &AppHandle, payload: TimerSessionSaved) {
+    emit(app, TIMER_SESSION_SAVED, payload);
+}
+
+pub fn vault_synced(app: &AppHandle, payload: VaultSynced) {
+    emit(app, VAULT_SYNCED, payload);
+}
+
+pub fn vault_locked(app: &AppHandle, payload: VaultLocked) {
+    emit(app, VAULT_LOCKED, payload);
+}
+
+pub fn vault_unlocked(app: &AppHandle, payload: VaultUnlocked) {
+    emit(app, VAULT_UNLOCKED, payload);
+}
+
+pub fn chat_token(app: &AppHandle, payload: ChatToken) {
+    emit(app, CHAT_TOKEN, payload);
+}
+
+// Only the window that should react gets the link, not every window
+pub fn deep_link(window: &Window, payload: DeepLink) {
+    if let Err(e) = window.emit(DEEP_LINK, payload) {
+        eprintln!("Failed to emit {}: {}", DEEP_LINK, e);
+    }
+}
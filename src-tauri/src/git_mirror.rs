@@ -0,0 +1,670 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::db::{extract_title, insert_new_folder, load_document, load_documents, load_folders, save_document_in, update_document, Block, Document, EditorDocument, Folder};
+use crate::error::AppError;
+use crate::settings::{get_json, set_json};
+use crate::sync::{record_change, ChangeOp, Entity};
+use crate::{events, markdown, vault, DB_PATH};
+
+// Mirror of the vault into a git repository.
+//
+// Every document is written as `<folder path>/<title>.jd-<id>.md` (or `.json`), with
+// the folder path following the `folders` table. Changes are collected from the change
+// events and committed in batches, so a burst of autosaves becomes one commit.
+//
+// The repository may hold files of the user's own. Only names with the `.jd-<id>`
+// marker are read as existing documents, and an export only deletes files listed in
+// `.jd-manifest`, the list of files it wrote last time.
+//
+// The mirror writes notes as plain text, so it cannot be used together with vault
+// encryption: enabling either one is refused while the other is on.
+
+const CONFIG_KEY: &str = "git_mirror.config";
+const ID_MARKER: &str = ".jd-";
+pub const ENCRYPTION_CONFLICT: &str =
+    "The git mirror writes notes as plain text and cannot be used with vault encryption";
+const MANIFEST_FILE: &str = ".jd-manifest";
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct GitMirrorConfig {
+    pub enabled: bool,
+    pub path: String,
+    pub format: MirrorFormat,
+    // Changes are committed once the oldest pending one is this old
+    pub batch_seconds: u64,
+}
+
+impl Default for GitMirrorConfig {
+    fn default() -> Self {
+        GitMirrorConfig { enabled: false, path: String::new(), format: MirrorFormat::Markdown, batch_seconds: 30 }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PendingChange {
+    Created { title: String },
+    Updated { title: String },
+    Moved { id: i64 },
+    Deleted { id: i64 },
+    // Folder renames and peer syncs touch many files at once
+    Everything,
+}
+
+// Managed Tauri state
+#[derive(Default)]
+pub struct GitMirrorState {
+    pending: Mutex<Vec<PendingChange>>,
+    oldest: Mutex<Option<Instant>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Manifest {
+    // Paths relative to the repository root, `/` separated
+    files: BTreeSet<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+fn load_config(conn: &Connection) -> Result<GitMirrorConfig, AppError> {
+    get_json(conn, CONFIG_KEY, GitMirrorConfig::default())
+}
+
+pub fn is_enabled(conn: &Connection) -> Result<bool, AppError> {
+    let config = load_config(conn)?;
+    Ok(config.enabled && !config.path.is_empty())
+}
+
+fn git(repo: &Path, args: &[&str]) -> Result<String, AppError> {
+    let output = Command::new("git").arg("-C").arg(repo).args(args).output()?;
+    if !output.status.success() {
+        return Err(AppError::SyncError(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn ensure_repo(repo: &Path) -> Result<(), AppError> {
+    fs::create_dir_all(repo)?;
+    if !repo.join(".git").exists() {
+        git(repo, &["init"])?;
+    }
+    Ok(())
+}
+
+// File and directory names safe on every platform
+fn sanitize(name: &str) -> String {
+    let cleaned: String = markdown::inline_to_text(name)
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let cleaned = cleaned.trim().trim_matches('.').to_string();
+    if cleaned.is_empty() {
+        "Untitled".to_string()
+    } else {
+        cleaned.chars().take(80).collect()
+    }
+}
+
+fn folder_path(folders: &HashMap<i64, &Folder>, folder_id: Option<i64>) -> PathBuf {
+    let mut parts = Vec::new();
+    let mut current = folder_id;
+    // Bounded walk in case of a parent cycle
+    while let Some(id) = current {
+        match folders.get(&id) {
+            Some(folder) if parts.len() < 32 => {
+                parts.push(sanitize(&folder.name));
+                current = folder.parent_id;
+            }
+            _ => break,
+        }
+    }
+    parts.iter().rev().collect()
+}
+
+// Id encoded in a mirrored file name, `<title>.jd-<id>.<ext>`. `sanitize` replaces dots
+// in titles, so the marker can't come from a title.
+fn file_document_id(path: &Path) -> Option<i64> {
+    let stem = path.file_stem()?.to_str()?;
+    let (_, id) = stem.rsplit_once(ID_MARKER)?;
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    id.parse().ok()
+}
+
+fn relative_key(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+fn read_manifest(root: &Path) -> Result<Manifest, AppError> {
+    match fs::read_to_string(root.join(MANIFEST_FILE)) {
+        Ok(text) => Ok(serde_json::from_str(&text)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn mirrored_files(root: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.file_name().map_or(false, |n| n == ".git") {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else if matches!(path.extension().and_then(|e| e.to_str()), Some("md") | Some("json")) {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn render(doc: &Document, format: MirrorFormat) -> Result<String, AppError> {
+    let editor_doc: EditorDocument = serde_json::from_str(&doc.content)?;
+    match format {
+        MirrorFormat::Json => Ok(serde_json::to_string_pretty(&editor_doc)?),
+        MirrorFormat::Markdown => Ok(format!(
+            "---\nid: {}\ntitle: {}\ntime: {}\n---\n\n{}",
+            doc.id,
+            markdown::inline_to_text(&doc.title),
+            doc.time,
+            markdown::document_to_markdown(&editor_doc)
+        )),
+    }
+}
+
+// Writes the current state of every document and removes files of documents that
+// no longer exist or moved. Cheap enough for a vault of notes and keeps the tree
+// exactly in line with the database. Also used by the command-line export.
+pub fn export_tree(conn: &Connection, root: &Path, format: MirrorFormat) -> Result<(), AppError> {
+    let folders = load_folders(conn)?;
+    let folder_map: HashMap<i64, &Folder> = folders.iter().map(|f| (f.id, f)).collect();
+    let extension = match format {
+        MirrorFormat::Markdown => "md",
+        MirrorFormat::Json => "json",
+    };
+
+    let mut wanted: HashMap<PathBuf, String> = HashMap::new();
+    for doc in load_documents(conn)? {
+        let rendered = match render(&doc, format) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                eprintln!("Skipping document {} in git mirror: {}", doc.id, e);
+                continue;
+            }
+        };
+        let path = root
+            .join(folder_path(&folder_map, doc.folder_id))
+            .join(format!("{}{}{}.{}", sanitize(&doc.title), ID_MARKER, doc.id, extension));
+        wanted.insert(path, rendered);
+    }
+
+    let manifest = Manifest { files: wanted.keys().map(|path| relative_key(root, path)).collect() };
+    for stale in read_manifest(root)?.files.difference(&manifest.files) {
+        let path = root.join(stale);
+        if path.is_file() {
+            fs::remove_file(&path)?;
+        }
+    }
+    fs::create_dir_all(root)?;
+    fs::write(root.join(MANIFEST_FILE), serde_json::to_string_pretty(&manifest)?)?;
+    for (path, content) in wanted {
+        if fs::read_to_string(&path).ok().as_deref() == Some(content.as_str()) {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)?;
+    }
+    Ok(())
+}
+
+fn commit_message(conn: &Connection, pending: &[PendingChange]) -> String {
+    let title = |id: i64| {
+        load_document(conn, id)
+            .map(|d| markdown::inline_to_text(&d.title))
+            .unwrap_or_else(|_| format!("document {}", id))
+    };
+    let mut lines: Vec<String> = Vec::new();
+    for change in pending {
+        let line = match change {
+            PendingChange::Created { title } => format!("create {}", markdown::inline_to_text(title)),
+            PendingChange::Updated { title } => format!("update {}", markdown::inline_to_text(title)),
+            PendingChange::Moved { id } => format!("move {}", title(*id)),
+            PendingChange::Deleted { id } => format!("delete document {}", id),
+            PendingChange::Everything => "sync vault".to_string(),
+        };
+        if !lines.contains(&line) {
+            lines.push(line);
+        }
+    }
+    match lines.len() {
+        0 => "update vault".to_string(),
+        1 => lines.remove(0),
+        n => format!("update {} notes\n\n{}", n, lines.iter().map(|l| format!("- {}", l)).collect::<Vec<_>>().join("\n")),
+    }
+}
+
+fn flush(conn: &Connection, config: &GitMirrorConfig, pending: &[PendingChange]) -> Result<(), AppError> {
+    if vault::is_enabled() {
+        return Err(AppError::InvalidInput(ENCRYPTION_CONFLICT.to_string()));
+    }
+    // A locked vault cannot render notes, and exporting anyway would delete their files
+    if vault::is_locked() {
+        return Err(AppError::VaultLocked);
+    }
+    let root = Path::new(&config.path);
+    ensure_repo(root)?;
+    export_tree(conn, root, config.format)?;
+    git(root, &["add", "-A"])?;
+    if git(root, &["status", "--porcelain"])?.trim().is_empty() {
+        return Ok(());
+    }
+    git(root, &["commit", "-q", "-m", &commit_message(conn, pending)])?;
+    Ok(())
+}
+
+fn queue(app: &AppHandle, change: PendingChange) {
+    let state = app.state::<GitMirrorState>();
+    state.pending.lock().unwrap().push(change);
+    state.oldest.lock().unwrap().get_or_insert_with(Instant::now);
+}
+
+// Subscribes to change events and commits batches in the background
+pub fn start(app: AppHandle) {
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_CREATED, move |e: events::DocumentCreated| {
+        queue(&handle, PendingChange::Created { title: e.title })
+    });
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_UPDATED, move |e: events::DocumentUpdated| {
+        queue(&handle, PendingChange::Updated { title: e.title })
+    });
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_MOVED, move |e: events::DocumentMoved| {
+        queue(&handle, PendingChange::Moved { id: e.id })
+    });
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_DELETED, move |e: events::DocumentDeleted| {
+        queue(&handle, PendingChange::Deleted { id: e.id })
+    });
+    let handle = app.clone();
+    events::listen(&app, events::FOLDER_CHANGED, move |_: events::FolderChanged| {
+        queue(&handle, PendingChange::Everything)
+    });
+    let handle = app.clone();
+    events::listen(&app, events::VAULT_SYNCED, move |_: events::VaultSynced| {
+        queue(&handle, PendingChange::Everything)
+    });
+
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        let state = app.state::<GitMirrorState>();
+        let oldest = *state.oldest.lock().unwrap();
+        let oldest = match oldest {
+            Some(oldest) => oldest,
+            None => continue,
+        };
+
+        let conn = match Connection::open(DB_PATH) {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Git mirror could not open database: {}", e);
+                continue;
+            }
+        };
+        let config = match load_config(&conn) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Git mirror could not load config: {}", e);
+                continue;
+            }
+        };
+        // A mirror left enabled from before encryption was turned on stays idle
+        if !config.enabled || config.path.is_empty() || vault::is_enabled() {
+            state.pending.lock().unwrap().clear();
+            *state.oldest.lock().unwrap() = None;
+            continue;
+        }
+        // Pending changes wait for the vault to be unlocked again
+        if oldest.elapsed() < Duration::from_secs(config.batch_seconds) || vault::is_locked() {
+            continue;
+        }
+
+        let pending = std::mem::take(&mut *state.pending.lock().unwrap());
+        *state.oldest.lock().unwrap() = None;
+        if let Err(e) = flush(&conn, &config, &pending) {
+            eprintln!("Git mirror commit failed: {}", e);
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_git_mirror_config_command() -> Result<GitMirrorConfig, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    load_config(&conn).map_err(|e| e.to_string())
+}
+
+// Saving an enabled config writes the whole vault and commits it right away
+#[tauri::command]
+pub fn set_git_mirror_config_command(config: GitMirrorConfig) -> Result<(), String> {
+    println!("set_git_mirror_config_command -> {:?}", config);
+    if config.enabled && vault::is_enabled() {
+        return Err(ENCRYPTION_CONFLICT.to_string());
+    }
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    set_json(&conn, CONFIG_KEY, &config).map_err(|e| e.to_string())?;
+    if config.enabled && !config.path.is_empty() {
+        flush(&conn, &config, &[PendingChange::Everything]).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn git_mirror_commit_now_command(state: State<GitMirrorState>) -> Result<(), String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let config = load_config(&conn).map_err(|e| e.to_string())?;
+    if !config.enabled || config.path.is_empty() {
+        return Err("Git mirror is not enabled".to_string());
+    }
+    let pending = std::mem::take(&mut *state.pending.lock().unwrap());
+    *state.oldest.lock().unwrap() = None;
+    flush(&conn, &config, &pending).map_err(|e| e.to_string())
+}
+
+fn parse_mirrored_file(path: &Path, time: i64) -> Result<EditorDocument, AppError> {
+    let text = fs::read_to_string(path)?;
+    if path.extension().and_then(|e| e.to_str()) == Some("json") {
+        return Ok(serde_json::from_str(&text)?);
+    }
+    // Strip the front matter written by `render`
+    let body = match text.strip_prefix("---\n").and_then(|rest| rest.split_once("\n---\n")) {
+        Some((_, body)) => body,
+        None => text.as_str(),
+    };
+    Ok(markdown::markdown_to_document(body, time))
+}
+
+// Markdown keeps no block ids, so a parsed block gets the id (and the data, which may
+// hold more than Markdown can express) of a stored block that renders to the same
+// Markdown. Deep links and tasks keep pointing at the blocks the edit didn't touch.
+fn keep_unchanged_blocks(stored: &EditorDocument, imported: EditorDocument) -> EditorDocument {
+    let mut unused: Vec<(String, &Block)> = stored
+        .blocks
+        .iter()
+        .map(|block| (markdown::block_to_markdown(block), block))
+        .collect();
+    let blocks = imported
+        .blocks
+        .into_iter()
+        .map(|block| {
+            let rendered = markdown::block_to_markdown(&block);
+            match unused.iter().position(|(md, old)| *md == rendered && old.r#type == block.r#type) {
+                Some(idx) => unused.remove(idx).1.clone(),
+                None => block,
+            }
+        })
+        .collect();
+    EditorDocument { blocks, ..imported }
+}
+
+fn find_or_create_folder(conn: &Connection, folders: &mut Vec<Folder>, parts: &[String]) -> Result<Option<i64>, AppError> {
+    let mut parent: Option<i64> = None;
+    for part in parts {
+        let existing = folders.iter().find(|f| f.parent_id == parent && sanitize(&f.name) == *part).map(|f| f.id);
+        parent = Some(match existing {
+            Some(id) => id,
+            None => {
+                let id = insert_new_folder(conn, part, parent)?;
+                record_change(conn, Entity::Folder, id, ChangeOp::Upsert)?;
+                folders.push(Folder { id, name: part.clone(), parent_id: parent, documents: Vec::new() });
+                id
+            }
+        });
+    }
+    Ok(parent)
+}
+
+// Reads the mirror's working tree back into the database: edited files update their
+// document, files without a known id (or new files) become new documents, and the
+// directory a file lives in decides its folder. Without an `app` (the command-line
+// import) no change events are sent; `remove_created` deletes files that became new
+// documents, since the mirror's next export writes them again under their id.
+pub fn import_tree(conn: &Connection, root: &Path, app: Option<&AppHandle>, remove_created: bool) -> Result<ImportReport, AppError> {
+    let mut folders = load_folders(conn)?;
+    let mut report = ImportReport { created: 0, updated: 0, unchanged: 0 };
+    let now = chrono::Utc::now().timestamp_millis();
+
+    for path in mirrored_files(root)? {
+        let editor_doc = match parse_mirrored_file(&path, now) {
+            Ok(doc) => doc,
+            Err(e) => {
+                eprintln!("Skipping {:?}: {}", path, e);
+                continue;
+            }
+        };
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let parts: Vec<String> = relative
+            .parent()
+            .map(|p| p.iter().map(|s| s.to_string_lossy().to_string()).collect())
+            .unwrap_or_default();
+        let folder_id = find_or_create_folder(conn, &mut folders, &parts)?;
+
+        let existing = file_document_id(&path).and_then(|id| load_document(conn, id).ok());
+        match existing {
+            Some(doc) => {
+                let format = if path.extension().and_then(|e| e.to_str()) == Some("json") {
+                    MirrorFormat::Json
+                } else {
+                    MirrorFormat::Markdown
+                };
+                // Files identical to what the export would write keep the stored blocks
+                // untouched, including their ids
+                let same_content = render(&doc, format).ok() == fs::read_to_string(&path).ok();
+                if same_content && doc.folder_id == folder_id {
+                    report.unchanged += 1;
+                    continue;
+                }
+                let content = if same_content {
+                    doc.content.clone()
+                } else {
+                    let merged = match serde_json::from_str::<EditorDocument>(&doc.content) {
+                        Ok(stored) => keep_unchanged_blocks(&stored, editor_doc),
+                        Err(_) => editor_doc,
+                    };
+                    serde_json::to_string(&merged)?
+                };
+                let updated = Document {
+                    id: doc.id,
+                    title: extract_title(&content).unwrap_or_else(|| doc.title.clone()),
+                    time: if same_content { doc.time.clone() } else { now.to_string() },
+                    content,
+                    folder_id,
+                };
+                update_document(conn, doc.id, &updated)?;
+                if folder_id.is_none() {
+                    conn.execute("UPDATE documents SET folder_id = NULL WHERE id = ?", [doc.id])?;
+                }
+                record_change(conn, Entity::Document, doc.id, ChangeOp::Upsert)?;
+                if let Some(app) = app {
+                    events::document_updated(app, events::DocumentUpdated {
+                        id: doc.id,
+                        title: updated.title,
+                        time: updated.time,
+                        folder_id,
+                        source_window: None,
+                    });
+                }
+                report.updated += 1;
+            }
+            None => {
+                let id = save_document_in(conn, &editor_doc, folder_id)?;
+                record_change(conn, Entity::Document, id, ChangeOp::Upsert)?;
+                let saved = load_document(conn, id)?;
+                if remove_created {
+                    fs::remove_file(&path)?;
+                }
+                if let Some(app) = app {
+                    events::document_created(app, events::DocumentCreated { id, title: saved.title, folder_id: saved.folder_id });
+                }
+                report.created += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[tauri::command]
+pub fn git_mirror_import_command(app: AppHandle) -> Result<ImportReport, String> {
+    println!("git_mirror_import_command");
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let config = load_config(&conn).map_err(|e| e.to_string())?;
+    if config.path.is_empty() {
+        return Err("Git mirror path is not configured".to_string());
+    }
+    import_tree(&conn, &PathBuf::from(&config.path), Some(&app), true).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vault() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn).unwrap();
+        crate::settings::create_tables(&conn).unwrap();
+        crate::sync::create_tables(&conn).unwrap();
+        conn
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jd-mirror-test-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn block(id: &str, r#type: &str, data: serde_json::Value) -> Block {
+        Block { id: id.to_string(), r#type: r#type.to_string(), data }
+    }
+
+    fn note(conn: &Connection, folder_id: i64, title: &str, text: &str) -> i64 {
+        let doc = EditorDocument {
+            time: 1,
+            blocks: vec![
+                block("title1", "header", json!({ "text": title, "level": 1 })),
+                block("body1", "Paragraph", json!({ "text": text })),
+            ],
+            version: "2.30.5".to_string(),
+        };
+        save_document_in(conn, &doc, Some(folder_id)).unwrap()
+    }
+
+    #[test]
+    fn only_marked_file_names_carry_an_id() {
+        assert_eq!(file_document_id(Path::new("Work/Plan.jd-12.md")), Some(12));
+        assert_eq!(file_document_id(Path::new("Plan.jd-7.json")), Some(7));
+        assert_eq!(file_document_id(Path::new("meeting-2024.md")), None);
+        assert_eq!(file_document_id(Path::new("Plan.jd-.md")), None);
+        assert_eq!(file_document_id(Path::new("Plan.jd-12a.md")), None);
+    }
+
+    #[test]
+    fn unchanged_blocks_keep_their_ids_and_data() {
+        let stored = EditorDocument {
+            time: 1,
+            blocks: vec![
+                block("keep-h", "header", json!({ "text": "Title", "level": 1 })),
+                block("edit-p", "Paragraph", json!({ "text": "old text" })),
+                block("keep-c", "checklist", json!({ "items": [{ "text": "task", "checked": false, "meta": 1 }] })),
+            ],
+            version: "2.30.5".to_string(),
+        };
+        let imported = markdown::markdown_to_document("# Title\n\nnew text\n\n- [ ] task\n", 2);
+        let merged = keep_unchanged_blocks(&stored, imported);
+        let ids: Vec<&str> = merged.blocks.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids[0], "keep-h");
+        assert_ne!(ids[1], "edit-p");
+        assert_eq!(ids[2], "keep-c");
+        assert_eq!(merged.blocks[2].data["items"][0]["meta"], 1);
+        assert_eq!(merged.time, 2);
+    }
+
+    #[test]
+    fn export_leaves_user_files_alone() {
+        let conn = vault();
+        let root = temp_dir();
+        let folder = insert_new_folder(&conn, "Work", None).unwrap();
+        let id = note(&conn, folder, "Plan", "first");
+        fs::write(root.join("meeting-2024.md"), "# Mine\n").unwrap();
+        fs::write(root.join(format!("notes-{}.md", id)), "# Also mine\n").unwrap();
+
+        export_tree(&conn, &root, MirrorFormat::Markdown).unwrap();
+        let exported = root.join("Work").join(format!("Plan.jd-{}.md", id));
+        assert!(exported.is_file());
+
+        conn.execute("DELETE FROM documents WHERE id = ?", [id]).unwrap();
+        export_tree(&conn, &root, MirrorFormat::Markdown).unwrap();
+        assert!(!exported.exists());
+        assert!(root.join("meeting-2024.md").is_file());
+        assert!(root.join(format!("notes-{}.md", id)).is_file());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn import_updates_marked_files_and_creates_the_rest() {
+        let conn = vault();
+        let root = temp_dir();
+        let folder = insert_new_folder(&conn, "Work", None).unwrap();
+        let id = note(&conn, folder, "Plan", "first");
+        export_tree(&conn, &root, MirrorFormat::Markdown).unwrap();
+
+        let exported = root.join("Work").join(format!("Plan.jd-{}.md", id));
+        let edited = fs::read_to_string(&exported).unwrap().replace("first", "second");
+        fs::write(&exported, edited).unwrap();
+        // Named like the old `<title>-<id>` scheme, but not one of ours
+        fs::write(root.join("Work").join(format!("meeting-{}.md", id)), "# Meeting\n\nagenda\n").unwrap();
+        fs::write(root.join("Inbox.md"), "# Inbox\n\nloose note\n").unwrap();
+
+        let report = import_tree(&conn, &root, None, false).unwrap();
+        assert_eq!((report.created, report.updated), (2, 1));
+        let loose = load_documents(&conn).unwrap().into_iter().find(|d| d.title == "Inbox").unwrap();
+        assert_eq!(loose.folder_id, None);
+        let doc: EditorDocument = serde_json::from_str(&load_document(&conn, id).unwrap().content).unwrap();
+        assert_eq!(doc.blocks[0].id, "title1");
+        assert_eq!(doc.blocks[1].data["text"], "second");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+// Everything the app and the `jd` command-line tool (src/bin/jd.rs) share; main.rs
+// only adds the window commands and starts Tauri.
+
+pub mod ai;
+pub mod api;
+pub mod backend;
+pub mod capture;
+pub mod chunking;
+pub mod classify;
+pub mod collab;
+pub mod collab_relay;
+pub mod crdt;
+pub mod credentials;
+pub mod crypto;
+pub mod db;
+pub mod deeplink;
+pub mod error;
+pub mod events;
+pub mod git_mirror;
+pub mod markdown;
+pub mod outbox;
+pub mod related;
+pub mod remote;
+pub mod search;
+pub mod settings;
+pub mod summaries;
+pub mod supervisor;
+pub mod sync;
+pub mod tasks;
+pub mod templates;
+pub mod vault;
+
+// const DB_PATH: &str = "../sqlite_database/documents.db";
+// const DB_PATH: &str = "/Users/tim/Documents/Programming/Projects/J.A.R.V.I.S./database/database.db";
+// const DB_PATH: &str = "/Users/tim/Documents/Programming/Projects/personal_assistant/knowledge-graph/pa_db.db";
+pub const DB_PATH: &str = "/Users/tim/Documents/Programming/Projects/personal_assistant/knowledge-graph/test_data.db";
@@ -0,0 +1,466 @@
+use serde_json::{json, Value};
+
+use crate::db::{Block, EditorDocument};
+
+// Conversion between Editor.js blocks and Markdown.
+//
+// Inline text in blocks is the HTML Editor.js produces (`<b>`, `<i>`, `<a href>`,
+// entities such as `&nbsp;`); it is mapped to the Markdown equivalents and back.
+
+pub fn decode_entities(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = tag[start..].find('"')? + start;
+    Some(tag[start..end].to_string())
+}
+
+// Targets of the `<a href>` links in inline HTML
+pub fn links(html: &str) -> Vec<String> {
+    html.split('<')
+        .skip(1)
+        .filter_map(|part| {
+            let tag = &part[..part.find('>')?];
+            let is_anchor = tag.split_whitespace().next().map_or(false, |name| name.eq_ignore_ascii_case("a"));
+            if is_anchor {
+                attribute(tag, "href").filter(|href| !href.is_empty())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// Inline HTML to Markdown. Unknown tags are dropped, their text is kept.
+pub fn inline_to_markdown(html: &str) -> String {
+    let mut out = String::new();
+    let mut links: Vec<String> = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&decode_entities(&rest[..start]));
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+        let tag = &rest[start + 1..end];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+        let closing = tag.starts_with('/');
+        match (name.as_str(), closing) {
+            ("b", _) | ("strong", _) => out.push_str("**"),
+            ("i", _) | ("em", _) => out.push('*'),
+            ("code", _) => out.push('`'),
+            ("s", _) | ("del", _) => out.push_str("~~"),
+            ("br", _) => out.push('\n'),
+            ("a", false) => {
+                links.push(attribute(tag, "href").map(|href| decode_entities(&href)).unwrap_or_default());
+                out.push('[');
+            }
+            ("a", true) => {
+                out.push_str(&format!("]({})", links.pop().unwrap_or_default()));
+            }
+            _ => {}
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(&decode_entities(rest));
+    out
+}
+
+// Plain text of inline HTML, used for titles, search and embeddings
+pub fn inline_to_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    decode_entities(&out).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Link targets imported Markdown may carry into the editor: web and mail links, links
+// between notes, and relative links without a scheme. Anything else (`javascript:`,
+// `data:`, ...) is dropped and only the link text kept.
+const LINK_SCHEMES: &[&str] = &["http:", "https:", "mailto:", "j_desktop:"];
+
+fn safe_href(href: &str) -> Option<String> {
+    let href = href.trim();
+    let lower = href.to_ascii_lowercase();
+    let allowed = !href.contains(':') || LINK_SCHEMES.iter().any(|scheme| lower.starts_with(scheme));
+    if !allowed || href.chars().any(char::is_control) {
+        return None;
+    }
+    Some(escape_html(href).replace('"', "&quot;"))
+}
+
+// Markdown inline syntax back to the HTML Editor.js expects
+pub fn markdown_to_inline(md: &str) -> String {
+    let mut out = String::new();
+    let chars: Vec<char> = md.chars().collect();
+    let mut bold = false;
+    let mut italic = false;
+    let mut code = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '`' {
+            out.push_str(if code { "</code>" } else { "<code>" });
+            code = !code;
+            i += 1;
+        } else if code {
+            out.push_str(&escape_html(&c.to_string()));
+            i += 1;
+        } else if c == '*' && chars.get(i + 1) == Some(&'*') {
+            out.push_str(if bold { "</b>" } else { "<b>" });
+            bold = !bold;
+            i += 2;
+        } else if c == '*' {
+            out.push_str(if italic { "</i>" } else { "<i>" });
+            italic = !italic;
+            i += 1;
+        } else if c == '[' {
+            // [text](href)
+            let rest: String = chars[i..].iter().collect();
+            let parsed = rest.find("](").and_then(|mid| {
+                let close = rest[mid..].find(')')? + mid;
+                Some((mid, close))
+            });
+            match parsed {
+                Some((mid, close)) => {
+                    let text = markdown_to_inline(&rest[1..mid]);
+                    match safe_href(&rest[mid + 2..close]) {
+                        Some(href) => out.push_str(&format!("<a href=\"{}\">{}</a>", href, text)),
+                        None => out.push_str(&text),
+                    }
+                    i += rest[..=close].chars().count();
+                }
+                None => {
+                    out.push('[');
+                    i += 1;
+                }
+            }
+        } else {
+            out.push_str(&escape_html(&c.to_string()));
+            i += 1;
+        }
+    }
+    out
+}
+
+fn list_items_to_markdown(items: &[Value], ordered: bool, depth: usize, out: &mut Vec<String>) {
+    for (idx, item) in items.iter().enumerate() {
+        let content = item
+            .get("content")
+            .and_then(Value::as_str)
+            .or_else(|| item.as_str())
+            .unwrap_or("");
+        let marker = if ordered { format!("{}.", idx + 1) } else { "-".to_string() };
+        out.push(format!("{}{} {}", "  ".repeat(depth), marker, inline_to_markdown(content).trim()));
+        if let Some(children) = item.get("items").and_then(Value::as_array) {
+            list_items_to_markdown(children, ordered, depth + 1, out);
+        }
+    }
+}
+
+fn flashcards_to_markdown(items: &[Value], depth: usize, out: &mut Vec<String>) {
+    for item in items {
+        let question = item.get("question").and_then(Value::as_str).unwrap_or("");
+        let answer = item.get("answer").and_then(Value::as_str).unwrap_or("");
+        out.push(format!(
+            "{}- {} >> {}",
+            "  ".repeat(depth),
+            inline_to_markdown(question).trim(),
+            inline_to_markdown(answer).trim()
+        ));
+        if let Some(children) = item.get("items").and_then(Value::as_array) {
+            flashcards_to_markdown(children, depth + 1, out);
+        }
+    }
+}
+
+pub fn block_to_markdown(block: &Block) -> String {
+    let data = &block.data;
+    let text = data.get("text").and_then(Value::as_str).unwrap_or("");
+    match block.r#type.as_str() {
+        "header" => {
+            let level = data.get("level").and_then(Value::as_u64).unwrap_or(1).clamp(1, 6) as usize;
+            format!("{} {}", "#".repeat(level), inline_to_markdown(text).trim())
+        }
+        "nestedList" | "list" => {
+            let ordered = data.get("style").and_then(Value::as_str) == Some("ordered");
+            let mut lines = Vec::new();
+            if let Some(items) = data.get("items").and_then(Value::as_array) {
+                list_items_to_markdown(items, ordered, 0, &mut lines);
+            }
+            lines.join("\n")
+        }
+        "checklist" => data
+            .get("items")
+            .and_then(Value::as_array)
+            .map(|items| {
+                items
+                    .iter()
+                    .map(|item| {
+                        let checked = item.get("checked").and_then(Value::as_bool).unwrap_or(false);
+                        let text = item.get("text").and_then(Value::as_str).unwrap_or("");
+                        format!("- [{}] {}", if checked { "x" } else { " " }, inline_to_markdown(text).trim())
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default(),
+        "flashcard" => {
+            let mut lines = Vec::new();
+            if let Some(items) = data.get("items").and_then(Value::as_array) {
+                flashcards_to_markdown(items, 0, &mut lines);
+            }
+            lines.join("\n")
+        }
+        _ => inline_to_markdown(text).trim().to_string(),
+    }
+}
+
+pub fn document_to_markdown(doc: &EditorDocument) -> String {
+    let mut out = doc
+        .blocks
+        .iter()
+        .map(block_to_markdown)
+        .filter(|md| !md.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    out.push('\n');
+    out
+}
+
+pub fn new_block_id() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789_-";
+    (0..10)
+        .map(|_| ALPHABET[rand::random::<usize>() % ALPHABET.len()] as char)
+        .collect()
+}
+
+fn block(r#type: &str, data: Value) -> Block {
+    Block { id: new_block_id(), r#type: r#type.to_string(), data }
+}
+
+// Parses `- item` lines (indented by two spaces per level) into nested items
+fn parse_list(lines: &[&str], depth: usize, idx: &mut usize, make: &dyn Fn(&str) -> Value) -> Vec<Value> {
+    let mut items: Vec<Value> = Vec::new();
+    while *idx < lines.len() {
+        let line = lines[*idx];
+        let indent = (line.len() - line.trim_start().len()) / 2;
+        if indent < depth {
+            break;
+        }
+        if indent > depth {
+            let children = parse_list(lines, depth + 1, idx, make);
+            if let Some(last) = items.last_mut() {
+                last["items"] = Value::Array(children);
+            }
+            continue;
+        }
+        items.push(make(list_item_text(line.trim_start())));
+        *idx += 1;
+    }
+    items
+}
+
+fn list_item_text(line: &str) -> &str {
+    if let Some(rest) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+        return rest;
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && line[digits..].starts_with(". ") {
+        return &line[digits + 2..];
+    }
+    line
+}
+
+fn is_list_line(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("- ") || trimmed.starts_with("* ") || {
+        let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+        digits > 0 && trimmed[digits..].starts_with(". ")
+    }
+}
+
+fn is_checklist_line(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("- [ ] ") || trimmed.starts_with("- [x] ") || trimmed.starts_with("- [X] ")
+}
+
+// Markdown back to Editor.js blocks, the inverse of `document_to_markdown`
+pub fn markdown_to_document(md: &str, time: i64) -> EditorDocument {
+    let lines: Vec<&str> = md.lines().collect();
+    let mut blocks = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut i = 0;
+
+    let flush = |paragraph: &mut Vec<&str>, blocks: &mut Vec<Block>| {
+        if !paragraph.is_empty() {
+            let text = paragraph.iter().map(|l| markdown_to_inline(l.trim())).collect::<Vec<_>>().join("<br>");
+            blocks.push(block("Paragraph", json!({ "text": text })));
+            paragraph.clear();
+        }
+    };
+
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            flush(&mut paragraph, &mut blocks);
+            i += 1;
+        } else if trimmed.starts_with('#') && trimmed.trim_start_matches('#').starts_with(' ') {
+            flush(&mut paragraph, &mut blocks);
+            let level = trimmed.chars().take_while(|&c| c == '#').count().min(6);
+            let text = markdown_to_inline(trimmed[level..].trim());
+            blocks.push(block("header", json!({ "text": text, "level": level })));
+            i += 1;
+        } else if trimmed.starts_with("```") {
+            // The editor has no code tool, fenced code becomes an inline-code paragraph
+            flush(&mut paragraph, &mut blocks);
+            let mut code = Vec::new();
+            i += 1;
+            while i < lines.len() && !lines[i].trim().starts_with("```") {
+                code.push(format!("<code>{}</code>", escape_html(lines[i])));
+                i += 1;
+            }
+            i += 1;
+            blocks.push(block("Paragraph", json!({ "text": code.join("<br>") })));
+        } else if trimmed == "---" {
+            flush(&mut paragraph, &mut blocks);
+            i += 1;
+        } else if is_checklist_line(line) {
+            flush(&mut paragraph, &mut blocks);
+            let mut items = Vec::new();
+            while i < lines.len() && is_checklist_line(lines[i]) {
+                let item = lines[i].trim_start();
+                items.push(json!({
+                    "text": markdown_to_inline(&item[6..]),
+                    "checked": !item.starts_with("- [ ]"),
+                }));
+                i += 1;
+            }
+            blocks.push(block("checklist", json!({ "items": items })));
+        } else if is_list_line(line) {
+            flush(&mut paragraph, &mut blocks);
+            let start = i;
+            while i < lines.len() && is_list_line(lines[i]) {
+                i += 1;
+            }
+            let list = &lines[start..i];
+            let ordered = !list[0].trim_start().starts_with('-') && !list[0].trim_start().starts_with('*');
+            let flashcards = list.iter().all(|l| l.contains(" >> "));
+            let mut idx = 0;
+            if flashcards {
+                let items = parse_list(list, 0, &mut idx, &|text| {
+                    let (question, answer) = text.split_once(" >> ").unwrap_or((text, ""));
+                    let question = markdown_to_inline(question.trim());
+                    let answer = markdown_to_inline(answer.trim());
+                    json!({
+                        "content": format!("{} &gt;&gt; {}", question, answer),
+                        "items": [],
+                        "question": question,
+                        "answer": answer,
+                    })
+                });
+                blocks.push(block("flashcard", json!({ "style": "unordered", "items": items })));
+            } else {
+                let items = parse_list(list, 0, &mut idx, &|text| json!({ "content": markdown_to_inline(text), "items": [] }));
+                let style = if ordered { "ordered" } else { "unordered" };
+                blocks.push(block("nestedList", json!({ "style": style, "items": items })));
+            }
+        } else {
+            paragraph.push(line);
+            i += 1;
+        }
+    }
+    flush(&mut paragraph, &mut blocks);
+
+    EditorDocument { time, blocks, version: "2.30.5".to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(blocks: Vec<(&str, Value)>) -> EditorDocument {
+        EditorDocument {
+            time: 1,
+            blocks: blocks.into_iter().map(|(r#type, data)| block(r#type, data)).collect(),
+            version: "2.30.5".to_string(),
+        }
+    }
+
+    #[test]
+    fn document_round_trips_through_markdown() {
+        let original = doc(vec![
+            ("header", json!({ "text": "Plan &amp; <b>notes</b>", "level": 2 })),
+            ("Paragraph", json!({ "text": "See <a href=\"https://example.com/?a=1&amp;b=2\">the <i>docs</i></a>" })),
+            ("checklist", json!({ "items": [
+                { "text": "write tests", "checked": true },
+                { "text": "ship @2024-05-17", "checked": false },
+            ] })),
+            ("nestedList", json!({ "style": "ordered", "items": [
+                { "content": "one", "items": [{ "content": "nested", "items": [] }] },
+                { "content": "two", "items": [] },
+            ] })),
+            ("flashcard", json!({ "style": "unordered", "items": [
+                { "content": "Q &gt;&gt; A", "items": [], "question": "Capital of France?", "answer": "Paris" },
+            ] })),
+        ]);
+        let md = document_to_markdown(&original);
+        let parsed = markdown_to_document(&md, 1);
+        assert_eq!(parsed.blocks.len(), original.blocks.len());
+        assert_eq!(document_to_markdown(&parsed), md);
+        assert_eq!(parsed.blocks[0].data, original.blocks[0].data);
+        assert_eq!(parsed.blocks[1].data, original.blocks[1].data);
+        assert_eq!(parsed.blocks[2].data, original.blocks[2].data);
+    }
+
+    #[test]
+    fn link_targets_are_escaped() {
+        let html = markdown_to_inline("[x](https://a.example/\"onmouseover=\"alert`1`<b>)");
+        assert_eq!(html, "<a href=\"https://a.example/&quot;onmouseover=&quot;alert`1`&lt;b&gt;\">x</a>");
+        assert_eq!(links(&html).len(), 1);
+    }
+
+    #[test]
+    fn unsafe_link_schemes_keep_only_the_text() {
+        assert_eq!(markdown_to_inline("[click](JavaScript:alert`1`)"), "click");
+        assert_eq!(markdown_to_inline("[img](data:text/html;base64,AAAA)"), "img");
+        assert_eq!(markdown_to_inline("[tab](java\tscript:alert`1`)"), "tab");
+        assert_eq!(markdown_to_inline("[mail](mailto:a@b.c)"), "<a href=\"mailto:a@b.c\">mail</a>");
+        assert_eq!(markdown_to_inline("[note](j_desktop://doc/3)"), "<a href=\"j_desktop://doc/3\">note</a>");
+        assert_eq!(markdown_to_inline("[rel](../other.md)"), "<a href=\"../other.md\">rel</a>");
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(markdown_to_inline("<script> & **bold**"), "&lt;script&gt; &amp; <b>bold</b>");
+        assert_eq!(inline_to_text("a&nbsp;<b>b</b>  c"), "a b c");
+    }
+}
@@ -0,0 +1,444 @@
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::error::AppError;
+use crate::{backend, events, vault, DB_PATH};
+
+// Persistent queue of operations for the Python RAG backend.
+//
+// Commands only enqueue; a worker thread sends due items and deletes them on
+// success. Failures are retried with exponential backoff and moved to `failed`
+// (the dead letter state) after `MAX_ATTEMPTS`, where they wait for a manual retry.
+// Items carry only the document id, so a retry always sends the current content.
+// Besides explicit creates, updates and deletes of documents the backend knows are
+// queued from the change events.
+
+const MAX_ATTEMPTS: i64 = 8;
+const BASE_DELAY_SECONDS: i64 = 10;
+const MAX_DELAY_SECONDS: i64 = 60 * 60;
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxOp {
+    CreateDocument,
+    UpdateDocument,
+    DeleteDocument,
+}
+
+impl OutboxOp {
+    fn as_str(self) -> &'static str {
+        match self {
+            OutboxOp::CreateDocument => "create_document",
+            OutboxOp::UpdateDocument => "update_document",
+            OutboxOp::DeleteDocument => "delete_document",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "create_document" => Some(OutboxOp::CreateDocument),
+            "update_document" => Some(OutboxOp::UpdateDocument),
+            "delete_document" => Some(OutboxOp::DeleteDocument),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OutboxItem {
+    pub id: i64,
+    pub op: String,
+    pub document_id: i64,
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OutboxStatus {
+    pub pending: usize,
+    pub failed: usize,
+    pub items: Vec<OutboxItem>,
+}
+
+// Managed Tauri state; wakes the worker when new work is queued
+#[derive(Default)]
+pub struct OutboxState {
+    wake: Mutex<bool>,
+    signal: Condvar,
+}
+
+impl OutboxState {
+    pub fn notify(&self) {
+        *self.wake.lock().unwrap() = true;
+        self.signal.notify_one();
+    }
+
+    fn wait(&self) {
+        let guard = self.wake.lock().unwrap();
+        let (mut woken, _) = self.signal.wait_timeout_while(guard, POLL_INTERVAL, |woken| !*woken).unwrap();
+        *woken = false;
+    }
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS backend_outbox (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            op TEXT NOT NULL,
+            document_id INTEGER NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL,
+            last_error TEXT,
+            created_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS backend_outbox_due ON backend_outbox (status, next_attempt_at)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn backoff(attempts: i64) -> i64 {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    BASE_DELAY_SECONDS.saturating_mul(2i64.pow(exponent)).min(MAX_DELAY_SECONDS)
+}
+
+// Queues `op` for a document, folding it into what is already waiting: a create
+// or update reads the content when it is sent, so one pending upsert per document
+// is enough, and a delete supersedes both.
+pub fn enqueue(conn: &Connection, op: OutboxOp, document_id: i64) -> Result<i64, AppError> {
+    let upserts = [OutboxOp::CreateDocument.as_str(), OutboxOp::UpdateDocument.as_str()];
+    let same_kind = if op == OutboxOp::DeleteDocument {
+        conn.execute(
+            "DELETE FROM backend_outbox WHERE document_id = ?1 AND status = 'pending' AND op IN (?2, ?3)",
+            params![document_id, upserts[0], upserts[1]],
+        )?;
+        [op.as_str(), op.as_str()]
+    } else {
+        upserts
+    };
+    let existing: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM backend_outbox WHERE document_id = ?1 AND status = 'pending' AND op IN (?2, ?3)",
+            params![document_id, same_kind[0], same_kind[1]],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(id) = existing {
+        conn.execute("UPDATE backend_outbox SET next_attempt_at = ? WHERE id = ?", params![now(), id])?;
+        return Ok(id);
+    }
+    conn.execute(
+        "INSERT INTO backend_outbox (op, document_id, next_attempt_at) VALUES (?1, ?2, ?3)",
+        params![op.as_str(), document_id, now()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn send(conn: &Connection, op: OutboxOp, document_id: i64) -> Result<(), AppError> {
+    match op {
+        OutboxOp::CreateDocument | OutboxOp::UpdateDocument => backend::upsert_document(conn, document_id),
+        OutboxOp::DeleteDocument => backend::delete_document(conn, document_id),
+    }
+}
+
+fn record_failure(conn: &Connection, id: i64, attempts: i64, error: &AppError) -> Result<(), AppError> {
+    let attempts = attempts + 1;
+    let status = if attempts >= MAX_ATTEMPTS { "failed" } else { "pending" };
+    conn.execute(
+        "UPDATE backend_outbox SET attempts = ?1, status = ?2, next_attempt_at = ?3, last_error = ?4 WHERE id = ?5",
+        params![attempts, status, now() + backoff(attempts), error.to_string(), id],
+    )?;
+    Ok(())
+}
+
+// Sends every due item once and returns how many were delivered
+fn process_due(conn: &Connection) -> Result<usize, AppError> {
+    let due = {
+        let mut stmt = conn.prepare(
+            "SELECT id, op, document_id, attempts FROM backend_outbox
+             WHERE status = 'pending' AND next_attempt_at <= ? ORDER BY id",
+        )?;
+        let rows = stmt
+            .query_map([now()], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        rows
+    };
+
+    let mut delivered = 0;
+    for (id, op, document_id, attempts) in due {
+        let result = match OutboxOp::parse(&op) {
+            Some(op) => send(conn, op, document_id),
+            None => Err(AppError::InvalidInput(format!("Unknown outbox operation {}", op))),
+        };
+        match result {
+            Ok(()) => {
+                conn.execute("DELETE FROM backend_outbox WHERE id = ?", [id])?;
+                delivered += 1;
+            }
+            Err(e) => {
+                eprintln!("Backend outbox item {} ({} for document {}) failed: {}", id, op, document_id, e);
+                record_failure(conn, id, attempts, &e)?;
+            }
+        }
+    }
+    Ok(delivered)
+}
+
+// Queues from a change event; only documents the backend already has or is about
+// to receive are followed
+fn follow(app: &AppHandle, op: OutboxOp, document_id: Option<i64>) {
+    let result = Connection::open(DB_PATH).map_err(AppError::from).and_then(|conn| {
+        let ids = match document_id {
+            Some(id) => vec![id],
+            None => backend::tracked_documents(&conn)?,
+        };
+        for id in ids {
+            if backend::is_tracked(&conn, id)? || has_pending(&conn, id)? {
+                enqueue(&conn, op, id)?;
+            }
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => app.state::<OutboxState>().notify(),
+        Err(e) => eprintln!("Failed to queue backend {}: {}", op.as_str(), e),
+    }
+}
+
+fn has_pending(conn: &Connection, document_id: i64) -> Result<bool, AppError> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM backend_outbox WHERE document_id = ? AND status = 'pending'",
+        [document_id],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+pub fn start(app: AppHandle) {
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_UPDATED, move |e: events::DocumentUpdated| {
+        follow(&handle, OutboxOp::UpdateDocument, Some(e.id))
+    });
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_DELETED, move |e: events::DocumentDeleted| {
+        follow(&handle, OutboxOp::DeleteDocument, Some(e.id))
+    });
+    // Moves can change the collection a document belongs to
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_MOVED, move |e: events::DocumentMoved| {
+        follow(&handle, OutboxOp::UpdateDocument, Some(e.id))
+    });
+    let handle = app.clone();
+    events::listen(&app, events::FOLDER_CHANGED, move |e: events::FolderChanged| {
+        if matches!(e.kind, events::FolderChangeKind::Deleted) {
+            follow(&handle, OutboxOp::UpdateDocument, None)
+        }
+    });
+    // Peer syncs change documents without per-document events; unchanged ones are
+    // skipped by the content hash when sent
+    let handle = app.clone();
+    events::listen(&app, events::VAULT_SYNCED, move |_: events::VaultSynced| {
+        follow(&handle, OutboxOp::UpdateDocument, None)
+    });
+
+    thread::spawn(move || loop {
+        // Documents cannot be read while the vault is locked; that is not a failure
+        if !vault::is_locked() {
+            let result = Connection::open(DB_PATH).map_err(AppError::from).and_then(|conn| process_due(&conn));
+            match result {
+                Ok(0) => {}
+                Ok(delivered) => println!("Backend outbox delivered {} items", delivered),
+                Err(e) => eprintln!("Backend outbox run failed: {}", e),
+            }
+        }
+        app.state::<OutboxState>().wait();
+    });
+}
+
+#[tauri::command]
+pub fn outbox_status_command() -> Result<OutboxStatus, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, op, document_id, status, attempts, next_attempt_at, last_error, created_at
+             FROM backend_outbox ORDER BY id",
+        )
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map([], |row| {
+            Ok(OutboxItem {
+                id: row.get(0)?,
+                op: row.get(1)?,
+                document_id: row.get(2)?,
+                status: row.get(3)?,
+                attempts: row.get(4)?,
+                next_attempt_at: row.get(5)?,
+                last_error: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<OutboxItem>, rusqlite::Error>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(OutboxStatus {
+        pending: items.iter().filter(|item| item.status == "pending").count(),
+        failed: items.iter().filter(|item| item.status == "failed").count(),
+        items,
+    })
+}
+
+// Makes failed items (or the single item `id`) due again with a fresh attempt budget
+#[tauri::command]
+pub fn retry_outbox_command(state: State<OutboxState>, id: Option<i64>) -> Result<usize, String> {
+    println!("retry_outbox_command -> id: {:?}", id);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let retried = match id {
+        Some(id) => conn.execute(
+            "UPDATE backend_outbox SET status = 'pending', attempts = 0, next_attempt_at = ?1 WHERE id = ?2",
+            params![now(), id],
+        ),
+        None => conn.execute(
+            "UPDATE backend_outbox SET status = 'pending', attempts = 0, next_attempt_at = ?1 WHERE status = 'failed'",
+            [now()],
+        ),
+    }
+    .map_err(|e| e.to_string())?;
+    state.notify();
+    Ok(retried)
+}
+
+#[tauri::command]
+pub fn discard_outbox_item_command(id: i64) -> Result<(), String> {
+    println!("discard_outbox_item_command -> id: {}", id);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM backend_outbox WHERE id = ?", [id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    fn items(conn: &Connection) -> Vec<(String, i64, String)> {
+        let mut stmt = conn.prepare("SELECT op, document_id, status FROM backend_outbox ORDER BY id").unwrap();
+        let items = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        items
+    }
+
+    fn item(op: &str, document_id: i64, status: &str) -> (String, i64, String) {
+        (op.to_string(), document_id, status.to_string())
+    }
+
+    fn delivery(conn: &Connection, id: i64) -> (i64, String, i64, Option<String>) {
+        conn.query_row(
+            "SELECT attempts, status, next_attempt_at, last_error FROM backend_outbox WHERE id = ?",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_from_ten_seconds_up_to_an_hour() {
+        let delays: Vec<i64> = (0..=10).map(backoff).collect();
+        assert_eq!(delays, vec![10, 10, 20, 40, 80, 160, 320, 640, 1280, 2560, 3600]);
+        assert_eq!(backoff(64), MAX_DELAY_SECONDS);
+        assert_eq!(backoff(i64::MAX), MAX_DELAY_SECONDS);
+    }
+
+    #[test]
+    fn repeated_operations_on_a_document_fold_into_one_item() {
+        let conn = queue();
+        let created = enqueue(&conn, OutboxOp::CreateDocument, 1).unwrap();
+        assert_eq!(enqueue(&conn, OutboxOp::UpdateDocument, 1).unwrap(), created);
+        assert_eq!(enqueue(&conn, OutboxOp::UpdateDocument, 1).unwrap(), created);
+        enqueue(&conn, OutboxOp::UpdateDocument, 2).unwrap();
+        assert_eq!(items(&conn), vec![item("create_document", 1, "pending"), item("update_document", 2, "pending")]);
+
+        // A delete replaces the waiting upsert, and further deletes fold into it
+        let deleted = enqueue(&conn, OutboxOp::DeleteDocument, 1).unwrap();
+        assert_eq!(enqueue(&conn, OutboxOp::DeleteDocument, 1).unwrap(), deleted);
+        assert_eq!(items(&conn), vec![item("update_document", 2, "pending"), item("delete_document", 1, "pending")]);
+    }
+
+    #[test]
+    fn failed_items_are_not_folded_into() {
+        let conn = queue();
+        let id = enqueue(&conn, OutboxOp::UpdateDocument, 1).unwrap();
+        conn.execute("UPDATE backend_outbox SET status = 'failed' WHERE id = ?", [id]).unwrap();
+
+        assert_ne!(enqueue(&conn, OutboxOp::UpdateDocument, 1).unwrap(), id);
+        enqueue(&conn, OutboxOp::DeleteDocument, 1).unwrap();
+        assert_eq!(items(&conn), vec![item("update_document", 1, "failed"), item("delete_document", 1, "pending")]);
+    }
+
+    #[test]
+    fn items_fail_for_good_after_max_attempts() {
+        let conn = queue();
+        let id = enqueue(&conn, OutboxOp::UpdateDocument, 1).unwrap();
+        let error = AppError::ServerError("backend down".to_string());
+
+        for attempt in 0..MAX_ATTEMPTS - 1 {
+            let before = now();
+            record_failure(&conn, id, attempt, &error).unwrap();
+            let (attempts, status, next_attempt_at, last_error) = delivery(&conn, id);
+            assert_eq!((attempts, status.as_str()), (attempt + 1, "pending"));
+            assert!(next_attempt_at >= before + backoff(attempt + 1));
+            assert_eq!(last_error.as_deref(), Some("Server error: backend down"));
+        }
+        record_failure(&conn, id, MAX_ATTEMPTS - 1, &error).unwrap();
+        assert_eq!(delivery(&conn, id).1, "failed");
+    }
+
+    #[test]
+    fn failed_sends_wait_for_their_backoff() {
+        let conn = queue();
+        conn.execute(
+            "INSERT INTO backend_outbox (op, document_id, next_attempt_at) VALUES ('reindex_everything', 1, ?)",
+            [now()],
+        )
+        .unwrap();
+        let id = conn.last_insert_rowid();
+
+        assert_eq!(process_due(&conn).unwrap(), 0);
+        let (attempts, status, next_attempt_at, last_error) = delivery(&conn, id);
+        assert_eq!((attempts, status.as_str()), (1, "pending"));
+        assert!(next_attempt_at > now());
+        assert!(last_error.unwrap().contains("Unknown outbox operation reindex_everything"));
+
+        // Not due yet, so a second run leaves it alone
+        assert_eq!(process_due(&conn).unwrap(), 0);
+        assert_eq!(delivery(&conn, id).0, 1);
+    }
+}
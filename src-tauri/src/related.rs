@@ -0,0 +1,455 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::AppHandle;
+
+use crate::ai;
+use crate::chunking::extract_tags;
+use crate::db::{load_documents, EditorDocument};
+use crate::error::AppError;
+use crate::markdown::{document_to_markdown, inline_to_text, links};
+use crate::{events, vault, DB_PATH};
+
+// "Related notes" for the open document, from four signals:
+//
+// - similarity of the documents' mean chunk embeddings
+// - shared `#tags`
+// - links between the two notes, or links to the same pages
+// - being edited during the same timer session
+//
+// Edits are logged from the change events since documents only keep their last
+// edit time. Results are cached per document. A change drops the cache of the
+// changed note and of every list that contains it; lists it might newly join are
+// refreshed after `CACHE_TTL_SECONDS`.
+
+const DEFAULT_LIMIT: usize = 10;
+// Entries kept per cached list; also the largest limit served
+const CACHE_SIZE: usize = 50;
+const CACHE_TTL_SECONDS: i64 = 3600;
+// Edits of the same document closer together than this are logged once
+const EDIT_LOG_GAP_MS: i64 = 60_000;
+const EDIT_LOG_RETENTION_MS: i64 = 365 * 24 * 3600 * 1000;
+const MIN_SCORE: f64 = 0.05;
+
+const WEIGHT_SEMANTIC: f64 = 0.5;
+const WEIGHT_TAGS: f64 = 0.2;
+const WEIGHT_LINKS: f64 = 0.2;
+const WEIGHT_SESSIONS: f64 = 0.1;
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RelatedSignals {
+    pub similarity: f32,
+    pub shared_tags: Vec<String>,
+    // One of the notes links to the other
+    pub linked: bool,
+    pub shared_links: usize,
+    pub shared_sessions: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RelatedDocument {
+    pub document_id: i64,
+    pub title: String,
+    pub score: f64,
+    pub signals: RelatedSignals,
+}
+
+struct Profile {
+    title: String,
+    tags: HashSet<String>,
+    links: HashSet<String>,
+    linked_ids: HashSet<i64>,
+    vector: Option<Vec<f32>>,
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS document_edits (
+            document_id INTEGER NOT NULL,
+            edited_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS document_edits_time ON document_edits (edited_at)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS related_cache (
+            document_id INTEGER PRIMARY KEY,
+            results TEXT NOT NULL,
+            computed_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn log_edit(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    let now = Utc::now().timestamp_millis();
+    let recent: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM document_edits WHERE document_id = ? AND edited_at > ?)",
+        params![document_id, now - EDIT_LOG_GAP_MS],
+        |row| row.get(0),
+    )?;
+    if !recent {
+        conn.execute(
+            "INSERT INTO document_edits (document_id, edited_at) VALUES (?, ?)",
+            params![document_id, now],
+        )?;
+        conn.execute("DELETE FROM document_edits WHERE edited_at < ?", [now - EDIT_LOG_RETENTION_MS])?;
+    }
+    Ok(())
+}
+
+// Drops the cached list of the document and every list it appears in
+fn invalidate(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    conn.execute("DELETE FROM related_cache WHERE document_id = ?", [document_id])?;
+    let mut stmt = conn.prepare("SELECT document_id, results FROM related_cache")?;
+    let cached = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    for (id, results) in cached {
+        let contains = vault::open_text(&results)
+            .ok()
+            .and_then(|json| serde_json::from_str::<Vec<RelatedDocument>>(&json).ok())
+            .map_or(true, |list| list.iter().any(|r| r.document_id == document_id));
+        if contains {
+            conn.execute("DELETE FROM related_cache WHERE document_id = ?", [id])?;
+        }
+    }
+    Ok(())
+}
+
+fn clear_cache(conn: &Connection) -> Result<(), AppError> {
+    conn.execute("DELETE FROM related_cache", [])?;
+    Ok(())
+}
+
+// Document id a link points at: `note-12.md` (backend), `Title-12.md` (git mirror)
+// or a `.../document/12` URL
+fn linked_document(href: &str) -> Option<i64> {
+    let path = href.split(['?', '#']).next()?.trim_end_matches('/');
+    let mut segments = path.rsplit('/');
+    let last = segments.next()?;
+    if let Some(name) = last.strip_suffix(".md") {
+        return name.rsplit('-').next()?.parse().ok();
+    }
+    match segments.next() {
+        Some("document") | Some("documents") => last.parse().ok(),
+        _ => None,
+    }
+}
+
+fn collect_links(value: &Value, out: &mut Vec<String>) {
+    match value {
+        Value::String(text) => out.extend(links(text)),
+        Value::Array(items) => items.iter().for_each(|item| collect_links(item, out)),
+        Value::Object(map) => map.values().for_each(|item| collect_links(item, out)),
+        _ => {}
+    }
+}
+
+// Mean of each document's chunk vectors in the current embedding model
+fn document_vectors(conn: &Connection) -> Result<HashMap<i64, Vec<f32>>, AppError> {
+    let model = ai::load_provider(conn)?.model_id();
+    let mut stmt = conn.prepare("SELECT document_id, vector FROM embedding_chunks WHERE model = ?")?;
+    let rows = stmt
+        .query_map([model], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    let mut sums: HashMap<i64, Vec<f32>> = HashMap::new();
+    for (id, blob) in rows {
+        let vector = ai::from_blob(&blob);
+        let sum = sums.entry(id).or_insert_with(|| vec![0.0; vector.len()]);
+        if sum.len() == vector.len() {
+            sum.iter_mut().zip(&vector).for_each(|(s, v)| *s += v);
+        }
+    }
+    sums.values_mut().for_each(|sum| ai::normalize(sum));
+    Ok(sums)
+}
+
+fn parse_time(value: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.timestamp_millis())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").map(|t| t.and_utc().timestamp_millis()))
+        .ok()
+}
+
+// For each document, the timer sessions (by id) during which it was edited
+fn session_edits(conn: &Connection) -> Result<HashMap<i64, HashSet<i64>>, AppError> {
+    let mut stmt = conn.prepare("SELECT id, start_time_work, stop_time_work, extended_stop_time FROM timer_sessions")?;
+    let sessions: Vec<(i64, i64, i64)> = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?
+        .into_iter()
+        .filter_map(|(id, start, stop, extended_stop)| {
+            let stop = extended_stop.as_deref().and_then(parse_time).or_else(|| parse_time(&stop))?;
+            Some((id, parse_time(&start)?, stop))
+        })
+        .collect();
+
+    let mut stmt = conn.prepare("SELECT document_id, edited_at FROM document_edits")?;
+    let edits = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    let mut by_document: HashMap<i64, HashSet<i64>> = HashMap::new();
+    for (document_id, edited_at) in edits {
+        for (session, start, stop) in &sessions {
+            if edited_at >= *start && edited_at <= *stop {
+                by_document.entry(document_id).or_default().insert(*session);
+            }
+        }
+    }
+    Ok(by_document)
+}
+
+fn jaccard<T: Eq + std::hash::Hash>(a: &HashSet<T>, b: &HashSet<T>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        0.0
+    } else {
+        a.intersection(b).count() as f64 / union as f64
+    }
+}
+
+pub fn compute_related(conn: &Connection, document_id: i64, limit: usize) -> Result<Vec<RelatedDocument>, AppError> {
+    let vectors = document_vectors(conn)?;
+    let mut profiles: HashMap<i64, Profile> = HashMap::new();
+    for doc in load_documents(conn)? {
+        let editor_doc: EditorDocument = match serde_json::from_str(&doc.content) {
+            Ok(editor_doc) => editor_doc,
+            Err(_) => continue,
+        };
+        let mut hrefs = Vec::new();
+        editor_doc.blocks.iter().for_each(|block| collect_links(&block.data, &mut hrefs));
+        let linked_ids = hrefs.iter().filter_map(|href| linked_document(href)).collect();
+        profiles.insert(
+            doc.id,
+            Profile {
+                title: inline_to_text(&doc.title),
+                tags: extract_tags(&document_to_markdown(&editor_doc)).into_iter().collect(),
+                links: hrefs.into_iter().filter(|href| linked_document(href).is_none()).collect(),
+                linked_ids,
+                vector: vectors.get(&doc.id).cloned(),
+            },
+        );
+    }
+    let source = match profiles.remove(&document_id) {
+        Some(source) => source,
+        None => return Ok(Vec::new()),
+    };
+    let sessions = session_edits(conn)?;
+    let empty = HashSet::new();
+    let source_sessions = sessions.get(&document_id).unwrap_or(&empty);
+
+    let mut related: Vec<RelatedDocument> = profiles
+        .into_iter()
+        .map(|(id, profile)| {
+            let similarity = match (&source.vector, &profile.vector) {
+                (Some(a), Some(b)) => ai::dot(a, b).max(0.0),
+                _ => 0.0,
+            };
+            let mut shared_tags: Vec<String> = source.tags.intersection(&profile.tags).cloned().collect();
+            shared_tags.sort();
+            let linked = source.linked_ids.contains(&id) || profile.linked_ids.contains(&document_id);
+            let shared_links = source.links.intersection(&profile.links).count();
+            let other_sessions = sessions.get(&id).unwrap_or(&empty);
+            let shared_sessions = source_sessions.intersection(other_sessions).count();
+
+            let link_score = if linked { 1.0 } else { jaccard(&source.links, &profile.links) };
+            let session_score = if source_sessions.is_empty() {
+                0.0
+            } else {
+                shared_sessions as f64 / source_sessions.len() as f64
+            };
+            let score = WEIGHT_SEMANTIC * similarity as f64
+                + WEIGHT_TAGS * jaccard(&source.tags, &profile.tags)
+                + WEIGHT_LINKS * link_score
+                + WEIGHT_SESSIONS * session_score;
+            RelatedDocument {
+                document_id: id,
+                title: profile.title,
+                score,
+                signals: RelatedSignals { similarity, shared_tags, linked, shared_links, shared_sessions },
+            }
+        })
+        .filter(|r| r.score >= MIN_SCORE)
+        .collect();
+    related.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    related.truncate(limit);
+    Ok(related)
+}
+
+pub fn related_documents(conn: &Connection, document_id: i64, limit: usize) -> Result<Vec<RelatedDocument>, AppError> {
+    let limit = limit.min(CACHE_SIZE);
+    let now = Utc::now().timestamp();
+    let cached: Option<(String, i64)> = conn
+        .query_row(
+            "SELECT results, computed_at FROM related_cache WHERE document_id = ?",
+            [document_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    if let Some((results, _)) = cached.filter(|(_, computed_at)| now - computed_at < CACHE_TTL_SECONDS) {
+        let results: Vec<RelatedDocument> = serde_json::from_str(&vault::open_text(&results)?)?;
+        return Ok(results.into_iter().take(limit).collect());
+    }
+    let results = compute_related(conn, document_id, CACHE_SIZE)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO related_cache (document_id, results, computed_at) VALUES (?, ?, ?)",
+        params![document_id, vault::seal_text(&serde_json::to_string(&results)?)?, now],
+    )?;
+    Ok(results.into_iter().take(limit).collect())
+}
+
+fn on_change(document_id: Option<i64>, edited: bool) {
+    let result = Connection::open(DB_PATH).map_err(AppError::from).and_then(|conn| {
+        match document_id {
+            Some(id) => {
+                if edited {
+                    log_edit(&conn, id)?;
+                }
+                invalidate(&conn, id)
+            }
+            None => clear_cache(&conn),
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to update related notes cache: {}", e);
+    }
+}
+
+pub fn start(app: AppHandle) {
+    events::listen(&app, events::DOCUMENT_CREATED, |e: events::DocumentCreated| on_change(Some(e.id), true));
+    events::listen(&app, events::DOCUMENT_UPDATED, |e: events::DocumentUpdated| {
+        // Updates without a window (generated titles, imports, peers) are not edits made here
+        on_change(Some(e.id), e.source_window.is_some())
+    });
+    events::listen(&app, events::DOCUMENT_MOVED, |e: events::DocumentMoved| on_change(Some(e.id), false));
+    events::listen(&app, events::DOCUMENT_DELETED, |e: events::DocumentDeleted| {
+        let result = Connection::open(DB_PATH).map_err(AppError::from).and_then(|conn| {
+            conn.execute("DELETE FROM document_edits WHERE document_id = ?", [e.id])?;
+            invalidate(&conn, e.id)
+        });
+        if let Err(err) = result {
+            eprintln!("Failed to update related notes cache: {}", err);
+        }
+    });
+    events::listen(&app, events::TIMER_SESSION_SAVED, |_: events::TimerSessionSaved| on_change(None, false));
+    events::listen(&app, events::VAULT_SYNCED, |_: events::VaultSynced| on_change(None, false));
+}
+
+#[tauri::command]
+pub async fn related_documents_command(id: i64, limit: Option<usize>) -> Result<Vec<RelatedDocument>, String> {
+    println!("related_documents_command -> id: {}", id);
+    tauri::async_runtime::spawn_blocking(move || {
+        vault::guard()?;
+        let conn = Connection::open(DB_PATH)?;
+        related_documents(&conn, id, limit.unwrap_or(DEFAULT_LIMIT))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{save_document_in, Block};
+    use serde_json::json;
+
+    fn note(conn: &Connection, text: &str) -> i64 {
+        let doc = EditorDocument {
+            time: 1,
+            blocks: vec![Block { id: "p".to_string(), r#type: "paragraph".to_string(), data: json!({ "text": text }) }],
+            version: "2.30.5".to_string(),
+        };
+        save_document_in(conn, &doc, None).unwrap()
+    }
+
+    fn edit(conn: &Connection, document_id: i64, at: &str) {
+        conn.execute(
+            "INSERT INTO document_edits (document_id, edited_at) VALUES (?, ?)",
+            params![document_id, parse_time(at).unwrap()],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn links_resolve_to_document_ids() {
+        assert_eq!(linked_document("note-12.md"), Some(12));
+        assert_eq!(linked_document("notes/Meeting-notes-34.md#blk-1"), Some(34));
+        assert_eq!(linked_document("http://localhost:1420/document/5/"), Some(5));
+        assert_eq!(linked_document("/documents/7?tab=1"), Some(7));
+        assert_eq!(linked_document("notes.md"), None);
+        assert_eq!(linked_document("https://example.com/page/5"), None);
+        assert_eq!(linked_document("https://example.com/document/latest"), None);
+    }
+
+    #[test]
+    fn session_times_parse_with_or_without_offset() {
+        assert_eq!(parse_time("2024-05-01T10:00:00Z"), Some(1714557600000));
+        assert_eq!(parse_time("2024-05-01T12:00:00+02:00"), Some(1714557600000));
+        assert_eq!(parse_time("2024-05-01T10:00:00.250"), Some(1714557600250));
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn related_notes_combine_tags_links_and_sessions() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn).unwrap();
+        crate::ai::create_tables(&conn).unwrap();
+        crate::settings::create_tables(&conn).unwrap();
+        create_tables(&conn).unwrap();
+
+        let linked = note(&conn, "Plain note");
+        let source = note(
+            &conn,
+            &format!(r#"Learning #rust and #web, see <a href="note-{}.md">this</a> and <a href="https://example.com">that</a>"#, linked),
+        );
+        let similar = note(&conn, r#"More #rust from <a href="https://example.com">the same page</a>"#);
+        let unrelated = note(&conn, "Nothing in common #cooking");
+
+        conn.execute(
+            "INSERT INTO timer_sessions (work_duration, break_duration, start_time_work, stop_time_work, extended)
+             VALUES (25, 5, '2024-05-01T10:00:00Z', '2024-05-01T10:25:00.000', 0)",
+            [],
+        )
+        .unwrap();
+        edit(&conn, source, "2024-05-01T10:05:00Z");
+        edit(&conn, similar, "2024-05-01T10:20:00Z");
+        edit(&conn, unrelated, "2024-05-01T11:00:00Z");
+
+        let related = compute_related(&conn, source, 10).unwrap();
+        let ids: Vec<i64> = related.iter().map(|r| r.document_id).collect();
+        assert_eq!(ids, vec![similar, linked]);
+
+        // Half the tags, the same external link and the only session
+        let first = &related[0];
+        assert!((first.score - (WEIGHT_TAGS * 0.5 + WEIGHT_LINKS + WEIGHT_SESSIONS)).abs() < 1e-9);
+        assert_eq!(first.signals.shared_tags, vec!["rust"]);
+        assert_eq!((first.signals.linked, first.signals.shared_links, first.signals.shared_sessions), (false, 1, 1));
+
+        let second = &related[1];
+        assert!((second.score - WEIGHT_LINKS).abs() < 1e-9);
+        assert!(second.signals.linked && second.signals.shared_tags.is_empty());
+
+        // The link counts from the other side too
+        let from_linked = compute_related(&conn, linked, 10).unwrap();
+        assert_eq!(from_linked.iter().map(|r| r.document_id).collect::<Vec<_>>(), vec![source]);
+
+        assert_eq!(compute_related(&conn, source, 1).unwrap().len(), 1);
+        assert!(compute_related(&conn, 999, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,661 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::ai::{self, EmbeddingProvider};
+use crate::chunking::chunk_document;
+use crate::db::{load_document, EditorDocument};
+use crate::error::AppError;
+use crate::markdown::inline_to_text;
+use crate::{backend, vault, DB_PATH};
+
+// Hybrid search: BM25 keyword ranking from a full-text index and embedding
+// similarity from the embedding index, fused per document with reciprocal rank fusion.
+//
+// The keyword side has an FTS5 index of its own, `search_chunks`, so it works
+// without any embeddings. Documents are chunked as for the embeddings and each chunk
+// is indexed as its lowercased words. Like the task index it is brought up to date
+// before every search by comparing document times, which picks up edits from peers,
+// imports and the command-line tool. In an encrypted vault the indexed words are
+// keyed digests (`vault::search_token`) and chunk texts and headings are sealed, so
+// the index holds no note text in plaintext while FTS5 still sees the term
+// frequencies BM25 needs. Only the chunks that end up in the results are decrypted.
+
+// RRF damping constant from the original paper; keeps a single first place from
+// outweighing consistent mid-table ranks
+const RRF_K: f64 = 60.0;
+// Below this cosine similarity a chunk does not count as a semantic match
+const MIN_SIMILARITY: f32 = 0.2;
+const SNIPPET_CHARS: usize = 240;
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SearchFilters {
+    // Includes subfolders
+    pub folder_id: Option<i64>,
+    // Documents must carry all of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Bounds on the document time, in milliseconds like `EditorDocument::time`
+    pub modified_after: Option<i64>,
+    pub modified_before: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct MatchExplanation {
+    pub keyword_rank: Option<usize>,
+    pub matched_terms: Vec<String>,
+    pub semantic_rank: Option<usize>,
+    pub similarity: Option<f32>,
+    // Human readable reasons, e.g. for a tooltip
+    pub reasons: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResult {
+    pub document_id: i64,
+    pub title: String,
+    pub folder_path: String,
+    pub score: f64,
+    // Blocks of the best matching chunk, for jumping to the section
+    pub block_ids: Vec<String>,
+    pub headings: Vec<String>,
+    pub snippet: String,
+    // Full Markdown of that chunk
+    pub text: String,
+    pub explanation: MatchExplanation,
+}
+
+// Best chunk of a document in one of the two rankings
+struct Hit {
+    document_id: i64,
+    // Row of `search_chunks` or `embedding_chunks`
+    chunk: i64,
+    score: f64,
+}
+
+// A document's place in the fused ranking
+#[derive(Default)]
+struct Fused {
+    score: f64,
+    explanation: MatchExplanation,
+    keyword_chunk: Option<i64>,
+    semantic_chunk: Option<i64>,
+}
+
+struct Section {
+    block_ids: Vec<String>,
+    headings: Vec<String>,
+    text: String,
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    // Only `terms` is tokenized; the words are split and lowercased before indexing
+    conn.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS search_chunks USING fts5(
+            terms,
+            document_id UNINDEXED,
+            chunk_index UNINDEXED,
+            block_ids UNINDEXED,
+            headings UNINDEXED,
+            text UNINDEXED,
+            tokenize = 'unicode61 remove_diacritics 0'
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS search_tags (
+            document_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (document_id, tag)
+        )",
+        [],
+    )?;
+    // Document time each document was indexed at
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS search_sources (
+            document_id INTEGER PRIMARY KEY,
+            time TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+// Drops the whole keyword index; the next search builds it again
+pub fn clear_index(conn: &Connection) -> Result<(), AppError> {
+    conn.execute("DELETE FROM search_chunks", [])?;
+    conn.execute("DELETE FROM search_tags", [])?;
+    conn.execute("DELETE FROM search_sources", [])?;
+    Ok(())
+}
+
+fn remove_document(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    conn.execute("DELETE FROM search_chunks WHERE document_id = ?", [document_id])?;
+    conn.execute("DELETE FROM search_tags WHERE document_id = ?", [document_id])?;
+    conn.execute("DELETE FROM search_sources WHERE document_id = ?", [document_id])?;
+    Ok(())
+}
+
+fn index_terms(text: &str) -> Result<String, AppError> {
+    Ok(ai::words(text)
+        .map(|word| vault::search_token(&word))
+        .collect::<Result<Vec<_>, AppError>>()?
+        .join(" "))
+}
+
+fn index_document(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    remove_document(conn, document_id)?;
+    let doc = match load_document(conn, document_id) {
+        Ok(doc) => doc,
+        Err(AppError::SqliteError(rusqlite::Error::QueryReturnedNoRows)) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    // Unreadable content has nothing to find; its time is still recorded so it isn't retried
+    let editor_doc: Option<EditorDocument> = serde_json::from_str(&doc.content).ok();
+    let chunks = editor_doc.map(|d| chunk_document(&d, document_id, "")).unwrap_or_default();
+
+    let title = inline_to_text(&doc.title);
+    let mut tags: HashSet<String> = HashSet::new();
+    for chunk in chunks {
+        tags.extend(chunk.metadata.tags.iter().cloned());
+        conn.execute(
+            "INSERT INTO search_chunks (terms, document_id, chunk_index, block_ids, headings, text)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                index_terms(&format!("{}\n{}", title, chunk.text))?,
+                document_id,
+                chunk.metadata.chunk_index,
+                serde_json::to_string(&chunk.metadata.block_ids)?,
+                vault::seal_text(&serde_json::to_string(&chunk.metadata.headings)?)?,
+                vault::seal_text(&chunk.text)?,
+            ],
+        )?;
+    }
+    for tag in tags {
+        conn.execute(
+            "INSERT OR IGNORE INTO search_tags (document_id, tag) VALUES (?, ?)",
+            params![document_id, vault::search_token(&tag)?],
+        )?;
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO search_sources (document_id, time) VALUES (?, ?)",
+        params![document_id, doc.time],
+    )?;
+    Ok(())
+}
+
+// Reindexes documents whose time changed and drops deleted ones
+fn refresh(conn: &Connection) -> Result<(), AppError> {
+    let indexed: HashMap<i64, String> = {
+        let mut stmt = conn.prepare("SELECT document_id, time FROM search_sources")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<_, _>, rusqlite::Error>>()?;
+        rows
+    };
+    let current: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare("SELECT id, time FROM documents")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        rows
+    };
+
+    let tx = conn.unchecked_transaction()?;
+    let mut changed = 0;
+    for (id, time) in &current {
+        if indexed.get(id) != Some(time) {
+            index_document(&tx, *id)?;
+            changed += 1;
+        }
+    }
+    let existing: HashSet<i64> = current.iter().map(|(id, _)| *id).collect();
+    for id in indexed.keys().filter(|id| !existing.contains(id)) {
+        remove_document(&tx, *id)?;
+    }
+    tx.commit()?;
+    if changed > 0 {
+        eprintln!("Indexed {} documents for search", changed);
+    }
+    Ok(())
+}
+
+// Ids of `folder_id` and all folders below it
+pub fn folder_subtree(conn: &Connection, folder_id: i64) -> Result<HashSet<i64>, AppError> {
+    let mut stmt = conn.prepare("SELECT id, parent_id FROM folders")?;
+    let folders = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?)))?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    let mut subtree = HashSet::from([folder_id]);
+    loop {
+        let before = subtree.len();
+        for (id, parent) in &folders {
+            if parent.map_or(false, |p| subtree.contains(&p)) {
+                subtree.insert(*id);
+            }
+        }
+        if subtree.len() == before {
+            return Ok(subtree);
+        }
+    }
+}
+
+// Documents that pass the filters, or None without filters
+fn filtered_documents(conn: &Connection, filters: &SearchFilters) -> Result<Option<HashSet<i64>>, AppError> {
+    if filters.folder_id.is_none()
+        && filters.tags.is_empty()
+        && filters.modified_after.is_none()
+        && filters.modified_before.is_none()
+    {
+        return Ok(None);
+    }
+    let folders = filters.folder_id.map(|id| folder_subtree(conn, id)).transpose()?;
+    let mut stmt = conn.prepare("SELECT id, time, folder_id FROM documents")?;
+    let documents = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<i64>>(2)?))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+    let mut allowed = HashSet::new();
+    for (id, time, folder_id) in documents {
+        let time = time.parse::<i64>().unwrap_or_default();
+        if filters.modified_after.map_or(false, |after| time < after)
+            || filters.modified_before.map_or(false, |before| time > before)
+        {
+            continue;
+        }
+        if let Some(folders) = &folders {
+            if !folder_id.map_or(false, |id| folders.contains(&id)) {
+                continue;
+            }
+        }
+        allowed.insert(id);
+    }
+
+    let mut wanted: Vec<String> = filters.tags.iter().map(|t| t.trim_start_matches('#').to_lowercase()).collect();
+    wanted.sort();
+    wanted.dedup();
+    for tag in wanted {
+        let mut stmt = conn.prepare("SELECT document_id FROM search_tags WHERE tag = ?")?;
+        let tagged = stmt
+            .query_map([vault::search_token(&tag)?], |row| row.get::<_, i64>(0))?
+            .collect::<Result<HashSet<_>, rusqlite::Error>>()?;
+        allowed.retain(|id| tagged.contains(id));
+    }
+    Ok(Some(allowed))
+}
+
+// Best chunk of every document that contains a query term, by BM25
+fn keyword_hits(conn: &Connection, terms: &[String], allowed: Option<&HashSet<i64>>) -> Result<Vec<Hit>, AppError> {
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query = terms
+        .iter()
+        .map(|term| Ok(format!("\"{}\"", vault::search_token(term)?.replace('"', "\"\""))))
+        .collect::<Result<Vec<_>, AppError>>()?
+        .join(" OR ");
+    // FTS5's bm25() is lower for better matches
+    let mut stmt = conn.prepare(
+        "SELECT document_id, rowid, -bm25(search_chunks) FROM search_chunks
+         WHERE search_chunks MATCH ? ORDER BY bm25(search_chunks)",
+    )?;
+    let rows = stmt
+        .query_map([query], |row| Ok(Hit { document_id: row.get(0)?, chunk: row.get(1)?, score: row.get(2)? }))?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+    let mut seen = HashSet::new();
+    Ok(rows
+        .into_iter()
+        .filter(|hit| allowed.map_or(true, |a| a.contains(&hit.document_id)) && seen.insert(hit.document_id))
+        .collect())
+}
+
+// Best chunk of every document by cosine similarity to the query
+fn semantic_hits(
+    conn: &Connection,
+    provider: &dyn EmbeddingProvider,
+    query: &str,
+    allowed: Option<&HashSet<i64>>,
+) -> Result<Vec<Hit>, AppError> {
+    // A provider that is down degrades the search to keywords instead of failing it
+    let mut query_vector = match provider.embed(&[query.to_string()]) {
+        Ok(mut vectors) if !vectors.is_empty() => vectors.remove(0),
+        Ok(_) => return Ok(Vec::new()),
+        Err(e) => {
+            eprintln!("Semantic part of search unavailable: {}", e);
+            return Ok(Vec::new());
+        }
+    };
+    ai::normalize(&mut query_vector);
+
+    // Vectors of another model live in a different space
+    let mut stmt = conn.prepare("SELECT document_id, id, vector FROM embedding_chunks WHERE model = ?")?;
+    let rows = stmt
+        .query_map([provider.model_id()], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, Vec<u8>>(2)?))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+    let mut best: HashMap<i64, Hit> = HashMap::new();
+    for (document_id, chunk, vector) in rows {
+        if !allowed.map_or(true, |a| a.contains(&document_id)) {
+            continue;
+        }
+        let score = ai::dot(&query_vector, &ai::from_blob(&vector)) as f64;
+        if score < MIN_SIMILARITY as f64 || best.get(&document_id).map_or(false, |hit| hit.score >= score) {
+            continue;
+        }
+        best.insert(document_id, Hit { document_id, chunk, score });
+    }
+    let mut hits: Vec<Hit> = best.into_values().collect();
+    hits.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(hits)
+}
+
+// Both chunk tables have the same columns for this
+fn load_section(conn: &Connection, table: &str, chunk: i64) -> Result<Section, AppError> {
+    let (block_ids, headings, text): (String, String, String) = conn.query_row(
+        &format!("SELECT block_ids, headings, text FROM {} WHERE rowid = ?", table),
+        [chunk],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    Ok(Section {
+        block_ids: serde_json::from_str(&block_ids)?,
+        headings: serde_json::from_str(&vault::open_text(&headings)?)?,
+        text: vault::open_text(&text)?,
+    })
+}
+
+fn snippet(text: &str, terms: &[String]) -> String {
+    let lower = text.to_lowercase();
+    let start = terms
+        .iter()
+        .filter_map(|term| lower.find(term.as_str()))
+        .min()
+        .map_or(0, |pos| pos.saturating_sub(SNIPPET_CHARS / 4));
+    // `lower` can differ in byte length from `text`; fall back to the start
+    let start = if text.is_char_boundary(start) { start } else { 0 };
+    let snippet: String = text[start..].chars().take(SNIPPET_CHARS).collect();
+    if start > 0 {
+        format!("…{}", snippet.trim_start())
+    } else {
+        snippet
+    }
+}
+
+pub fn hybrid_search(
+    conn: &Connection,
+    provider: &dyn EmbeddingProvider,
+    query: &str,
+    filters: &SearchFilters,
+    limit: usize,
+) -> Result<Vec<SearchResult>, AppError> {
+    refresh(conn)?;
+    let allowed = filtered_documents(conn, filters)?;
+    let mut terms: Vec<String> = ai::words(query).collect();
+    terms.sort();
+    terms.dedup();
+
+    let keyword = keyword_hits(conn, &terms, allowed.as_ref())?;
+    let semantic = semantic_hits(conn, provider, query, allowed.as_ref())?;
+
+    // Keyword chunks are preferred for display since they contain the query terms
+    let mut fused: HashMap<i64, Fused> = HashMap::new();
+    for (rank, hit) in keyword.iter().enumerate() {
+        let entry = fused.entry(hit.document_id).or_default();
+        entry.score += 1.0 / (RRF_K + rank as f64 + 1.0);
+        entry.explanation.keyword_rank = Some(rank + 1);
+        entry.keyword_chunk = Some(hit.chunk);
+    }
+    for (rank, hit) in semantic.iter().enumerate() {
+        let entry = fused.entry(hit.document_id).or_default();
+        entry.score += 1.0 / (RRF_K + rank as f64 + 1.0);
+        entry.explanation.semantic_rank = Some(rank + 1);
+        entry.explanation.similarity = Some(hit.score as f32);
+        entry.semantic_chunk = Some(hit.chunk);
+    }
+
+    let mut results: Vec<(i64, Fused)> = fused.into_iter().collect();
+    results.sort_by(|a, b| b.1.score.partial_cmp(&a.1.score).unwrap_or(std::cmp::Ordering::Equal));
+    results.truncate(limit);
+
+    results
+        .into_iter()
+        .map(|(document_id, Fused { score, mut explanation, keyword_chunk, semantic_chunk })| {
+            let (title, folder_id): (String, Option<i64>) = conn
+                .query_row("SELECT title, folder_id FROM documents WHERE id = ?", [document_id], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .optional()?
+                .unwrap_or_default();
+            let title = inline_to_text(&title);
+            let semantic_section = semantic_chunk.map(|chunk| load_section(conn, "embedding_chunks", chunk)).transpose()?;
+            let section = match (keyword_chunk, semantic_section) {
+                (Some(chunk), semantic_section) => {
+                    let section = load_section(conn, "search_chunks", chunk)?;
+                    let words: HashSet<String> = ai::words(&format!("{}\n{}", title, section.text)).collect();
+                    explanation.matched_terms = terms.iter().filter(|t| words.contains(*t)).cloned().collect();
+                    explanation.reasons.push(format!(
+                        "Contains {}",
+                        explanation.matched_terms.iter().map(|t| format!("\"{}\"", t)).collect::<Vec<_>>().join(", ")
+                    ));
+                    if let Some(semantic_section) = &semantic_section {
+                        explanation.reasons.push(similarity_reason(explanation.similarity, semantic_section));
+                    }
+                    section
+                }
+                (None, Some(section)) => {
+                    explanation.reasons.push(similarity_reason(explanation.similarity, &section));
+                    section
+                }
+                (None, None) => unreachable!("every fused document has a hit"),
+            };
+            Ok(SearchResult {
+                document_id,
+                title,
+                folder_path: backend::folder_path(conn, folder_id)?,
+                score,
+                block_ids: section.block_ids,
+                headings: section.headings,
+                snippet: snippet(&section.text, &explanation.matched_terms),
+                text: section.text,
+                explanation,
+            })
+        })
+        .collect()
+}
+
+fn similarity_reason(similarity: Option<f32>, section: &Section) -> String {
+    let similarity = similarity.unwrap_or_default();
+    if section.headings.is_empty() {
+        format!("Similar in meaning ({:.2})", similarity)
+    } else {
+        format!("Similar in meaning ({:.2}) in \"{}\"", similarity, section.headings.join(" › "))
+    }
+}
+
+#[tauri::command]
+pub async fn search_command(
+    query: String,
+    filters: Option<SearchFilters>,
+    limit: Option<usize>,
+) -> Result<Vec<SearchResult>, String> {
+    println!("search_command -> filters: {:?}", filters);
+    tauri::async_runtime::spawn_blocking(move || {
+        vault::guard()?;
+        let conn = Connection::open(DB_PATH)?;
+        let provider = ai::load_provider(&conn)?;
+        hybrid_search(&conn, provider.as_ref(), &query, &filters.unwrap_or_default(), limit.unwrap_or(20))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{delete_document, insert_new_folder, save_document, update_document, Block, Document};
+    use serde_json::json;
+
+    // Embeds into two dimensions: fruit words and everything else
+    struct FruitProvider;
+
+    impl EmbeddingProvider for FruitProvider {
+        fn model_id(&self) -> String {
+            "test:fruit".to_string()
+        }
+
+        fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, AppError> {
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    let (fruit, other) = ai::words(text).fold((0.0, 0.0), |(fruit, other), word| {
+                        if ["apple", "fruit", "orchard"].contains(&word.as_str()) {
+                            (fruit + 1.0, other)
+                        } else {
+                            (fruit, other + 1.0)
+                        }
+                    });
+                    vec![fruit, other]
+                })
+                .collect())
+        }
+    }
+
+    struct OfflineProvider;
+
+    impl EmbeddingProvider for OfflineProvider {
+        fn model_id(&self) -> String {
+            "test:offline".to_string()
+        }
+
+        fn embed(&self, _: &[String]) -> Result<Vec<Vec<f32>>, AppError> {
+            Err(AppError::AiError("offline".to_string()))
+        }
+    }
+
+    fn vault() -> (Connection, i64) {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn).unwrap();
+        crate::settings::create_tables(&conn).unwrap();
+        ai::create_tables(&conn).unwrap();
+        create_tables(&conn).unwrap();
+        let folder = insert_new_folder(&conn, "Notes", None).unwrap();
+        (conn, folder)
+    }
+
+    fn editor_doc(time: i64, title: &str, text: &str) -> EditorDocument {
+        EditorDocument {
+            time,
+            blocks: vec![
+                Block { id: "h".to_string(), r#type: "header".to_string(), data: json!({ "text": title, "level": 1 }) },
+                Block { id: "p".to_string(), r#type: "Paragraph".to_string(), data: json!({ "text": text }) },
+            ],
+            version: "2.30.5".to_string(),
+        }
+    }
+
+    fn note(conn: &Connection, folder_id: i64, title: &str, text: &str) -> i64 {
+        save_document(conn, &editor_doc(1, title, text), &folder_id).unwrap()
+    }
+
+    fn search(conn: &Connection, provider: &dyn EmbeddingProvider, query: &str, filters: &SearchFilters) -> Vec<SearchResult> {
+        hybrid_search(conn, provider, query, filters, 10).unwrap()
+    }
+
+    fn ids(results: &[SearchResult]) -> Vec<i64> {
+        results.iter().map(|r| r.document_id).collect()
+    }
+
+    #[test]
+    fn keyword_search_works_without_embeddings() {
+        let (conn, folder) = vault();
+        let once = note(&conn, folder, "Garden", "Tomatoes need sun and a lot of water in the summer months");
+        let twice = note(&conn, folder, "Tomatoes", "Tomatoes are easy");
+        note(&conn, folder, "Bread", "Flour, water and salt");
+
+        let results = search(&conn, &OfflineProvider, "tomatoes", &SearchFilters::default());
+        assert_eq!(ids(&results), vec![twice, once]);
+        let explanation = &results[0].explanation;
+        assert_eq!(explanation.keyword_rank, Some(1));
+        assert_eq!(explanation.semantic_rank, None);
+        assert_eq!(explanation.matched_terms, vec!["tomatoes".to_string()]);
+        assert!(results[1].snippet.contains("Tomatoes need sun"));
+        assert_eq!(results[0].block_ids, vec!["h".to_string(), "p".to_string()]);
+    }
+
+    #[test]
+    fn index_follows_edits_and_deletes() {
+        let (conn, folder) = vault();
+        let id = note(&conn, folder, "Plan", "alpha release");
+        assert_eq!(ids(&search(&conn, &OfflineProvider, "alpha", &SearchFilters::default())), vec![id]);
+
+        let edited = editor_doc(2, "Plan", "beta release");
+        let doc = Document {
+            id,
+            title: "Plan".to_string(),
+            time: "2".to_string(),
+            content: serde_json::to_string(&edited).unwrap(),
+            folder_id: Some(folder),
+        };
+        update_document(&conn, id, &doc).unwrap();
+        assert!(search(&conn, &OfflineProvider, "alpha", &SearchFilters::default()).is_empty());
+        assert_eq!(ids(&search(&conn, &OfflineProvider, "beta", &SearchFilters::default())), vec![id]);
+
+        delete_document(&conn, id).unwrap();
+        assert!(search(&conn, &OfflineProvider, "beta", &SearchFilters::default()).is_empty());
+        let indexed: i64 = conn.query_row("SELECT COUNT(*) FROM search_chunks", [], |row| row.get(0)).unwrap();
+        assert_eq!(indexed, 0);
+    }
+
+    #[test]
+    fn filters_by_folder_and_tags() {
+        let (conn, folder) = vault();
+        let sub = insert_new_folder(&conn, "Reading", Some(folder)).unwrap();
+        let other = insert_new_folder(&conn, "Other", None).unwrap();
+        let tagged = note(&conn, sub, "List", "novels to read #books #later");
+        let untagged = note(&conn, folder, "List", "novels on the shelf");
+        let elsewhere = note(&conn, other, "List", "novels #books");
+
+        let in_folder = SearchFilters { folder_id: Some(folder), ..SearchFilters::default() };
+        let mut found = ids(&search(&conn, &OfflineProvider, "novels", &in_folder));
+        found.sort();
+        assert_eq!(found, vec![tagged, untagged]);
+
+        let with_tags = SearchFilters { tags: vec!["#Books".to_string(), "later".to_string()], ..SearchFilters::default() };
+        assert_eq!(ids(&search(&conn, &OfflineProvider, "novels", &with_tags)), vec![tagged]);
+
+        let books = SearchFilters { tags: vec!["books".to_string()], ..SearchFilters::default() };
+        let mut found = ids(&search(&conn, &OfflineProvider, "novels", &books));
+        found.sort();
+        assert_eq!(found, vec![tagged, elsewhere]);
+    }
+
+    #[test]
+    fn documents_in_both_rankings_come_first() {
+        let (conn, folder) = vault();
+        let keyword_only = note(&conn, folder, "Shopping", "apple bread milk eggs butter cheese rice beans pasta salt");
+        let semantic_only = note(&conn, folder, "Fruit", "orchard");
+        let both = note(&conn, folder, "Apple", "orchard fruit");
+        for id in [keyword_only, semantic_only, both] {
+            ai::index_document(&conn, &FruitProvider, id).unwrap();
+        }
+
+        let results = search(&conn, &FruitProvider, "apple", &SearchFilters::default());
+        assert_eq!(results[0].document_id, both);
+        assert_eq!(results[0].explanation.keyword_rank, Some(1));
+        assert!(results[0].explanation.semantic_rank.is_some());
+        assert_eq!(results[0].explanation.reasons.len(), 2);
+
+        let semantic = results.iter().find(|r| r.document_id == semantic_only).unwrap();
+        assert_eq!(semantic.explanation.keyword_rank, None);
+        assert!(semantic.explanation.similarity.unwrap() > 0.9);
+        let keyword = results.iter().find(|r| r.document_id == keyword_only).unwrap();
+        assert_eq!(keyword.explanation.semantic_rank, None);
+        assert!(results[0].score > semantic.score && results[0].score > keyword.score);
+    }
+}
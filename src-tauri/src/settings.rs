@@ -0,0 +1,50 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::error::AppError;
+
+// Key/value store for app settings that live in the vault itself (device id, sync
+// peers, feature configuration), so they travel with the database file.
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+pub fn get_setting(conn: &Connection, key: &str) -> Result<Option<String>, AppError> {
+    let value = conn
+        .query_row("SELECT value FROM settings WHERE key = ?", [key], |row| row.get(0))
+        .optional()?;
+    Ok(value)
+}
+
+pub fn set_setting(conn: &Connection, key: &str, value: &str) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO settings (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+pub fn delete_setting(conn: &Connection, key: &str) -> Result<(), AppError> {
+    conn.execute("DELETE FROM settings WHERE key = ?", [key])?;
+    Ok(())
+}
+
+// Typed settings stored as JSON, falling back to `default` when unset
+pub fn get_json<T: serde::de::DeserializeOwned>(conn: &Connection, key: &str, default: T) -> Result<T, AppError> {
+    match get_setting(conn, key)? {
+        Some(value) => Ok(serde_json::from_str(&value)?),
+        None => Ok(default),
+    }
+}
+
+pub fn set_json<T: serde::Serialize>(conn: &Connection, key: &str, value: &T) -> Result<(), AppError> {
+    set_setting(conn, key, &serde_json::to_string(value)?)
+}
@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::ai;
+use crate::db::{load_document, EditorDocument};
+use crate::error::AppError;
+use crate::markdown::{block_to_markdown, inline_to_text, markdown_to_inline};
+use crate::settings::{get_json, set_json};
+use crate::sync::{record_change, ChangeOp, Entity};
+use crate::{events, vault, DB_PATH};
+
+// Short summaries of documents, and titles for documents that don't start with a header.
+//
+// Saved documents are marked stale and picked up by a worker every `POLL_INTERVAL`.
+// A summary is only regenerated when enough of the words changed since the last one
+// (estimated with MinHash, so the old text need not be kept) and at most once per
+// `min_interval_seconds` per document. With a chat model configured the model writes
+// both; offline, or when the model fails, the summary is the best scoring sentences
+// of the document and the title its first heading or sentence.
+
+const CONFIG_KEY: &str = "ai.summaries";
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const MINHASH_SIZE: u64 = 64;
+const SUMMARY_SENTENCES: usize = 3;
+const MAX_SUMMARY_CHARS: usize = 400;
+const MAX_TITLE_WORDS: usize = 8;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SummaryConfig {
+    pub enabled: bool,
+    pub auto_title: bool,
+    // Ask the chat model when one is configured
+    pub use_model: bool,
+    pub min_interval_seconds: i64,
+    // Share of changed words (0..1) that counts as a significant edit
+    pub min_change: f64,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        SummaryConfig { enabled: false, auto_title: true, use_model: true, min_interval_seconds: 600, min_change: 0.2 }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryMethod {
+    Model,
+    Extractive,
+}
+
+impl SummaryMethod {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SummaryMethod::Model => "model",
+            SummaryMethod::Extractive => "extractive",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocumentSummary {
+    pub document_id: i64,
+    pub summary: String,
+    // Only set for documents whose first block is not a header
+    pub title: Option<String>,
+    pub method: SummaryMethod,
+    pub generated_at: i64,
+}
+
+enum Refresh {
+    Updated(DocumentSummary),
+    Unchanged,
+    // Changed, but summarized too recently
+    Deferred,
+}
+
+// Managed Tauri state: documents saved since their last summary
+#[derive(Default)]
+pub struct SummaryState {
+    stale: Mutex<HashSet<i64>>,
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS document_summaries (
+            document_id INTEGER PRIMARY KEY,
+            summary TEXT NOT NULL,
+            title TEXT,
+            method TEXT NOT NULL,
+            fingerprint TEXT NOT NULL,
+            generated_at INTEGER NOT NULL,
+            FOREIGN KEY(document_id) REFERENCES documents(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn needs_title(doc: &EditorDocument) -> bool {
+    doc.blocks.first().map_or(true, |block| block.r#type != "header")
+}
+
+// Plain text lines of the document, without Markdown markers or inline formatting
+fn plain_lines(doc: &EditorDocument) -> Vec<String> {
+    doc.blocks
+        .iter()
+        .flat_map(|block| {
+            block_to_markdown(block)
+                .lines()
+                .map(|line| inline_to_text(&markdown_to_inline(line.trim_start_matches(['#', '-', '*', '>', ' ']))))
+                .collect::<Vec<_>>()
+        })
+        .filter(|line| !line.trim().is_empty())
+        .collect()
+}
+
+fn sentences(lines: &[String]) -> Vec<String> {
+    let mut sentences = Vec::new();
+    for line in lines {
+        let chars: Vec<char> = line.chars().collect();
+        let mut current = String::new();
+        for (i, c) in chars.iter().enumerate() {
+            current.push(*c);
+            let at_end = chars.get(i + 1).map_or(true, |next| next.is_whitespace());
+            if matches!(c, '.' | '!' | '?') && at_end {
+                sentences.push(current.trim().to_string());
+                current.clear();
+            }
+        }
+        if !current.trim().is_empty() {
+            sentences.push(current.trim().to_string());
+        }
+    }
+    sentences
+}
+
+// MinHash signature of the document's words; equal positions estimate the Jaccard similarity.
+// Each position permutes the word hashes with a different seed; FNV alone mixes its last
+// bytes too little, so a one word edit moved the minimum of a third of the positions.
+fn fingerprint(words: &HashSet<String>) -> Vec<u64> {
+    let hashes: Vec<u64> = words.iter().map(|word| ai::fnv1a(word.as_bytes())).collect();
+    (0..MINHASH_SIZE)
+        .map(|seed| {
+            let seed = seed.wrapping_mul(0x9e3779b97f4a7c15);
+            hashes.iter().map(|hash| mix(hash ^ seed)).min().unwrap_or(u64::MAX)
+        })
+        .collect()
+}
+
+// SplitMix64 finalizer, a bijection that spreads every input bit over the output
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn similarity(a: &[u64], b: &[u64]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    a.iter().zip(b).filter(|(x, y)| x == y).count() as f64 / a.len() as f64
+}
+
+// Sentences with the most frequent content words, in document order. Words of up to
+// three letters are skipped as a cheap stand-in for a stop word list.
+fn extractive_summary(sentences: &[String]) -> String {
+    let mut freq: HashMap<String, f64> = HashMap::new();
+    for sentence in sentences {
+        for word in ai::words(sentence).filter(|w| w.chars().count() > 3) {
+            *freq.entry(word).or_default() += 1.0;
+        }
+    }
+    let mut scored: Vec<(usize, f64)> = sentences
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.split_whitespace().count() >= 4)
+        .map(|(i, sentence)| {
+            let words: Vec<String> = ai::words(sentence).collect();
+            let score = words.iter().filter_map(|w| freq.get(w)).sum::<f64>() / (words.len() as f64).sqrt();
+            // Notes tend to open with what they are about
+            let position = if i == 0 { 1.5 } else { 1.0 };
+            (i, score * position)
+        })
+        .collect();
+    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    let mut picked: Vec<usize> = scored.into_iter().take(SUMMARY_SENTENCES).map(|(i, _)| i).collect();
+    picked.sort();
+
+    let summary = picked.iter().map(|i| sentences[*i].as_str()).collect::<Vec<_>>().join(" ");
+    if summary.chars().count() > MAX_SUMMARY_CHARS {
+        format!("{}…", summary.chars().take(MAX_SUMMARY_CHARS).collect::<String>().trim_end())
+    } else {
+        summary
+    }
+}
+
+fn heuristic_title(doc: &EditorDocument, sentences: &[String]) -> Option<String> {
+    let heading = doc
+        .blocks
+        .iter()
+        .find(|block| block.r#type == "header")
+        .and_then(|block| block.data.get("text").and_then(|t| t.as_str()))
+        .map(inline_to_text)
+        .filter(|text| !text.trim().is_empty());
+    heading.or_else(|| {
+        let first = sentences.first()?;
+        let words: Vec<&str> = first.split_whitespace().collect();
+        let title = words.iter().take(MAX_TITLE_WORDS).cloned().collect::<Vec<_>>().join(" ");
+        let title = title.trim_end_matches(['.', ',', ':', ';', '!', '?']).to_string();
+        Some(if words.len() > MAX_TITLE_WORDS { format!("{}…", title) } else { title })
+    })
+}
+
+#[derive(Deserialize)]
+struct ModelSummary {
+    title: Option<String>,
+    summary: String,
+}
+
+fn model_summary(conn: &Connection, text: &str, with_title: bool) -> Result<ModelSummary, AppError> {
+    let system = if with_title {
+        "Summarize the user's note in at most three sentences and give it a title of at most \
+         eight words. Reply with JSON only: {\"title\": \"...\", \"summary\": \"...\"}"
+    } else {
+        "Summarize the user's note in at most three sentences. Reply with JSON only: {\"summary\": \"...\"}"
+    };
+    let text: String = text.chars().take(12000).collect();
+    ai::reply_json(&ai::generate(conn, system.to_string(), text)?)
+}
+
+fn stored(conn: &Connection, document_id: i64) -> Result<Option<(DocumentSummary, Vec<u64>)>, AppError> {
+    let row = conn
+        .query_row(
+            "SELECT summary, title, method, fingerprint, generated_at FROM document_summaries WHERE document_id = ?",
+            [document_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            },
+        )
+        .optional()?;
+    match row {
+        Some((summary, title, method, fingerprint, generated_at)) => Ok(Some((
+            DocumentSummary {
+                document_id,
+                summary: vault::open_text(&summary)?,
+                title,
+                method: if method == "model" { SummaryMethod::Model } else { SummaryMethod::Extractive },
+                generated_at,
+            },
+            serde_json::from_str(&fingerprint)?,
+        ))),
+        None => Ok(None),
+    }
+}
+
+// Title generated for a document that doesn't start with a header, so saving it
+// keeps that title instead of the text of its first block
+pub fn generated_title(conn: &Connection, document_id: i64, doc: &EditorDocument) -> Option<String> {
+    let config = get_json(conn, CONFIG_KEY, SummaryConfig::default()).ok()?;
+    if !config.enabled || !config.auto_title || !needs_title(doc) {
+        return None;
+    }
+    stored(conn, document_id).ok()?.and_then(|(summary, _)| summary.title)
+}
+
+fn refresh(app: &AppHandle, conn: &Connection, document_id: i64, config: &SummaryConfig, force: bool) -> Result<Refresh, AppError> {
+    let doc = match load_document(conn, document_id) {
+        Ok(doc) => doc,
+        Err(AppError::SqliteError(rusqlite::Error::QueryReturnedNoRows)) => {
+            conn.execute("DELETE FROM document_summaries WHERE document_id = ?", [document_id])?;
+            return Ok(Refresh::Unchanged);
+        }
+        Err(e) => return Err(e),
+    };
+    let editor_doc: EditorDocument = serde_json::from_str(&doc.content)?;
+    let lines = plain_lines(&editor_doc);
+    let words: HashSet<String> = lines.iter().flat_map(|line| ai::words(line)).collect();
+    if words.is_empty() {
+        return Ok(Refresh::Unchanged);
+    }
+    let print = fingerprint(&words);
+
+    let now = chrono::Utc::now().timestamp();
+    if !force {
+        if let Some((previous, previous_print)) = stored(conn, document_id)? {
+            if 1.0 - similarity(&print, &previous_print) < config.min_change {
+                return Ok(Refresh::Unchanged);
+            }
+            if now - previous.generated_at < config.min_interval_seconds {
+                return Ok(Refresh::Deferred);
+            }
+        }
+    }
+
+    let with_title = config.auto_title && needs_title(&editor_doc);
+    let sentences = sentences(&lines);
+    let from_model = if config.use_model && ai::chat_model_configured(conn)? {
+        match model_summary(conn, &lines.join("\n"), with_title) {
+            Ok(result) => Some(result),
+            Err(e) => {
+                eprintln!("Model summary of document {} failed, using extractive summary: {}", document_id, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let (summary, title, method) = match from_model {
+        Some(result) => (result.summary, result.title, SummaryMethod::Model),
+        None => (extractive_summary(&sentences), heuristic_title(&editor_doc, &sentences), SummaryMethod::Extractive),
+    };
+    let title = title.map(|t| t.trim().to_string()).filter(|t| with_title && !t.is_empty());
+
+    conn.execute(
+        "INSERT INTO document_summaries (document_id, summary, title, method, fingerprint, generated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(document_id) DO UPDATE SET summary = ?2, title = ?3, method = ?4, fingerprint = ?5, generated_at = ?6",
+        params![document_id, vault::seal_text(&summary)?, title, method.as_str(), serde_json::to_string(&print)?, now],
+    )?;
+
+    if let Some(title) = title.as_ref().filter(|t| **t != doc.title) {
+        conn.execute("UPDATE documents SET title = ? WHERE id = ?", params![title, document_id])?;
+        record_change(conn, Entity::Document, document_id, ChangeOp::Upsert)?;
+        events::document_updated(app, events::DocumentUpdated {
+            id: document_id,
+            title: title.clone(),
+            time: doc.time.clone(),
+            folder_id: doc.folder_id,
+            source_window: None,
+        });
+    }
+
+    Ok(Refresh::Updated(DocumentSummary { document_id, summary, title, method, generated_at: now }))
+}
+
+fn run_worker(app: &AppHandle) {
+    let state = app.state::<SummaryState>();
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if vault::is_locked() {
+            continue;
+        }
+        let result = Connection::open(DB_PATH).map_err(AppError::from).and_then(|conn| {
+            let config = get_json(&conn, CONFIG_KEY, SummaryConfig::default())?;
+            if !config.enabled {
+                return Ok(());
+            }
+            let ids: Vec<i64> = state.stale.lock().unwrap().iter().cloned().collect();
+            for id in ids {
+                match refresh(app, &conn, id, &config, false) {
+                    Ok(Refresh::Deferred) => {}
+                    Ok(_) => {
+                        state.stale.lock().unwrap().remove(&id);
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to summarize document {}: {}", id, e);
+                        state.stale.lock().unwrap().remove(&id);
+                    }
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            eprintln!("Summary worker failed: {}", e);
+        }
+    }
+}
+
+pub fn start(app: AppHandle) {
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_CREATED, move |e: events::DocumentCreated| {
+        handle.state::<SummaryState>().stale.lock().unwrap().insert(e.id);
+    });
+    let handle = app.clone();
+    events::listen(&app, events::DOCUMENT_UPDATED, move |e: events::DocumentUpdated| {
+        handle.state::<SummaryState>().stale.lock().unwrap().insert(e.id);
+    });
+    let handle = app.clone();
+    events::listen(&app, events::VAULT_SYNCED, move |_: events::VaultSynced| {
+        let ids = Connection::open(DB_PATH)
+            .and_then(|conn| {
+                let mut stmt = conn.prepare("SELECT id FROM documents")?;
+                let ids = stmt.query_map([], |row| row.get(0))?.collect::<Result<Vec<i64>, rusqlite::Error>>();
+                ids
+            })
+            .unwrap_or_default();
+        handle.state::<SummaryState>().stale.lock().unwrap().extend(ids);
+    });
+    events::listen(&app, events::DOCUMENT_DELETED, move |e: events::DocumentDeleted| {
+        let result = Connection::open(DB_PATH)
+            .and_then(|conn| conn.execute("DELETE FROM document_summaries WHERE document_id = ?", [e.id]));
+        if let Err(err) = result {
+            eprintln!("Failed to drop summary of document {}: {}", e.id, err);
+        }
+    });
+
+    thread::spawn(move || run_worker(&app));
+}
+
+#[tauri::command]
+pub fn get_document_summary_command(id: i64) -> Result<Option<DocumentSummary>, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    Ok(stored(&conn, id).map_err(|e| e.to_string())?.map(|(summary, _)| summary))
+}
+
+// Regenerates right away, ignoring the change threshold and the rate limit
+#[tauri::command]
+pub async fn summarize_document_command(app: AppHandle, id: i64) -> Result<Option<DocumentSummary>, String> {
+    println!("summarize_document_command -> id: {}", id);
+    tauri::async_runtime::spawn_blocking(move || {
+        vault::guard()?;
+        let conn = Connection::open(DB_PATH)?;
+        let config = get_json(&conn, CONFIG_KEY, SummaryConfig::default())?;
+        match refresh(&app, &conn, id, &config, true)? {
+            Refresh::Updated(summary) => Ok(Some(summary)),
+            _ => Ok(None),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e: AppError| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_summary_config_command() -> Result<SummaryConfig, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    get_json(&conn, CONFIG_KEY, SummaryConfig::default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_summary_config_command(config: SummaryConfig) -> Result<(), String> {
+    println!("set_summary_config_command -> {:?}", config);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    set_json(&conn, CONFIG_KEY, &config).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Block;
+    use serde_json::json;
+
+    fn doc(blocks: Vec<(&str, serde_json::Value)>) -> EditorDocument {
+        EditorDocument {
+            time: 1,
+            blocks: blocks
+                .into_iter()
+                .enumerate()
+                .map(|(i, (kind, data))| Block { id: format!("b{}", i), r#type: kind.to_string(), data })
+                .collect(),
+            version: "2.30.5".to_string(),
+        }
+    }
+
+    fn words(text: &str) -> HashSet<String> {
+        ai::words(text).collect()
+    }
+
+    #[test]
+    fn plain_lines_drop_markers_and_blank_blocks() {
+        let doc = doc(vec![
+            ("header", json!({ "text": "<b>Garden</b>", "level": 2 })),
+            ("paragraph", json!({ "text": "   " })),
+            ("paragraph", json!({ "text": "Water the <i>beans</i>." })),
+        ]);
+        assert_eq!(plain_lines(&doc), vec!["Garden", "Water the beans."]);
+        assert!(!needs_title(&doc));
+        assert!(needs_title(&self::doc(vec![("paragraph", json!({ "text": "No header" }))])));
+        assert!(needs_title(&self::doc(Vec::new())));
+    }
+
+    #[test]
+    fn sentences_end_at_punctuation_before_whitespace() {
+        let lines = vec!["Version 1.5 is out! Is it good? Yes.".to_string(), "No final stop".to_string()];
+        assert_eq!(sentences(&lines), vec!["Version 1.5 is out!", "Is it good?", "Yes.", "No final stop"]);
+    }
+
+    #[test]
+    fn extractive_summary_keeps_the_best_sentences_in_order() {
+        let sentences: Vec<String> = [
+            "The garden needs water today.",
+            "Short one.",
+            "Garden beds need compost and garden water.",
+            "Nothing related appears in this sentence here.",
+            "Watering the garden keeps the garden green.",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(
+            extractive_summary(&sentences),
+            "The garden needs water today. Garden beds need compost and garden water. \
+             Watering the garden keeps the garden green."
+        );
+
+        let long = vec![(0..120).map(|i| format!("word{}", i)).collect::<Vec<_>>().join(" ")];
+        let summary = extractive_summary(&long);
+        assert!(summary.ends_with('…'));
+        assert_eq!(summary.chars().count(), MAX_SUMMARY_CHARS + 1);
+        assert_eq!(extractive_summary(&[]), "");
+    }
+
+    #[test]
+    fn title_is_the_first_header_or_the_start_of_the_first_sentence() {
+        let with_header = doc(vec![
+            ("paragraph", json!({ "text": "Intro text" })),
+            ("header", json!({ "text": "<b>Tomatoes</b>", "level": 2 })),
+        ]);
+        assert_eq!(heuristic_title(&with_header, &["Intro text".to_string()]), Some("Tomatoes".to_string()));
+
+        let plain = doc(vec![("paragraph", json!({ "text": "" }))]);
+        assert_eq!(heuristic_title(&plain, &["Groceries for Sunday.".to_string()]), Some("Groceries for Sunday".to_string()));
+        assert_eq!(
+            heuristic_title(&plain, &["One two three four five six seven eight, nine ten.".to_string()]),
+            Some("One two three four five six seven eight…".to_string())
+        );
+        assert_eq!(heuristic_title(&plain, &[]), None);
+    }
+
+    #[test]
+    fn fingerprints_estimate_how_much_changed() {
+        let text = (0..200).map(|i| format!("word{}", i)).collect::<Vec<_>>().join(" ");
+        let print = fingerprint(&words(&text));
+        assert_eq!(print.len(), MINHASH_SIZE as usize);
+        assert_eq!(similarity(&print, &fingerprint(&words(&text))), 1.0);
+
+        let edited = format!("{} extra", text.replacen("word0 ", "", 1));
+        assert!(similarity(&print, &fingerprint(&words(&edited))) > 0.9);
+        assert!(similarity(&print, &fingerprint(&words("something else entirely"))) < 0.2);
+        assert_eq!(similarity(&print, &print[..10]), 0.0);
+        assert_eq!(similarity(&[], &[]), 0.0);
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::error::AppError;
+use crate::settings::{get_json, set_json};
+use crate::{credentials, DB_PATH};
+
+// Runs the Python backend as a child process.
+//
+// The script is started with the app's environment (including `.env`), plus
+// `HOST`/`PORT` taken from the configured backend URL. A monitor thread polls the
+// health endpoint, restarts the process with exponential backoff when it exits,
+// and resets the backoff once it has stayed up for a while. Output is forwarded to
+// the app log and the last lines are kept for the status command.
+
+const CONFIG_KEY: &str = "backend.supervisor";
+const LOG_LINES: usize = 200;
+const MONITOR_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Uptime after which a crash counts as new rather than part of a crash loop
+const STABLE_AFTER: Duration = Duration::from_secs(120);
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SupervisorConfig {
+    pub enabled: bool,
+    // Relative paths are resolved against the app's working directory
+    pub script: String,
+    pub health_path: String,
+    pub health_interval_seconds: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        SupervisorConfig {
+            enabled: true,
+            script: "../backend/start.sh".to_string(),
+            health_path: "health".to_string(),
+            health_interval_seconds: 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendStatus {
+    Stopped,
+    Starting,
+    Running,
+    Unhealthy,
+    Crashed,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SupervisorStatus {
+    pub status: BackendStatus,
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub last_health_check: Option<String>,
+    pub logs: Vec<String>,
+}
+
+struct Supervisor {
+    child: Option<Child>,
+    status: BackendStatus,
+    started_at: Option<Instant>,
+    restarts: u32,
+    backoff: Duration,
+    next_start: Option<Instant>,
+    last_health: Option<Instant>,
+    last_health_check: Option<String>,
+    last_error: Option<String>,
+    logs: VecDeque<String>,
+    // Set by `stop`; keeps the monitor from restarting the process
+    stopped_by_user: bool,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Supervisor {
+            child: None,
+            status: BackendStatus::Stopped,
+            started_at: None,
+            restarts: 0,
+            backoff: Duration::from_secs(1),
+            next_start: Some(Instant::now()),
+            last_health: None,
+            last_health_check: None,
+            last_error: None,
+            logs: VecDeque::new(),
+            stopped_by_user: false,
+        }
+    }
+}
+
+// Managed Tauri state
+#[derive(Default)]
+pub struct SupervisorState {
+    inner: Mutex<Supervisor>,
+}
+
+impl SupervisorState {
+    fn log(&self, line: String) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.logs.len() == LOG_LINES {
+            inner.logs.pop_front();
+        }
+        inner.logs.push_back(line);
+    }
+}
+
+fn load_config() -> Result<SupervisorConfig, AppError> {
+    let conn = Connection::open(DB_PATH)?;
+    get_json(&conn, CONFIG_KEY, SupervisorConfig::default())
+}
+
+fn forward_output<R: Read + Send + 'static>(app: AppHandle, stream: R, is_err: bool) {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if is_err {
+                eprintln!("[backend] {}", line);
+            } else {
+                println!("[backend] {}", line);
+            }
+            app.state::<SupervisorState>().log(line);
+        }
+    });
+}
+
+fn spawn(app: &AppHandle, config: &SupervisorConfig) -> Result<Child, AppError> {
+    let script = PathBuf::from(&config.script);
+    if !script.exists() {
+        return Err(AppError::NotFound(format!("backend script {}", script.display())));
+    }
+    let mut command = Command::new("bash");
+    command
+        .arg(&script)
+        .current_dir(script.parent().unwrap_or(Path::new(".")))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(url) = credentials::backend_url(credentials::PYTHON_BACKEND).and_then(|u| reqwest::Url::parse(&u).ok()) {
+        if let Some(host) = url.host_str() {
+            command.env("HOST", host);
+        }
+        if let Some(port) = url.port_or_known_default() {
+            command.env("PORT", port.to_string());
+        }
+    }
+    // Own process group, so shutdown reaches uvicorn and its workers too
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let mut child = command.spawn()?;
+    if let Some(stdout) = child.stdout.take() {
+        forward_output(app.clone(), stdout, false);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_output(app.clone(), stderr, true);
+    }
+    println!("Started Python backend (pid {})", child.id());
+    Ok(child)
+}
+
+fn terminate(mut child: Child) {
+    #[cfg(unix)]
+    {
+        let _ = Command::new("kill").arg("-TERM").arg(format!("-{}", child.id())).status();
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        let _ = Command::new("kill").arg("-KILL").arg(format!("-{}", child.id())).status();
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn health_check(config: &SupervisorConfig) -> bool {
+    let url = match credentials::backend_url(credentials::PYTHON_BACKEND) {
+        Some(url) => url,
+        // Without a URL there is nothing to poll; a live process counts as healthy
+        None => return true,
+    };
+    let endpoint = format!("{}/{}", url.trim_end_matches('/'), config.health_path.trim_start_matches('/'));
+    reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .and_then(|client| client.get(endpoint).send())
+        .map(|res| res.status().is_success())
+        .unwrap_or(false)
+}
+
+// One pass of the monitor loop
+fn supervise(app: &AppHandle, config: &SupervisorConfig) {
+    let state = app.state::<SupervisorState>();
+    let mut inner = state.inner.lock().unwrap();
+
+    if let Some(child) = inner.child.as_mut() {
+        match child.try_wait() {
+            Ok(None) => {}
+            Ok(Some(exit)) => {
+                let uptime = inner.started_at.map_or(Duration::ZERO, |t| t.elapsed());
+                if uptime >= STABLE_AFTER {
+                    inner.backoff = Duration::from_secs(1);
+                }
+                eprintln!("Python backend exited with {}; restarting in {:?}", exit, inner.backoff);
+                inner.child = None;
+                inner.status = BackendStatus::Crashed;
+                inner.last_error = Some(format!("Exited with {}", exit));
+                inner.next_start = Some(Instant::now() + inner.backoff);
+                inner.backoff = (inner.backoff * 2).min(MAX_BACKOFF);
+                inner.restarts += 1;
+            }
+            Err(e) => inner.last_error = Some(e.to_string()),
+        }
+    }
+
+    if inner.child.is_none() {
+        if inner.stopped_by_user || !config.enabled {
+            inner.status = BackendStatus::Stopped;
+            return;
+        }
+        if inner.next_start.map_or(true, |t| Instant::now() < t) {
+            return;
+        }
+        match spawn(app, config) {
+            Ok(child) => {
+                inner.child = Some(child);
+                inner.status = BackendStatus::Starting;
+                inner.started_at = Some(Instant::now());
+                inner.last_health = None;
+                inner.next_start = None;
+            }
+            Err(e) => {
+                eprintln!("Failed to start Python backend: {}", e);
+                inner.status = BackendStatus::Crashed;
+                inner.last_error = Some(e.to_string());
+                inner.next_start = Some(Instant::now() + inner.backoff);
+                inner.backoff = (inner.backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+        return;
+    }
+
+    let due = inner
+        .last_health
+        .map_or(true, |t| t.elapsed() >= Duration::from_secs(config.health_interval_seconds.max(1)));
+    if due {
+        inner.last_health = Some(Instant::now());
+        // The check can take seconds; don't hold the lock meanwhile
+        drop(inner);
+        let healthy = health_check(config);
+        let mut inner = state.inner.lock().unwrap();
+        if inner.child.is_some() {
+            inner.last_health_check = Some(chrono::Utc::now().to_rfc3339());
+            inner.status = match (healthy, inner.status) {
+                (true, _) => BackendStatus::Running,
+                // Still booting; uvicorn takes a while to load the models
+                (false, BackendStatus::Starting) => BackendStatus::Starting,
+                (false, _) => BackendStatus::Unhealthy,
+            };
+        }
+    }
+}
+
+pub fn start(app: AppHandle) {
+    thread::spawn(move || loop {
+        match load_config() {
+            Ok(config) => supervise(&app, &config),
+            Err(e) => eprintln!("Backend supervisor could not load config: {}", e),
+        }
+        thread::sleep(MONITOR_INTERVAL);
+    });
+}
+
+// Stops the backend when the app exits
+pub fn shutdown(app: &AppHandle) {
+    let state = app.state::<SupervisorState>();
+    let child = {
+        let mut inner = state.inner.lock().unwrap();
+        inner.stopped_by_user = true;
+        inner.child.take()
+    };
+    if let Some(child) = child {
+        println!("Stopping Python backend (pid {})", child.id());
+        terminate(child);
+    }
+}
+
+#[tauri::command]
+pub fn backend_status_command(state: State<SupervisorState>) -> Result<SupervisorStatus, String> {
+    let inner = state.inner.lock().unwrap();
+    Ok(SupervisorStatus {
+        status: inner.status,
+        pid: inner.child.as_ref().map(Child::id),
+        restarts: inner.restarts,
+        last_error: inner.last_error.clone(),
+        last_health_check: inner.last_health_check.clone(),
+        logs: inner.logs.iter().cloned().collect(),
+    })
+}
+
+// Stops the running process (if any) and starts a fresh one right away
+#[tauri::command]
+pub async fn restart_backend_command(app: AppHandle) -> Result<(), String> {
+    println!("restart_backend_command");
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<SupervisorState>();
+        let child = {
+            let mut inner = state.inner.lock().unwrap();
+            inner.stopped_by_user = true;
+            inner.child.take()
+        };
+        if let Some(child) = child {
+            terminate(child);
+        }
+        let mut inner = state.inner.lock().unwrap();
+        inner.stopped_by_user = false;
+        inner.backoff = Duration::from_secs(1);
+        inner.next_start = Some(Instant::now());
+        inner.status = BackendStatus::Stopped;
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn stop_backend_command(app: AppHandle) -> Result<(), String> {
+    println!("stop_backend_command");
+    tauri::async_runtime::spawn_blocking(move || shutdown(&app))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_supervisor_config_command() -> Result<SupervisorConfig, String> {
+    load_config().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_supervisor_config_command(config: SupervisorConfig) -> Result<(), String> {
+    println!("set_supervisor_config_command -> {:?}", config);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    set_json(&conn, CONFIG_KEY, &config).map_err(|e| e.to_string())
+}
@@ -0,0 +1,1112 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, State};
+
+use crate::crypto;
+use crate::db::{self, FlashcardReview, TimerSession};
+use crate::error::AppError;
+use crate::settings::{get_setting, set_setting};
+use crate::{events, vault, DB_PATH};
+
+// Peer-to-peer vault sync.
+//
+// Every mutation of a synced entity appends a row to `sync_changes`, numbered by a
+// per-device counter and stamped with a Lamport clock. Rows are addressed by a global
+// id so autoincrement ids never have to match between devices. Peers exchange the
+// changes the other side has not seen (tracked as a version vector of the highest
+// counter per device) and the change with the highest (lamport, device_id) wins, so
+// every device converges on the same state regardless of arrival order. Concurrent
+// edits are kept in `sync_conflicts` for review.
+//
+// Devices share one secret, `sync.token`, generated when the endpoint first starts
+// and copied to the other devices; requests without it are refused.
+
+const DEVICE_ID_KEY: &str = "sync.device_id";
+const TOKEN_KEY: &str = "sync.token";
+const MIN_TOKEN_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Entity {
+    Document,
+    Folder,
+    TimerSession,
+    FlashcardReview,
+}
+
+impl Entity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Entity::Document => "document",
+            Entity::Folder => "folder",
+            Entity::TimerSession => "timer_session",
+            Entity::FlashcardReview => "flashcard_review",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Entity> {
+        match value {
+            "document" => Some(Entity::Document),
+            "folder" => Some(Entity::Folder),
+            "timer_session" => Some(Entity::TimerSession),
+            "flashcard_review" => Some(Entity::FlashcardReview),
+            _ => None,
+        }
+    }
+
+    fn table(&self) -> &'static str {
+        match self {
+            Entity::Document => "documents",
+            Entity::Folder => "folders",
+            Entity::TimerSession => "timer_sessions",
+            Entity::FlashcardReview => "flashcard_reviews",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeOp {
+    Upsert,
+    Delete,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Version {
+    pub lamport: i64,
+    pub device_id: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Change {
+    pub device_id: String,
+    pub counter: i64,
+    pub lamport: i64,
+    pub entity: Entity,
+    pub global_id: String,
+    pub op: ChangeOp,
+    // Version this change was made on top of; anything else means a concurrent edit
+    pub base: Option<Version>,
+    pub payload: Option<Value>,
+}
+
+impl Change {
+    fn version(&self) -> Version {
+        Version { lamport: self.lamport, device_id: self.device_id.clone() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequest {
+    pub vector: HashMap<String, i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullResponse {
+    pub vector: HashMap<String, i64>,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PushRequest {
+    pub changes: Vec<Change>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ApplyReport {
+    pub applied: usize,
+    pub conflicts: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyncReport {
+    pub peer: String,
+    pub pulled: usize,
+    pub pushed: usize,
+    pub conflicts: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PeerStatus {
+    pub address: String,
+    pub last_sync: Option<String>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyncStatus {
+    pub device_id: String,
+    pub vector: HashMap<String, i64>,
+    pub local_changes: i64,
+    pub open_conflicts: i64,
+    pub server_port: Option<u16>,
+    pub peers: Vec<PeerStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConflictReport {
+    pub id: i64,
+    pub entity: Entity,
+    pub global_id: String,
+    pub local_id: Option<i64>,
+    pub winner: Change,
+    pub loser: Change,
+    pub detected_at: String,
+    pub resolved: bool,
+}
+
+// Managed Tauri state for the sync endpoint
+#[derive(Default)]
+pub struct SyncState {
+    server_port: Mutex<Option<u16>>,
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_ids (
+            entity TEXT NOT NULL,
+            local_id INTEGER NOT NULL,
+            global_id TEXT NOT NULL,
+            PRIMARY KEY(entity, local_id),
+            UNIQUE(entity, global_id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_changes (
+            device_id TEXT NOT NULL,
+            counter INTEGER NOT NULL,
+            lamport INTEGER NOT NULL,
+            entity TEXT NOT NULL,
+            global_id TEXT NOT NULL,
+            op TEXT NOT NULL,
+            base TEXT,
+            payload TEXT,
+            PRIMARY KEY(device_id, counter)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_versions (
+            entity TEXT NOT NULL,
+            global_id TEXT NOT NULL,
+            lamport INTEGER NOT NULL,
+            device_id TEXT NOT NULL,
+            PRIMARY KEY(entity, global_id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_peers (
+            address TEXT PRIMARY KEY,
+            last_sync TEXT,
+            last_error TEXT
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_conflicts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            entity TEXT NOT NULL,
+            global_id TEXT NOT NULL,
+            winner TEXT NOT NULL,
+            loser TEXT NOT NULL,
+            detected_at TEXT NOT NULL DEFAULT (datetime('now')),
+            resolved INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+pub fn device_id(conn: &Connection) -> Result<String, AppError> {
+    if let Some(id) = get_setting(conn, DEVICE_ID_KEY)? {
+        return Ok(id);
+    }
+    let id = format!("{:016x}", rand::random::<u64>());
+    set_setting(conn, DEVICE_ID_KEY, &id)?;
+    Ok(id)
+}
+
+fn global_id(conn: &Connection, entity: Entity, local_id: i64) -> Result<String, AppError> {
+    let existing: Option<String> = conn
+        .query_row(
+            "SELECT global_id FROM sync_ids WHERE entity = ? AND local_id = ?",
+            params![entity.as_str(), local_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+    let id = format!("{}-{}", device_id(conn)?, local_id);
+    map_ids(conn, entity, local_id, &id)?;
+    Ok(id)
+}
+
+fn map_ids(conn: &Connection, entity: Entity, local_id: i64, global_id: &str) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_ids (entity, local_id, global_id) VALUES (?1, ?2, ?3)",
+        params![entity.as_str(), local_id, global_id],
+    )?;
+    Ok(())
+}
+
+pub fn local_id(conn: &Connection, entity: Entity, global_id: &str) -> Result<Option<i64>, AppError> {
+    let id = conn
+        .query_row(
+            "SELECT local_id FROM sync_ids WHERE entity = ? AND global_id = ?",
+            params![entity.as_str(), global_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(id)
+}
+
+fn optional_global_id(conn: &Connection, entity: Entity, local_id: Option<i64>) -> Result<Option<String>, AppError> {
+    local_id.map(|id| global_id(conn, entity, id)).transpose()
+}
+
+fn optional_local_id(conn: &Connection, entity: Entity, global_id: Option<&str>) -> Result<Option<i64>, AppError> {
+    match global_id {
+        Some(gid) => local_id(conn, entity, gid),
+        None => Ok(None),
+    }
+}
+
+fn current_version(conn: &Connection, entity: Entity, global_id: &str) -> Result<Option<Version>, AppError> {
+    let version = conn
+        .query_row(
+            "SELECT lamport, device_id FROM sync_versions WHERE entity = ? AND global_id = ?",
+            params![entity.as_str(), global_id],
+            |row| Ok(Version { lamport: row.get(0)?, device_id: row.get(1)? }),
+        )
+        .optional()?;
+    Ok(version)
+}
+
+fn set_version(conn: &Connection, entity: Entity, global_id: &str, version: &Version) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_versions (entity, global_id, lamport, device_id) VALUES (?1, ?2, ?3, ?4)",
+        params![entity.as_str(), global_id, version.lamport, version.device_id],
+    )?;
+    Ok(())
+}
+
+// Serializes a row in a device independent form: references to other rows use global ids
+fn snapshot(conn: &Connection, entity: Entity, local_id: i64) -> Result<Option<Value>, AppError> {
+    match entity {
+        Entity::Document => {
+            let doc = match db::load_document(conn, local_id) {
+                Ok(doc) => doc,
+                Err(AppError::SqliteError(rusqlite::Error::QueryReturnedNoRows)) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            Ok(Some(json!({
+                "title": doc.title,
+                "time": doc.time,
+                "content": doc.content,
+                "folder": optional_global_id(conn, Entity::Folder, doc.folder_id)?,
+            })))
+        }
+        Entity::Folder => {
+            let row = conn
+                .query_row("SELECT name, parent_id FROM folders WHERE id = ?", [local_id], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?))
+                })
+                .optional()?;
+            match row {
+                Some((name, parent_id)) => Ok(Some(json!({
+                    "name": name,
+                    "parent": optional_global_id(conn, Entity::Folder, parent_id)?,
+                }))),
+                None => Ok(None),
+            }
+        }
+        Entity::TimerSession => {
+            let session = conn
+                .query_row(
+                    "SELECT work_duration, break_duration, start_time_work, stop_time_work, start_time_break,
+                            stop_time_break, extended, extended_start_time, extended_stop_time
+                     FROM timer_sessions WHERE id = ?",
+                    [local_id],
+                    |row| {
+                        Ok(TimerSession {
+                            work_duration: row.get(0)?,
+                            break_duration: row.get(1)?,
+                            start_time_work: row.get(2)?,
+                            stop_time_work: row.get(3)?,
+                            start_time_break: row.get(4)?,
+                            stop_time_break: row.get(5)?,
+                            extended: row.get(6)?,
+                            extended_start_time: row.get(7)?,
+                            extended_stop_time: row.get(8)?,
+                        })
+                    },
+                )
+                .optional()?;
+            session.map(|s| serde_json::to_value(s).map_err(AppError::from)).transpose()
+        }
+        Entity::FlashcardReview => {
+            let review = conn
+                .query_row(
+                    "SELECT document_id, block_id, card_path, grade, reviewed_at FROM flashcard_reviews WHERE id = ?",
+                    [local_id],
+                    |row| {
+                        Ok(FlashcardReview {
+                            document_id: row.get(0)?,
+                            block_id: row.get(1)?,
+                            card_path: row.get(2)?,
+                            grade: row.get(3)?,
+                            reviewed_at: row.get(4)?,
+                        })
+                    },
+                )
+                .optional()?;
+            match review {
+                Some(review) => Ok(Some(json!({
+                    "document": global_id(conn, Entity::Document, review.document_id)?,
+                    "block_id": review.block_id,
+                    "card_path": review.card_path,
+                    "grade": review.grade,
+                    "reviewed_at": review.reviewed_at,
+                }))),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+fn next_counters(conn: &Connection, device: &str) -> Result<(i64, i64), AppError> {
+    let counter: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(counter), 0) + 1 FROM sync_changes WHERE device_id = ?",
+        [device],
+        |row| row.get(0),
+    )?;
+    let lamport: i64 = conn.query_row("SELECT COALESCE(MAX(lamport), 0) + 1 FROM sync_changes", [], |row| row.get(0))?;
+    Ok((counter, lamport))
+}
+
+fn has_change(conn: &Connection, change: &Change) -> Result<bool, AppError> {
+    let found: Option<i64> = conn
+        .query_row(
+            "SELECT 1 FROM sync_changes WHERE device_id = ? AND counter = ?",
+            params![change.device_id, change.counter],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(found.is_some())
+}
+
+fn insert_change(conn: &Connection, change: &Change) -> Result<bool, AppError> {
+    let base = change.base.as_ref().map(serde_json::to_string).transpose()?;
+    let payload = change
+        .payload
+        .as_ref()
+        .map(|p| vault::seal_text(&serde_json::to_string(p)?))
+        .transpose()?;
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO sync_changes (device_id, counter, lamport, entity, global_id, op, base, payload)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            change.device_id,
+            change.counter,
+            change.lamport,
+            change.entity.as_str(),
+            change.global_id,
+            match change.op {
+                ChangeOp::Upsert => "upsert",
+                ChangeOp::Delete => "delete",
+            },
+            base,
+            payload
+        ],
+    )?;
+    Ok(inserted > 0)
+}
+
+fn record_payload(conn: &Connection, entity: Entity, gid: String, op: ChangeOp, payload: Option<Value>) -> Result<(), AppError> {
+    let device = device_id(conn)?;
+    let (counter, lamport) = next_counters(conn, &device)?;
+    let base = current_version(conn, entity, &gid)?;
+    let change = Change { device_id: device, counter, lamport, entity, global_id: gid, op, base, payload };
+    insert_change(conn, &change)?;
+    set_version(conn, entity, &change.global_id, &change.version())?;
+    Ok(())
+}
+
+// Appends a local mutation to the change log. Call after the row was written (or
+// deleted); upserts snapshot the row as it is now.
+pub fn record_change(conn: &Connection, entity: Entity, local_id: i64, op: ChangeOp) -> Result<(), AppError> {
+    let gid = global_id(conn, entity, local_id)?;
+    let payload = match op {
+        ChangeOp::Upsert => match snapshot(conn, entity, local_id)? {
+            Some(payload) => Some(payload),
+            None => return Ok(()),
+        },
+        ChangeOp::Delete => None,
+    };
+    record_payload(conn, entity, gid, op, payload)
+}
+
+// Rows created before sync was enabled (or by the initial migration) get an upsert
+// so the first exchange carries the whole vault
+pub fn bootstrap(conn: &Connection) -> Result<(), AppError> {
+    for entity in [Entity::Folder, Entity::Document, Entity::TimerSession, Entity::FlashcardReview] {
+        let sql = format!(
+            "SELECT id FROM {} WHERE id NOT IN (SELECT local_id FROM sync_ids WHERE entity = ?) ORDER BY id",
+            entity.table()
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let ids = stmt
+            .query_map([entity.as_str()], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+        for id in ids {
+            record_change(conn, entity, id, ChangeOp::Upsert)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn vector(conn: &Connection) -> Result<HashMap<String, i64>, AppError> {
+    let mut stmt = conn.prepare("SELECT device_id, MAX(counter) FROM sync_changes GROUP BY device_id")?;
+    let vector = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<HashMap<String, i64>, rusqlite::Error>>()?;
+    Ok(vector)
+}
+
+fn row_to_change(row: &rusqlite::Row) -> Result<Change, rusqlite::Error> {
+    let parse_json = |idx: usize, text: Option<String>| -> Result<Option<Value>, rusqlite::Error> {
+        text.map(|t| {
+            serde_json::from_str(&t)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e)))
+        })
+        .transpose()
+    };
+    let entity: String = row.get(3)?;
+    let op: String = row.get(5)?;
+    let payload = row
+        .get::<_, Option<String>>(7)?
+        .map(|text| vault::open_text(&text))
+        .transpose()
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(7, rusqlite::types::Type::Text, Box::new(e)))?;
+    let base = parse_json(6, row.get(6)?)?.map(serde_json::from_value).transpose().map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(6, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    Ok(Change {
+        device_id: row.get(0)?,
+        counter: row.get(1)?,
+        lamport: row.get(2)?,
+        entity: Entity::parse(&entity).ok_or_else(|| {
+            rusqlite::Error::InvalidColumnType(3, entity.clone(), rusqlite::types::Type::Text)
+        })?,
+        global_id: row.get(4)?,
+        op: if op == "delete" { ChangeOp::Delete } else { ChangeOp::Upsert },
+        base,
+        payload: parse_json(7, payload)?,
+    })
+}
+
+pub fn changes_since(conn: &Connection, vector: &HashMap<String, i64>) -> Result<Vec<Change>, AppError> {
+    let mut stmt = conn.prepare(
+        "SELECT device_id, counter, lamport, entity, global_id, op, base, payload
+         FROM sync_changes ORDER BY lamport, device_id",
+    )?;
+    let changes = stmt
+        .query_map([], row_to_change)?
+        .collect::<Result<Vec<Change>, rusqlite::Error>>()?
+        .into_iter()
+        .filter(|c| c.counter > vector.get(&c.device_id).copied().unwrap_or(0))
+        .collect();
+    Ok(changes)
+}
+
+fn payload_str<'a>(payload: &'a Value, key: &str) -> Option<&'a str> {
+    payload.get(key).and_then(Value::as_str)
+}
+
+// Writes a winning change into the regular tables
+fn apply_to_tables(conn: &Connection, change: &Change) -> Result<(), AppError> {
+    let existing = local_id(conn, change.entity, &change.global_id)?;
+
+    if change.op == ChangeOp::Delete {
+        if let Some(id) = existing {
+            match change.entity {
+                Entity::Folder => {
+                    db::delete_folder(conn, id)?;
+                }
+                entity => {
+                    conn.execute(&format!("DELETE FROM {} WHERE id = ?", entity.table()), [id])?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let payload = change
+        .payload
+        .as_ref()
+        .ok_or_else(|| AppError::SyncError(format!("Upsert without payload for {}", change.global_id)))?;
+
+    let id = match change.entity {
+        Entity::Document => {
+            let folder_id = optional_local_id(conn, Entity::Folder, payload_str(payload, "folder"))?;
+            let title = payload_str(payload, "title").unwrap_or("Untitled");
+            let time = payload_str(payload, "time").unwrap_or("0");
+            let content = payload_str(payload, "content").unwrap_or("{}");
+            match existing {
+                Some(id) => {
+                    conn.execute(
+                        "UPDATE documents SET title = ?, time = ?, content = ?, folder_id = ? WHERE id = ?",
+                        params![title, time, vault::seal_text(content)?, folder_id, id],
+                    )?;
+                    id
+                }
+                None => {
+                    conn.execute(
+                        "INSERT INTO documents (title, time, content, folder_id) VALUES (?, ?, ?, ?)",
+                        params![title, time, vault::seal_text(content)?, folder_id],
+                    )?;
+                    conn.last_insert_rowid()
+                }
+            }
+        }
+        Entity::Folder => {
+            let parent_id = optional_local_id(conn, Entity::Folder, payload_str(payload, "parent"))?;
+            let name = payload_str(payload, "name").unwrap_or("Untitled");
+            match existing {
+                Some(id) => {
+                    conn.execute(
+                        "UPDATE folders SET name = ?, parent_id = ? WHERE id = ?",
+                        params![name, parent_id, id],
+                    )?;
+                    id
+                }
+                None => db::insert_new_folder(conn, name, parent_id)?,
+            }
+        }
+        Entity::TimerSession => {
+            let session: TimerSession = serde_json::from_value(payload.clone())?;
+            if let Some(id) = existing {
+                conn.execute("DELETE FROM timer_sessions WHERE id = ?", [id])?;
+            }
+            db::save_timer_session(conn, &session)?
+        }
+        Entity::FlashcardReview => {
+            let document_id = optional_local_id(conn, Entity::Document, payload_str(payload, "document"))?
+                .ok_or_else(|| AppError::SyncError(format!("Review {} references an unknown document", change.global_id)))?;
+            let review = FlashcardReview {
+                document_id,
+                block_id: payload_str(payload, "block_id").unwrap_or_default().to_string(),
+                card_path: payload_str(payload, "card_path").unwrap_or_default().to_string(),
+                grade: payload.get("grade").and_then(Value::as_i64).unwrap_or(0) as i32,
+                reviewed_at: payload_str(payload, "reviewed_at").unwrap_or_default().to_string(),
+            };
+            if let Some(id) = existing {
+                conn.execute("DELETE FROM flashcard_reviews WHERE id = ?", [id])?;
+            }
+            db::save_flashcard_review(conn, &review)?
+        }
+    };
+
+    map_ids(conn, change.entity, id, &change.global_id)?;
+    Ok(())
+}
+
+// Applies changes received from a peer. The change with the highest version wins;
+// a change that was not based on the version we hold is recorded as a conflict.
+//
+// The batch is all or nothing: a change only enters the log together with its effect,
+// so after a failure the version vector still asks the peer for all of it again.
+pub fn apply_changes(conn: &Connection, mut changes: Vec<Change>) -> Result<ApplyReport, AppError> {
+    let mut report = ApplyReport::default();
+    changes.sort_by_key(Change::version);
+
+    let tx = conn.unchecked_transaction()?;
+    for change in changes {
+        if has_change(&tx, &change)? {
+            continue;
+        }
+        let current = current_version(&tx, change.entity, &change.global_id)?;
+        let incoming = change.version();
+        let concurrent = current.is_some() && change.base != current;
+
+        if current.as_ref().map_or(true, |v| &incoming > v) {
+            if concurrent {
+                if let Some(loser) = load_change(&tx, current.as_ref().unwrap())? {
+                    save_conflict(&tx, &change, &loser)?;
+                    report.conflicts += 1;
+                }
+            }
+            apply_to_tables(&tx, &change)?;
+            set_version(&tx, change.entity, &change.global_id, &incoming)?;
+            report.applied += 1;
+        } else if concurrent {
+            if let Some(winner) = load_change(&tx, current.as_ref().unwrap())? {
+                save_conflict(&tx, &winner, &change)?;
+                report.conflicts += 1;
+            }
+        }
+        insert_change(&tx, &change)?;
+    }
+    tx.commit()?;
+    Ok(report)
+}
+
+pub fn load_change(conn: &Connection, version: &Version) -> Result<Option<Change>, AppError> {
+    let change = conn
+        .query_row(
+            "SELECT device_id, counter, lamport, entity, global_id, op, base, payload
+             FROM sync_changes WHERE device_id = ? AND lamport = ?",
+            params![version.device_id, version.lamport],
+            row_to_change,
+        )
+        .optional()?;
+    Ok(change)
+}
+
+fn save_conflict(conn: &Connection, winner: &Change, loser: &Change) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO sync_conflicts (entity, global_id, winner, loser) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            winner.entity.as_str(),
+            winner.global_id,
+            vault::seal_text(&serde_json::to_string(winner)?)?,
+            vault::seal_text(&serde_json::to_string(loser)?)?
+        ],
+    )?;
+    Ok(())
+}
+
+// The shared secret, created on first use
+fn sync_token(conn: &Connection) -> Result<String, AppError> {
+    if let Some(token) = get_setting(conn, TOKEN_KEY)? {
+        return Ok(token);
+    }
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    set_setting(conn, TOKEN_KEY, &token)?;
+    Ok(token)
+}
+
+// Without a stored token nothing is accepted
+fn check_token(conn: &Connection, request: &tiny_http::Request) -> Result<bool, AppError> {
+    let expected = match get_setting(conn, TOKEN_KEY)? {
+        Some(token) => token,
+        None => return Ok(false),
+    };
+    Ok(request.headers().iter().any(|h| {
+        h.field.equiv("X-Sync-Token") && crypto::constant_time_eq(h.value.as_str().as_bytes(), expected.as_bytes())
+    }))
+}
+
+fn handle_request(app: &AppHandle, request: &mut tiny_http::Request) -> Result<Value, AppError> {
+    let conn = Connection::open(DB_PATH)?;
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+
+    bootstrap(&conn)?;
+    match request.url() {
+        "/sync/pull" => {
+            let pull: PullRequest = serde_json::from_str(&body)?;
+            let response = PullResponse { vector: vector(&conn)?, changes: changes_since(&conn, &pull.vector)? };
+            Ok(serde_json::to_value(response)?)
+        }
+        "/sync/push" => {
+            let push: PushRequest = serde_json::from_str(&body)?;
+            let received = push.changes.len();
+            let report = apply_changes(&conn, push.changes)?;
+            if report.applied > 0 {
+                events::vault_synced(app, events::VaultSynced {
+                    peer: request.remote_addr().map(|a| a.to_string()).unwrap_or_default(),
+                    pulled: received,
+                    pushed: 0,
+                    conflicts: report.conflicts,
+                });
+            }
+            Ok(serde_json::to_value(report)?)
+        }
+        other => Err(AppError::SyncError(format!("Unknown sync endpoint {}", other))),
+    }
+}
+
+fn run_server(app: AppHandle, server: tiny_http::Server) {
+    for mut request in server.incoming_requests() {
+        let authorized = Connection::open(DB_PATH)
+            .map_err(AppError::from)
+            .and_then(|conn| check_token(&conn, &request));
+        let result = match authorized {
+            Ok(true) => handle_request(&app, &mut request),
+            Ok(false) => {
+                eprintln!("Rejected sync request from {:?}: invalid token", request.remote_addr());
+                let body = json!({ "error": "Invalid sync token" }).to_string();
+                if let Err(e) = request.respond(tiny_http::Response::from_string(body).with_status_code(401)) {
+                    eprintln!("Failed to answer sync request: {}", e);
+                }
+                continue;
+            }
+            Err(e) => Err(e),
+        };
+        let response = match result {
+            Ok(body) => tiny_http::Response::from_string(body.to_string()).with_status_code(200),
+            Err(e) => {
+                eprintln!("Sync request failed: {}", e);
+                tiny_http::Response::from_string(json!({ "error": e.to_string() }).to_string()).with_status_code(400)
+            }
+        };
+        let response = response.with_header(
+            "Content-Type: application/json".parse::<tiny_http::Header>().unwrap(),
+        );
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to answer sync request: {}", e);
+        }
+    }
+}
+
+fn record_peer(conn: &Connection, address: &str, error: Option<&str>) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO sync_peers (address, last_sync, last_error) VALUES (?1, CASE WHEN ?2 IS NULL THEN datetime('now') END, ?2)
+         ON CONFLICT(address) DO UPDATE SET
+            last_sync = CASE WHEN ?2 IS NULL THEN datetime('now') ELSE last_sync END,
+            last_error = ?2",
+        params![address, error],
+    )?;
+    Ok(())
+}
+
+async fn exchange(address: &str) -> Result<SyncReport, AppError> {
+    let (local_vector, token) = {
+        let conn = Connection::open(DB_PATH)?;
+        bootstrap(&conn)?;
+        (vector(&conn)?, sync_token(&conn)?)
+    };
+
+    let client = reqwest::Client::new();
+    let with_token = |builder: reqwest::RequestBuilder| builder.header("X-Sync-Token", &token);
+
+    let pulled: PullResponse = with_token(client.post(format!("http://{}/sync/pull", address)))
+        .json(&PullRequest { vector: local_vector })
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let pulled_count = pulled.changes.len();
+    let (report, outgoing) = {
+        let conn = Connection::open(DB_PATH)?;
+        let report = apply_changes(&conn, pulled.changes)?;
+        (report, changes_since(&conn, &pulled.vector)?)
+    };
+
+    let pushed = outgoing.len();
+    if pushed > 0 {
+        with_token(client.post(format!("http://{}/sync/push", address)))
+            .json(&PushRequest { changes: outgoing })
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+
+    Ok(SyncReport {
+        peer: address.to_string(),
+        pulled: pulled_count,
+        pushed,
+        conflicts: report.conflicts,
+    })
+}
+
+// Starts the endpoint peers sync against. Peers must send the token from
+// `get_sync_token_command`. The endpoint is plain HTTP and only listens on loopback
+// unless `lan` is set; on the LAN anyone who can see the traffic can read the token.
+#[tauri::command]
+pub fn start_sync_server_command(app: AppHandle, state: State<SyncState>, port: u16, lan: Option<bool>) -> Result<(), String> {
+    println!("start_sync_server_command -> port: {}, lan: {:?}", port, lan);
+    vault::guard().map_err(|e| e.to_string())?;
+    let mut running = state.server_port.lock().unwrap();
+    if running.is_some() {
+        return Err(format!("Sync server already running on port {}", running.unwrap()));
+    }
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    sync_token(&conn).map_err(|e| e.to_string())?;
+    let host = if lan.unwrap_or(false) { "0.0.0.0" } else { "127.0.0.1" };
+    let server = tiny_http::Server::http((host, port)).map_err(|e| e.to_string())?;
+    *running = Some(port);
+    thread::spawn(move || run_server(app, server));
+    Ok(())
+}
+
+// Shown once per device so the user can copy it to the others
+#[tauri::command]
+pub fn get_sync_token_command() -> Result<String, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    sync_token(&conn).map_err(|e| e.to_string())
+}
+
+// Stores the token copied from another device, or without one replaces it with a new
+// random token; every device then needs the new value.
+#[tauri::command]
+pub fn set_sync_token_command(token: Option<String>) -> Result<String, String> {
+    println!("set_sync_token_command -> rotate: {}", token.is_none());
+    let token = match token.map(|t| t.trim().to_string()) {
+        Some(token) if token.len() < MIN_TOKEN_LEN => {
+            return Err(format!("Sync token must be at least {} characters", MIN_TOKEN_LEN));
+        }
+        Some(token) => token,
+        None => hex::encode(rand::random::<[u8; 32]>()),
+    };
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    set_setting(&conn, TOKEN_KEY, &token).map_err(|e| e.to_string())?;
+    Ok(token)
+}
+
+#[tauri::command]
+pub async fn sync_with_peer_command(app: AppHandle, address: String) -> Result<SyncReport, String> {
+    println!("sync_with_peer_command -> {}", address);
+    let result = exchange(&address).await;
+
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    match result {
+        Ok(report) => {
+            record_peer(&conn, &address, None).map_err(|e| e.to_string())?;
+            events::vault_synced(&app, events::VaultSynced {
+                peer: address,
+                pulled: report.pulled,
+                pushed: report.pushed,
+                conflicts: report.conflicts,
+            });
+            Ok(report)
+        }
+        Err(e) => {
+            record_peer(&conn, &address, Some(&e.to_string())).map_err(|e| e.to_string())?;
+            Err(e.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+pub fn sync_status_command(state: State<SyncState>) -> Result<SyncStatus, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let device = device_id(&conn).map_err(|e| e.to_string())?;
+    let local_changes: i64 = conn
+        .query_row("SELECT COUNT(*) FROM sync_changes WHERE device_id = ?", [&device], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let open_conflicts: i64 = conn
+        .query_row("SELECT COUNT(*) FROM sync_conflicts WHERE resolved = 0", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare("SELECT address, last_sync, last_error FROM sync_peers ORDER BY address")
+        .map_err(|e| e.to_string())?;
+    let peers = stmt
+        .query_map([], |row| {
+            Ok(PeerStatus { address: row.get(0)?, last_sync: row.get(1)?, last_error: row.get(2)? })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<PeerStatus>, rusqlite::Error>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(SyncStatus {
+        device_id: device,
+        vector: vector(&conn).map_err(|e| e.to_string())?,
+        local_changes,
+        open_conflicts,
+        server_port: *state.server_port.lock().unwrap(),
+        peers,
+    })
+}
+
+#[tauri::command]
+pub fn sync_conflicts_command(include_resolved: bool) -> Result<Vec<ConflictReport>, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, entity, global_id, winner, loser, detected_at, resolved FROM sync_conflicts
+             WHERE resolved = 0 OR ?1 ORDER BY id DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([include_resolved], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, bool>(6)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut reports = Vec::new();
+    for (id, entity, global_id, winner, loser, detected_at, resolved) in rows {
+        let entity = Entity::parse(&entity).ok_or_else(|| format!("Unknown entity {}", entity))?;
+        reports.push(ConflictReport {
+            id,
+            entity,
+            local_id: local_id(&conn, entity, &global_id).map_err(|e| e.to_string())?,
+            global_id,
+            winner: serde_json::from_str(&vault::open_text(&winner).map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())?,
+            loser: serde_json::from_str(&vault::open_text(&loser).map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())?,
+            detected_at,
+            resolved,
+        });
+    }
+    Ok(reports)
+}
+
+// Marks a conflict as handled. With `keep_loser` the losing side is written again as
+// a new local change, which then wins on every device at the next sync.
+#[tauri::command]
+pub fn resolve_sync_conflict_command(id: i64, keep_loser: bool) -> Result<(), String> {
+    println!("resolve_sync_conflict_command -> id: {}, keep_loser: {}", id, keep_loser);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+
+    if keep_loser {
+        let loser: String = conn
+            .query_row("SELECT loser FROM sync_conflicts WHERE id = ?", [id], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        let loser = vault::open_text(&loser).map_err(|e| e.to_string())?;
+        let loser: Change = serde_json::from_str(&loser).map_err(|e| e.to_string())?;
+        let device = device_id(&conn).map_err(|e| e.to_string())?;
+        let (counter, lamport) = next_counters(&conn, &device).map_err(|e| e.to_string())?;
+        let change = Change {
+            device_id: device,
+            counter,
+            lamport,
+            base: current_version(&conn, loser.entity, &loser.global_id).map_err(|e| e.to_string())?,
+            ..loser
+        };
+        apply_to_tables(&conn, &change).map_err(|e| e.to_string())?;
+        insert_change(&conn, &change).map_err(|e| e.to_string())?;
+        set_version(&conn, change.entity, &change.global_id, &change.version()).map_err(|e| e.to_string())?;
+    }
+
+    conn.execute("UPDATE sync_conflicts SET resolved = 1 WHERE id = ?", [id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::EditorDocument;
+
+    fn device() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        db::create_tables(&conn).unwrap();
+        crate::settings::create_tables(&conn).unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    // One exchange as `exchange` does it, pull then push
+    fn sync(local: &Connection, peer: &Connection) -> (ApplyReport, ApplyReport) {
+        let pulled = apply_changes(local, changes_since(peer, &vector(local).unwrap()).unwrap()).unwrap();
+        let pushed = apply_changes(peer, changes_since(local, &vector(peer).unwrap()).unwrap()).unwrap();
+        (pulled, pushed)
+    }
+
+    fn folder_name(conn: &Connection, global_id: &str) -> String {
+        let id = local_id(conn, Entity::Folder, global_id).unwrap().unwrap();
+        conn.query_row("SELECT name FROM folders WHERE id = ?", [id], |row| row.get(0)).unwrap()
+    }
+
+    fn rename(conn: &Connection, global_id: &str, name: &str) {
+        let id = local_id(conn, Entity::Folder, global_id).unwrap().unwrap();
+        db::rename_folder(conn, id, name).unwrap();
+        record_change(conn, Entity::Folder, id, ChangeOp::Upsert).unwrap();
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+    }
+
+    fn shared_folder(a: &Connection, b: &Connection) -> String {
+        let id = db::insert_new_folder(a, "Projects", None).unwrap();
+        record_change(a, Entity::Folder, id, ChangeOp::Upsert).unwrap();
+        sync(b, a);
+        global_id(a, Entity::Folder, id).unwrap()
+    }
+
+    #[test]
+    fn sequential_edits_are_not_conflicts() {
+        let (a, b) = (device(), device());
+        let gid = shared_folder(&a, &b);
+        rename(&b, &gid, "Work");
+        let (_, pushed) = sync(&b, &a);
+        assert_eq!(pushed.applied, 1);
+        assert_eq!(pushed.conflicts, 0);
+        assert_eq!(folder_name(&a, &gid), "Work");
+    }
+
+    #[test]
+    fn concurrent_edits_converge_and_are_reported_on_both_sides() {
+        let (a, b) = (device(), device());
+        let gid = shared_folder(&a, &b);
+        rename(&a, &gid, "From A");
+        rename(&b, &gid, "From B");
+
+        let (pulled, pushed) = sync(&a, &b);
+        assert_eq!(pulled.conflicts + pushed.conflicts, 2);
+        // Exactly one side took the other's edit
+        assert_eq!(pulled.applied + pushed.applied, 1);
+        assert_eq!(folder_name(&a, &gid), folder_name(&b, &gid));
+
+        let winner = if device_id(&a).unwrap() > device_id(&b).unwrap() { "From A" } else { "From B" };
+        assert_eq!(folder_name(&a, &gid), winner);
+        assert_eq!(count(&a, "sync_conflicts"), 1);
+        assert_eq!(count(&b, "sync_conflicts"), 1);
+    }
+
+    #[test]
+    fn applying_a_batch_twice_changes_nothing() {
+        let (a, b) = (device(), device());
+        shared_folder(&a, &b);
+        let changes = changes_since(&a, &HashMap::new()).unwrap();
+        let report = apply_changes(&b, changes).unwrap();
+        assert_eq!(report.applied, 0);
+        assert_eq!(count(&b, "folders"), 1);
+    }
+
+    #[test]
+    fn failed_batch_is_not_logged_and_applies_on_retry() {
+        let a = device();
+        let folder = db::insert_new_folder(&a, "Cards", None).unwrap();
+        record_change(&a, Entity::Folder, folder, ChangeOp::Upsert).unwrap();
+        let doc = EditorDocument { time: 1, blocks: Vec::new(), version: "2.28.0".to_string() };
+        let document_id = db::save_document(&a, &doc, &folder).unwrap();
+        record_change(&a, Entity::Document, document_id, ChangeOp::Upsert).unwrap();
+        let review = FlashcardReview {
+            document_id,
+            block_id: "b1".to_string(),
+            card_path: "0".to_string(),
+            grade: 4,
+            reviewed_at: "2024-05-17T08:30:00Z".to_string(),
+        };
+        let review_id = db::save_flashcard_review(&a, &review).unwrap();
+        record_change(&a, Entity::FlashcardReview, review_id, ChangeOp::Upsert).unwrap();
+
+        // The review arrives without the document it belongs to
+        let b = device();
+        let all = changes_since(&a, &HashMap::new()).unwrap();
+        let review_only: Vec<Change> = all.iter().filter(|c| c.entity == Entity::FlashcardReview).cloned().collect();
+        assert!(apply_changes(&b, review_only).is_err());
+        assert_eq!(count(&b, "sync_changes"), 0);
+        assert!(vector(&b).unwrap().is_empty());
+
+        sync(&b, &a);
+        assert_eq!(count(&b, "flashcard_reviews"), 1);
+        assert_eq!(vector(&b).unwrap(), vector(&a).unwrap());
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::NaiveDate;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::AppHandle;
+
+use crate::db::{load_document, update_document, Document, EditorDocument};
+use crate::error::AppError;
+use crate::markdown::inline_to_text;
+use crate::search::folder_subtree;
+use crate::sync::{record_change, ChangeOp, Entity};
+use crate::{collab, events, vault, DB_PATH};
+
+// Tasks: every item of a `checklist` block, across the vault.
+//
+// Items are indexed into `tasks` with the block id and their position in the block,
+// and a due date written as `@2026-11-01` anywhere in the item text. The index is
+// brought up to date before every read by comparing document times with the ones
+// it was built from, so edits from peers, imports and the command-line tool are
+// picked up without listening for each of them. Task ids stay the same while the
+// item keeps its place in its block.
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Task {
+    pub id: i64,
+    pub document_id: i64,
+    pub document_title: String,
+    pub folder_id: Option<i64>,
+    pub block_id: String,
+    pub item_index: i64,
+    pub text: String,
+    pub checked: bool,
+    // YYYY-MM-DD
+    pub due: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TaskFilter {
+    #[serde(default)]
+    pub include_checked: bool,
+    // Inclusive bounds, YYYY-MM-DD; tasks without a due date only match without bounds
+    pub due_after: Option<String>,
+    pub due_before: Option<String>,
+    // Includes subfolders
+    pub folder_id: Option<i64>,
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tasks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            document_id INTEGER NOT NULL,
+            block_id TEXT NOT NULL,
+            block_index INTEGER NOT NULL,
+            item_index INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            checked INTEGER NOT NULL,
+            due TEXT,
+            UNIQUE(document_id, block_id, item_index)
+        )",
+        [],
+    )?;
+    conn.execute("CREATE INDEX IF NOT EXISTS tasks_due ON tasks (due)", [])?;
+    // Document time each document's tasks were indexed at
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS task_sources (
+            document_id INTEGER PRIMARY KEY,
+            time TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+// First `@YYYY-MM-DD` that is a real date and starts a word
+fn parse_due(text: &str) -> Option<String> {
+    text.match_indices('@').find_map(|(at, _)| {
+        let starts_word = text[..at].chars().next_back().map_or(true, char::is_whitespace);
+        let date = text.get(at + 1..at + 11)?;
+        let ends_word = text[at + 11..].chars().next().map_or(true, |c| !c.is_alphanumeric());
+        if !starts_word || !ends_word {
+            return None;
+        }
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").ok().map(|d| d.format("%Y-%m-%d").to_string())
+    })
+}
+
+fn checklist_items(doc: &EditorDocument) -> Vec<(usize, &str, usize, &Value)> {
+    let mut items = Vec::new();
+    for (block_index, block) in doc.blocks.iter().enumerate() {
+        if block.r#type != "checklist" {
+            continue;
+        }
+        if let Some(list) = block.data.get("items").and_then(Value::as_array) {
+            for (item_index, item) in list.iter().enumerate() {
+                items.push((block_index, block.id.as_str(), item_index, item));
+            }
+        }
+    }
+    items
+}
+
+fn item_text(item: &Value) -> String {
+    inline_to_text(item.get("text").and_then(Value::as_str).unwrap_or(""))
+}
+
+fn remove_document(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    conn.execute("DELETE FROM tasks WHERE document_id = ?", [document_id])?;
+    conn.execute("DELETE FROM task_sources WHERE document_id = ?", [document_id])?;
+    Ok(())
+}
+
+fn index_document(conn: &Connection, document_id: i64) -> Result<(), AppError> {
+    let doc = match load_document(conn, document_id) {
+        Ok(doc) => doc,
+        Err(AppError::SqliteError(rusqlite::Error::QueryReturnedNoRows)) => return remove_document(conn, document_id),
+        Err(e) => return Err(e),
+    };
+    // Unreadable content has no tasks; its time is still recorded so it isn't retried
+    let editor_doc: Option<EditorDocument> = serde_json::from_str(&doc.content).ok();
+    let items = editor_doc.as_ref().map(checklist_items).unwrap_or_default();
+
+    let mut kept: HashSet<i64> = HashSet::new();
+    for (block_index, block_id, item_index, item) in items {
+        let text = item_text(item);
+        let checked = item.get("checked").and_then(Value::as_bool).unwrap_or(false);
+        let id: i64 = conn.query_row(
+            "INSERT INTO tasks (document_id, block_id, block_index, item_index, text, checked, due)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(document_id, block_id, item_index) DO UPDATE SET
+                block_index = excluded.block_index, text = excluded.text,
+                checked = excluded.checked, due = excluded.due
+             RETURNING id",
+            params![document_id, block_id, block_index, item_index, vault::seal_text(&text)?, checked, parse_due(&text)],
+            |row| row.get(0),
+        )?;
+        kept.insert(id);
+    }
+
+    let mut stmt = conn.prepare("SELECT id FROM tasks WHERE document_id = ?")?;
+    let stale = stmt
+        .query_map([document_id], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    for id in stale.into_iter().filter(|id| !kept.contains(id)) {
+        conn.execute("DELETE FROM tasks WHERE id = ?", [id])?;
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO task_sources (document_id, time) VALUES (?, ?)",
+        params![document_id, doc.time],
+    )?;
+    Ok(())
+}
+
+// Reindexes documents whose time changed and drops tasks of deleted documents
+fn refresh(conn: &Connection) -> Result<(), AppError> {
+    let indexed: HashMap<i64, String> = {
+        let mut stmt = conn.prepare("SELECT document_id, time FROM task_sources")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<_, _>, rusqlite::Error>>()?;
+        rows
+    };
+    let current: Vec<(i64, String)> = {
+        let mut stmt = conn.prepare("SELECT id, time FROM documents")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        rows
+    };
+
+    let mut changed = 0;
+    for (id, time) in &current {
+        if indexed.get(id) != Some(time) {
+            index_document(conn, *id)?;
+            changed += 1;
+        }
+    }
+    let existing: HashSet<i64> = current.iter().map(|(id, _)| *id).collect();
+    for id in indexed.keys().filter(|id| !existing.contains(id)) {
+        remove_document(conn, *id)?;
+    }
+    if changed > 0 {
+        println!("Indexed tasks of {} documents", changed);
+    }
+    Ok(())
+}
+
+fn row_to_task(row: &rusqlite::Row) -> Result<Task, rusqlite::Error> {
+    let text = vault::open_text(&row.get::<_, String>(6)?)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(6, rusqlite::types::Type::Text, Box::new(e)))?;
+    Ok(Task {
+        id: row.get(0)?,
+        document_id: row.get(1)?,
+        document_title: inline_to_text(&row.get::<_, String>(2)?),
+        folder_id: row.get(3)?,
+        block_id: row.get(4)?,
+        item_index: row.get(5)?,
+        text,
+        checked: row.get(7)?,
+        due: row.get(8)?,
+    })
+}
+
+const TASK_COLUMNS: &str = "t.id, t.document_id, d.title, d.folder_id, t.block_id, t.item_index, t.text, t.checked, t.due
+     FROM tasks t JOIN documents d ON d.id = t.document_id";
+
+fn load_task(conn: &Connection, id: i64) -> Result<Task, AppError> {
+    Ok(conn.query_row(&format!("SELECT {} WHERE t.id = ?", TASK_COLUMNS), [id], row_to_task)?)
+}
+
+pub fn list_tasks(conn: &Connection, filter: &TaskFilter) -> Result<Vec<Task>, AppError> {
+    refresh(conn)?;
+    let folders = filter.folder_id.map(|id| folder_subtree(conn, id)).transpose()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+         WHERE (?1 OR t.checked = 0)
+           AND (?2 IS NULL OR t.due >= ?2)
+           AND (?3 IS NULL OR t.due <= ?3)
+         ORDER BY t.due IS NULL, t.due, t.document_id, t.block_index, t.item_index",
+        TASK_COLUMNS
+    ))?;
+    let tasks = stmt
+        .query_map(params![filter.include_checked, filter.due_after, filter.due_before], row_to_task)?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    Ok(tasks
+        .into_iter()
+        .filter(|task| folders.as_ref().map_or(true, |f| task.folder_id.map_or(false, |id| f.contains(&id))))
+        .collect())
+}
+
+// Writes the checked state into the checklist block the task came from and returns
+// the updated document; `None` toggles it
+fn write_checked(conn: &Connection, task_id: i64, checked: Option<bool>) -> Result<(Document, EditorDocument), AppError> {
+    let (document_id, block_id, item_index, stored): (i64, String, usize, String) = conn
+        .query_row(
+            "SELECT document_id, block_id, item_index, text FROM tasks WHERE id = ?",
+            [task_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?
+        .ok_or_else(|| AppError::NotFound(format!("task {}", task_id)))?;
+    let stored = vault::open_text(&stored)?;
+
+    let doc = load_document(conn, document_id)?;
+    let mut editor_doc: EditorDocument = serde_json::from_str(&doc.content)?;
+    let item = editor_doc
+        .blocks
+        .iter_mut()
+        .find(|block| block.id == block_id && block.r#type == "checklist")
+        .and_then(|block| block.data.get_mut("items"))
+        .and_then(|items| items.get_mut(item_index))
+        .filter(|item| item_text(item) == stored);
+    let item = match item {
+        Some(item) => item,
+        None => {
+            // Edited since it was listed; the caller should list again
+            index_document(conn, document_id)?;
+            return Err(AppError::NotFound(format!("task {}, it changed since it was listed", task_id)));
+        }
+    };
+    let checked = checked.unwrap_or_else(|| !item.get("checked").and_then(Value::as_bool).unwrap_or(false));
+    item["checked"] = Value::Bool(checked);
+    editor_doc.time = chrono::Utc::now().timestamp_millis();
+
+    let updated = Document {
+        time: editor_doc.time.to_string(),
+        content: serde_json::to_string(&editor_doc)?,
+        ..doc
+    };
+    update_document(conn, document_id, &updated)?;
+    record_change(conn, Entity::Document, document_id, ChangeOp::Upsert)?;
+    index_document(conn, document_id)?;
+    Ok((updated, editor_doc))
+}
+
+pub fn set_checked(app: &AppHandle, conn: &Connection, task_id: i64, checked: Option<bool>) -> Result<Task, AppError> {
+    let (updated, editor_doc) = write_checked(conn, task_id, checked)?;
+    collab::publish_local_update(app, updated.id, &editor_doc)?;
+    events::document_updated(app, events::DocumentUpdated {
+        id: updated.id,
+        title: updated.title,
+        time: updated.time,
+        folder_id: updated.folder_id,
+        source_window: None,
+    });
+    load_task(conn, task_id)
+}
+
+#[tauri::command]
+pub fn list_tasks_command(filter: Option<TaskFilter>) -> Result<Vec<Task>, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    let filter = filter.unwrap_or_default();
+    println!("list_tasks_command -> {:?}", filter);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    list_tasks(&conn, &filter).map_err(|e| e.to_string())
+}
+
+// Without `checked` the task is toggled
+#[tauri::command]
+pub fn set_task_checked_command(app: AppHandle, id: i64, checked: Option<bool>) -> Result<Task, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    println!("set_task_checked_command -> id: {}, checked: {:?}", id, checked);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    set_checked(&app, &conn, id, checked).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn rebuild_task_index_command() -> Result<(), String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    println!("rebuild_task_index_command");
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM task_sources", []).map_err(|e| e.to_string())?;
+    refresh(&conn).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{delete_document, insert_new_folder, save_document, Block};
+    use serde_json::json;
+
+    fn vault() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn).unwrap();
+        crate::settings::create_tables(&conn).unwrap();
+        crate::sync::create_tables(&conn).unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    fn checklist(time: i64, items: &[(&str, bool)]) -> EditorDocument {
+        let items: Vec<Value> = items.iter().map(|(text, checked)| json!({ "text": text, "checked": checked })).collect();
+        EditorDocument {
+            time,
+            blocks: vec![
+                Block { id: "h".to_string(), r#type: "header".to_string(), data: json!({ "text": "Todo", "level": 1 }) },
+                Block { id: "c".to_string(), r#type: "checklist".to_string(), data: json!({ "items": items }) },
+            ],
+            version: "2.30.5".to_string(),
+        }
+    }
+
+    fn items(conn: &Connection, id: i64) -> Value {
+        let doc: EditorDocument = serde_json::from_str(&load_document(conn, id).unwrap().content).unwrap();
+        doc.blocks[1].data["items"].clone()
+    }
+
+    fn edit(conn: &Connection, id: i64, doc: &EditorDocument) {
+        conn.execute(
+            "UPDATE documents SET time = ?, content = ? WHERE id = ?",
+            params![doc.time.to_string(), serde_json::to_string(doc).unwrap(), id],
+        )
+        .unwrap();
+    }
+
+    fn texts(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|t| t.text.as_str()).collect()
+    }
+
+    #[test]
+    fn due_dates_must_be_real_dates_starting_a_word() {
+        assert_eq!(parse_due("call @2026-11-01"), Some("2026-11-01".to_string()));
+        assert_eq!(parse_due("@2026-11-01, then rest"), Some("2026-11-01".to_string()));
+        assert_eq!(parse_due("mail me@2026-11-01"), None);
+        assert_eq!(parse_due("@2026-02-30 @2026-03-01"), Some("2026-03-01".to_string()));
+        assert_eq!(parse_due("@2026-11-012"), None);
+        assert_eq!(parse_due("no date"), None);
+    }
+
+    #[test]
+    fn lists_open_tasks_by_due_date_and_filters() {
+        let conn = vault();
+        let folder = insert_new_folder(&conn, "Work", None).unwrap();
+        let other = insert_new_folder(&conn, "Home", None).unwrap();
+        save_document(&conn, &checklist(1, &[("later @2026-12-01", false), ("done", true), ("sometime", false)]), &folder).unwrap();
+        save_document(&conn, &checklist(1, &[("soon @2026-11-01", false)]), &other).unwrap();
+
+        let open = list_tasks(&conn, &TaskFilter::default()).unwrap();
+        assert_eq!(texts(&open), vec!["soon @2026-11-01", "later @2026-12-01", "sometime"]);
+        assert_eq!(open[0].due.as_deref(), Some("2026-11-01"));
+
+        let all = TaskFilter { include_checked: true, ..TaskFilter::default() };
+        assert_eq!(list_tasks(&conn, &all).unwrap().len(), 4);
+
+        let november = TaskFilter { due_before: Some("2026-11-30".to_string()), ..TaskFilter::default() };
+        assert_eq!(texts(&list_tasks(&conn, &november).unwrap()), vec!["soon @2026-11-01"]);
+
+        let work = TaskFilter { folder_id: Some(folder), ..TaskFilter::default() };
+        assert_eq!(texts(&list_tasks(&conn, &work).unwrap()), vec!["later @2026-12-01", "sometime"]);
+    }
+
+    #[test]
+    fn index_follows_edits_and_deletes() {
+        let conn = vault();
+        let folder = insert_new_folder(&conn, "Work", None).unwrap();
+        let id = save_document(&conn, &checklist(1, &[("first", false), ("second", false)]), &folder).unwrap();
+        let before = list_tasks(&conn, &TaskFilter::default()).unwrap();
+
+        edit(&conn, id, &checklist(2, &[("first", true), ("second, reworded", false)]));
+        let after = list_tasks(&conn, &TaskFilter::default()).unwrap();
+        assert_eq!(texts(&after), vec!["second, reworded"]);
+        // Same place in the same block, same id
+        assert_eq!(after[0].id, before[1].id);
+
+        delete_document(&conn, id).unwrap();
+        assert!(list_tasks(&conn, &TaskFilter { include_checked: true, ..TaskFilter::default() }).unwrap().is_empty());
+        let indexed: i64 = conn.query_row("SELECT COUNT(*) FROM tasks", [], |row| row.get(0)).unwrap();
+        assert_eq!(indexed, 0);
+    }
+
+    #[test]
+    fn toggling_writes_into_the_checklist_block() {
+        let conn = vault();
+        let folder = insert_new_folder(&conn, "Work", None).unwrap();
+        let id = save_document(&conn, &checklist(1, &[("first", false), ("second", false)]), &folder).unwrap();
+        let second = list_tasks(&conn, &TaskFilter::default()).unwrap()[1].id;
+
+        let (updated, _) = write_checked(&conn, second, None).unwrap();
+        assert!(updated.time.parse::<i64>().unwrap() > 1);
+        assert_eq!(load_document(&conn, id).unwrap().time, updated.time);
+        assert_eq!(items(&conn, id), json!([{ "text": "first", "checked": false }, { "text": "second", "checked": true }]));
+        assert!(load_task(&conn, second).unwrap().checked);
+        assert_eq!(texts(&list_tasks(&conn, &TaskFilter::default()).unwrap()), vec!["first"]);
+        let logged: i64 = conn.query_row("SELECT COUNT(*) FROM sync_changes", [], |row| row.get(0)).unwrap();
+        assert_eq!(logged, 1);
+
+        // Toggling again unchecks
+        write_checked(&conn, second, None).unwrap();
+        assert_eq!(items(&conn, id)[1]["checked"], json!(false));
+    }
+
+    #[test]
+    fn explicit_states_set_rather_than_toggle() {
+        let conn = vault();
+        let folder = insert_new_folder(&conn, "Work", None).unwrap();
+        let id = save_document(&conn, &checklist(1, &[("done", true)]), &folder).unwrap();
+        let all = TaskFilter { include_checked: true, ..TaskFilter::default() };
+        let task = list_tasks(&conn, &all).unwrap()[0].id;
+
+        write_checked(&conn, task, Some(true)).unwrap();
+        assert_eq!(items(&conn, id)[0]["checked"], json!(true));
+        write_checked(&conn, task, Some(false)).unwrap();
+        assert_eq!(items(&conn, id)[0]["checked"], json!(false));
+        write_checked(&conn, task, Some(false)).unwrap();
+        assert!(!load_task(&conn, task).unwrap().checked);
+    }
+
+    #[test]
+    fn stale_tasks_are_not_found_and_reindexed() {
+        let conn = vault();
+        let folder = insert_new_folder(&conn, "Work", None).unwrap();
+        let id = save_document(&conn, &checklist(1, &[("call the bank", false)]), &folder).unwrap();
+        let task = list_tasks(&conn, &TaskFilter::default()).unwrap()[0].id;
+
+        edit(&conn, id, &checklist(2, &[("email the bank", false)]));
+        assert!(matches!(write_checked(&conn, task, None), Err(AppError::NotFound(_))));
+        // The document is left alone and the index already holds the new text
+        assert_eq!(items(&conn, id), json!([{ "text": "email the bank", "checked": false }]));
+        let stored: String = conn.query_row("SELECT text FROM tasks", [], |row| row.get(0)).unwrap();
+        assert_eq!(stored, "email the bank");
+
+        assert!(matches!(write_checked(&conn, 999, None), Err(AppError::NotFound(_))));
+    }
+}
@@ -0,0 +1,403 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::AppHandle;
+
+use crate::capture::top_level_folder;
+use crate::db::{load_document, save_document, save_document_in, Block, EditorDocument};
+use crate::error::AppError;
+use crate::markdown::{escape_html, inline_to_text, markdown_to_document, new_block_id};
+use crate::settings::{get_json, set_json};
+use crate::sync::{record_change, ChangeOp, Entity};
+use crate::{events, vault, DB_PATH};
+
+// Templates and daily notes.
+//
+// Any document can be marked as a template. Creating a note from it copies its blocks
+// with fresh block ids and expands placeholders in every text:
+//
+//     {{date}}           today, 2024-05-17
+//     {{date:%A %d %B}}  today in a chrono format, Friday 17 May
+//     {{time}}           now, 08:30
+//     {{title}}          the title asked for when creating the note
+//     {{folder}}         name of the folder the note is created in
+//
+// Unknown placeholders are left as they are. The daily note is found through the
+// `daily_notes` table, so renaming today's note doesn't create a second one.
+
+const DAILY_CONFIG_KEY: &str = "templates.daily";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DailyNoteConfig {
+    // Top-level folder, created on first use
+    pub folder: String,
+    pub template_id: Option<i64>,
+    // chrono format of the note title, also its `{{title}}`
+    pub title_format: String,
+}
+
+impl Default for DailyNoteConfig {
+    fn default() -> Self {
+        DailyNoteConfig { folder: "Journal".to_string(), template_id: None, title_format: "%Y-%m-%d".to_string() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Template {
+    pub document_id: i64,
+    pub name: String,
+    pub title: String,
+    pub folder_id: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DailyNote {
+    pub document_id: i64,
+    pub created: bool,
+}
+
+// Values for the placeholders of one expansion
+struct Context<'a> {
+    now: DateTime<Local>,
+    title: &'a str,
+    folder: &'a str,
+}
+
+pub fn create_tables(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS document_templates (
+            document_id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            FOREIGN KEY(document_id) REFERENCES documents(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS daily_notes (
+            day TEXT PRIMARY KEY,
+            document_id INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn load_config(conn: &Connection) -> Result<DailyNoteConfig, AppError> {
+    get_json(conn, DAILY_CONFIG_KEY, DailyNoteConfig::default())
+}
+
+fn placeholder(name: &str, context: &Context) -> Option<String> {
+    // Inserted into block HTML, so user-provided values are escaped
+    match name.trim() {
+        "date" => Some(context.now.format("%Y-%m-%d").to_string()),
+        "time" => Some(context.now.format("%H:%M").to_string()),
+        "title" => Some(escape_html(context.title)),
+        "folder" => Some(escape_html(context.folder)),
+        other => format_date(&context.now, other.strip_prefix("date:")?).map(|date| escape_html(&date)),
+    }
+}
+
+// chrono panics when displaying an invalid format, so it is checked first
+fn format_date(now: &DateTime<Local>, format: &str) -> Option<String> {
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return None;
+    }
+    Some(now.format_with_items(items.into_iter()).to_string())
+}
+
+fn expand_text(text: &str, context: &Context) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                match placeholder(&after[..end], context) {
+                    Some(value) => out.push_str(&value),
+                    None => out.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Every string inside the block data: paragraphs, list items, table cells, ...
+fn expand_value(value: &mut Value, context: &Context) {
+    match value {
+        Value::String(text) => *text = expand_text(text, context),
+        Value::Array(items) => items.iter_mut().for_each(|item| expand_value(item, context)),
+        Value::Object(fields) => fields.values_mut().for_each(|field| expand_value(field, context)),
+        _ => {}
+    }
+}
+
+fn instantiate(template: &EditorDocument, context: &Context) -> EditorDocument {
+    let mut blocks: Vec<Block> = template
+        .blocks
+        .iter()
+        .map(|block| {
+            let mut data = block.data.clone();
+            expand_value(&mut data, context);
+            Block { id: new_block_id(), r#type: block.r#type.clone(), data }
+        })
+        .collect();
+    // The document title comes from its first block
+    let has_title = blocks.first().map_or(false, |block| block.r#type == "header");
+    if !has_title && !context.title.is_empty() {
+        blocks.insert(0, Block {
+            id: new_block_id(),
+            r#type: "header".to_string(),
+            data: json!({ "text": escape_html(context.title), "level": 1 }),
+        });
+    }
+    EditorDocument { time: context.now.timestamp_millis(), blocks, version: template.version.clone() }
+}
+
+fn folder_name(conn: &Connection, folder_id: Option<i64>) -> Result<String, AppError> {
+    let name = match folder_id {
+        Some(id) => conn.query_row("SELECT name FROM folders WHERE id = ?", [id], |row| row.get(0)).optional()?,
+        None => None,
+    };
+    Ok(name.unwrap_or_default())
+}
+
+pub fn create_from_template(
+    app: &AppHandle,
+    conn: &Connection,
+    template_id: i64,
+    title: &str,
+    folder_id: Option<i64>,
+) -> Result<i64, AppError> {
+    let template = load_document(conn, template_id)?;
+    let template_doc: EditorDocument = serde_json::from_str(&template.content)?;
+    let folder = folder_name(conn, folder_id)?;
+    let doc = instantiate(&template_doc, &Context { now: Local::now(), title, folder: &folder });
+
+    let id = save_document_in(conn, &doc, folder_id)?;
+    record_change(conn, Entity::Document, id, ChangeOp::Upsert)?;
+    let saved = load_document(conn, id)?;
+    println!("Created document {} from template {}", id, template_id);
+    events::document_created(app, events::DocumentCreated { id, title: saved.title, folder_id: saved.folder_id });
+    Ok(id)
+}
+
+fn list_templates(conn: &Connection) -> Result<Vec<Template>, AppError> {
+    let mut stmt = conn.prepare(
+        "SELECT t.document_id, t.name, d.title, d.folder_id
+         FROM document_templates t JOIN documents d ON d.id = t.document_id
+         ORDER BY t.name COLLATE NOCASE",
+    )?;
+    let templates = stmt
+        .query_map([], |row| {
+            Ok(Template {
+                document_id: row.get(0)?,
+                name: row.get(1)?,
+                title: inline_to_text(&row.get::<_, String>(2)?),
+                folder_id: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    Ok(templates)
+}
+
+pub fn daily_note(app: &AppHandle, conn: &Connection) -> Result<DailyNote, AppError> {
+    let config = load_config(conn)?;
+    let now = Local::now();
+    let day = now.format("%Y-%m-%d").to_string();
+
+    let existing: Option<i64> = conn
+        .query_row(
+            "SELECT n.document_id FROM daily_notes n JOIN documents d ON d.id = n.document_id WHERE n.day = ?",
+            [&day],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(document_id) = existing {
+        return Ok(DailyNote { document_id, created: false });
+    }
+
+    let title = format_date(&now, &config.title_format)
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or_else(|| day.clone());
+    let folder_id = top_level_folder(app, conn, &config.folder)?;
+    let document_id = match config.template_id {
+        Some(template_id) => create_from_template(app, conn, template_id, &title, Some(folder_id))?,
+        None => {
+            let context = Context { now, title: &title, folder: &config.folder };
+            // Just the title heading
+            let doc = instantiate(&markdown_to_document("", 0), &context);
+            let id = save_document(conn, &doc, &folder_id)?;
+            record_change(conn, Entity::Document, id, ChangeOp::Upsert)?;
+            let saved = load_document(conn, id)?;
+            events::document_created(app, events::DocumentCreated { id, title: saved.title, folder_id: saved.folder_id });
+            id
+        }
+    };
+    conn.execute(
+        "INSERT OR REPLACE INTO daily_notes (day, document_id) VALUES (?, ?)",
+        params![day, document_id],
+    )?;
+    Ok(DailyNote { document_id, created: true })
+}
+
+pub fn start(app: AppHandle) {
+    events::listen(&app, events::DOCUMENT_DELETED, |e: events::DocumentDeleted| {
+        let result = Connection::open(DB_PATH).and_then(|conn| {
+            conn.execute("DELETE FROM document_templates WHERE document_id = ?", [e.id])?;
+            conn.execute("DELETE FROM daily_notes WHERE document_id = ?", [e.id])
+        });
+        if let Err(err) = result {
+            eprintln!("Failed to forget deleted template or daily note: {}", err);
+        }
+    });
+}
+
+#[tauri::command]
+pub fn list_templates_command() -> Result<Vec<Template>, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    list_templates(&conn).map_err(|e| e.to_string())
+}
+
+// Marks a document as a template; `name` defaults to the document title
+#[tauri::command]
+pub fn mark_template_command(id: i64, name: Option<String>) -> Result<(), String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    println!("mark_template_command -> id: {}", id);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let doc = load_document(&conn, id).map_err(|e| e.to_string())?;
+    let name = name.filter(|n| !n.trim().is_empty()).unwrap_or_else(|| inline_to_text(&doc.title));
+    conn.execute(
+        "INSERT OR REPLACE INTO document_templates (document_id, name) VALUES (?, ?)",
+        params![id, name],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn unmark_template_command(id: i64) -> Result<(), String> {
+    println!("unmark_template_command -> id: {}", id);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM document_templates WHERE document_id = ?", [id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn create_from_template_command(
+    app: AppHandle,
+    template_id: i64,
+    title: Option<String>,
+    folder_id: Option<i64>,
+) -> Result<i64, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    println!("create_from_template_command -> template: {}, folder: {:?}", template_id, folder_id);
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    create_from_template(&app, &conn, template_id, title.as_deref().unwrap_or_default(), folder_id)
+        .map_err(|e| e.to_string())
+}
+
+// Today's note in the configured folder, created from the configured template if needed
+#[tauri::command]
+pub fn daily_note_command(app: AppHandle) -> Result<DailyNote, String> {
+    vault::guard().map_err(|e| e.to_string())?;
+    println!("daily_note_command");
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    daily_note(&app, &conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_daily_note_config_command() -> Result<DailyNoteConfig, String> {
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    load_config(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_daily_note_config_command(config: DailyNoteConfig) -> Result<(), String> {
+    println!("set_daily_note_config_command -> {:?}", config);
+    if config.folder.trim().is_empty() {
+        return Err("Daily notes folder must not be empty".to_string());
+    }
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    if let Some(template_id) = config.template_id {
+        let is_template: bool = conn
+            .query_row("SELECT COUNT(*) > 0 FROM document_templates WHERE document_id = ?", [template_id], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if !is_template {
+            return Err(format!("Document {} is not a template", template_id));
+        }
+    }
+    set_json(&conn, DAILY_CONFIG_KEY, &config).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context<'a>(title: &'a str, folder: &'a str) -> Context<'a> {
+        Context { now: Local.with_ymd_and_hms(2024, 5, 17, 8, 30, 0).unwrap(), title, folder }
+    }
+
+    #[test]
+    fn expands_known_placeholders_and_keeps_the_rest() {
+        let context = context("Standup", "Work");
+        assert_eq!(
+            expand_text("{{date}} {{ time }} {{title}} in {{folder}}", &context),
+            "2024-05-17 08:30 Standup in Work"
+        );
+        assert_eq!(expand_text("{{date:%A %d %B}}", &context), "Friday 17 May");
+        assert_eq!(expand_text("{{unknown}} and {{date", &context), "{{unknown}} and {{date");
+        assert_eq!(expand_text("{{date:%Q}}", &context), "{{date:%Q}}");
+    }
+
+    #[test]
+    fn escapes_user_provided_values() {
+        let context = context("<b>Plan</b> & more", "R&D");
+        assert_eq!(expand_text("{{title}} / {{folder}}", &context), "&lt;b&gt;Plan&lt;/b&gt; &amp; more / R&amp;D");
+    }
+
+    #[test]
+    fn invalid_date_formats_are_rejected() {
+        let now = Local.with_ymd_and_hms(2024, 5, 17, 8, 30, 0).unwrap();
+        assert_eq!(format_date(&now, "%Y/%m"), Some("2024/05".to_string()));
+        assert_eq!(format_date(&now, "%"), None);
+    }
+
+    #[test]
+    fn instantiate_gives_fresh_ids_and_a_title() {
+        let template = EditorDocument {
+            time: 1,
+            blocks: vec![Block {
+                id: "tmpl".to_string(),
+                r#type: "checklist".to_string(),
+                data: json!({ "items": [{ "text": "Review {{title}}", "checked": false }] }),
+            }],
+            version: "2.30.5".to_string(),
+        };
+        let doc = instantiate(&template, &context("Week 20", ""));
+        assert_eq!(doc.blocks.len(), 2);
+        assert_eq!(doc.blocks[0].r#type, "header");
+        assert_eq!(doc.blocks[0].data["text"], "Week 20");
+        assert_ne!(doc.blocks[1].id, "tmpl");
+        assert_eq!(doc.blocks[1].data["items"][0]["text"], "Review Week 20");
+        assert_eq!(doc.blocks[1].data["items"][0]["checked"], false);
+
+        // A template with its own header keeps it as the title
+        let titled = instantiate(&doc, &context("Other", ""));
+        assert_eq!(titled.blocks.len(), 2);
+        assert_eq!(titled.blocks[0].data["text"], "Week 20");
+    }
+}
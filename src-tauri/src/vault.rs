@@ -0,0 +1,541 @@
+use std::fs;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use hmac::{Hmac, Mac};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tauri::AppHandle;
+
+use crate::crypto::{self, KEY_LEN};
+use crate::error::AppError;
+use crate::settings::{get_json, set_json};
+use crate::{events, git_mirror, search, DB_PATH};
+
+// Optional encryption of the vault at rest.
+//
+// Note bodies are sealed per row with a random data key; the data key itself is
+// stored wrapped by a key derived from the passphrase with Argon2id, so changing
+// the passphrase only rewraps it. Titles, folders and timestamps stay readable so
+// the database keeps working for lists and sync bookkeeping, everything that holds
+// note text is encrypted: `documents.content`, the sync change log payloads and
+// conflict reports, and the CRDT state of shared documents.
+//
+// The unlocked key lives in process-wide statics instead of managed state because
+// `db.rs`, the sync server and the background services read documents without an
+// `AppHandle`.
+
+const CONFIG_KEY: &str = "vault.config";
+// Marks sealed column values; anything else is plaintext from before encryption
+const PREFIX: &str = "vault:v1:";
+const BACKUP_FORMAT: &str = "j_desktop-backup";
+
+const ENCRYPTED_COLUMNS: &[(&str, &str)] = &[
+    ("documents", "content"),
+    ("sync_changes", "payload"),
+    ("sync_conflicts", "winner"),
+    ("sync_conflicts", "loser"),
+    ("document_crdt", "state"),
+    ("embedding_chunks", "text"),
+    ("chat_conversations", "title"),
+    ("chat_messages", "content"),
+    ("document_summaries", "summary"),
+    ("related_cache", "results"),
+    ("tasks", "text"),
+];
+
+struct State {
+    enabled: AtomicBool,
+    auto_lock_minutes: AtomicU64,
+    key: Mutex<Option<[u8; KEY_LEN]>>,
+    last_activity: Mutex<Option<Instant>>,
+}
+
+impl State {
+    const fn new() -> Self {
+        State {
+            enabled: AtomicBool::new(false),
+            auto_lock_minutes: AtomicU64::new(0),
+            key: Mutex::new(None),
+            last_activity: Mutex::new(None),
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn state() -> &'static State {
+    static STATE: State = State::new();
+    &STATE
+}
+
+// Tests run on parallel threads and each gets its own vault, so a test that turns
+// encryption on doesn't lock the others out of their in-memory databases
+#[cfg(test)]
+fn state() -> &'static State {
+    thread_local! {
+        static STATE: &'static State = Box::leak(Box::new(State::new()));
+    }
+    STATE.with(|state| *state)
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct KdfParams {
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct VaultConfig {
+    kdf: KdfParams,
+    // Data key sealed with the passphrase key, hex encoded
+    wrapped_key: String,
+    // 0 disables auto-lock
+    auto_lock_minutes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VaultStatus {
+    pub enabled: bool,
+    pub locked: bool,
+    pub auto_lock_minutes: u64,
+}
+
+// First line of a backup file; the sealed database follows the newline
+#[derive(Serialize, Deserialize, Debug)]
+struct BackupHeader {
+    format: String,
+    version: u32,
+    kdf: KdfParams,
+}
+
+impl KdfParams {
+    fn generate() -> Self {
+        let salt: [u8; 16] = rand::random();
+        KdfParams {
+            salt: hex::encode(salt),
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<[u8; KEY_LEN], AppError> {
+        let salt = hex::decode(&self.salt).map_err(|e| AppError::CryptoError(e.to_string()))?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_LEN))
+            .map_err(|e| AppError::CryptoError(e.to_string()))?;
+        let mut key = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| AppError::CryptoError(e.to_string()))?;
+        Ok(key)
+    }
+}
+
+fn load_config(conn: &Connection) -> Result<Option<VaultConfig>, AppError> {
+    get_json(conn, CONFIG_KEY, None)
+}
+
+fn unwrap_key(config: &VaultConfig, passphrase: &str) -> Result<[u8; KEY_LEN], AppError> {
+    let kek = config.kdf.derive(passphrase)?;
+    let wrapped = hex::decode(&config.wrapped_key).map_err(|e| AppError::CryptoError(e.to_string()))?;
+    let key = crypto::open(&kek, &wrapped).map_err(|_| AppError::CryptoError("Wrong passphrase".to_string()))?;
+    key.try_into()
+        .map_err(|_| AppError::CryptoError("Stored vault key is corrupted".to_string()))
+}
+
+fn wrap_key(key: &[u8; KEY_LEN], passphrase: &str) -> Result<(KdfParams, String), AppError> {
+    let kdf = KdfParams::generate();
+    let wrapped = crypto::seal(&kdf.derive(passphrase)?, key)?;
+    Ok((kdf, hex::encode(wrapped)))
+}
+
+// Reads the vault configuration at startup. An encrypted vault always starts locked.
+pub fn init(conn: &Connection) -> Result<(), AppError> {
+    let config = load_config(conn)?;
+    state().enabled.store(config.is_some(), Ordering::SeqCst);
+    state().auto_lock_minutes.store(config.map_or(0, |c| c.auto_lock_minutes), Ordering::SeqCst);
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    state().enabled.load(Ordering::SeqCst)
+}
+
+pub fn is_locked() -> bool {
+    state().enabled.load(Ordering::SeqCst) && state().key.lock().unwrap().is_none()
+}
+
+// Called at the start of every command; also counts as activity for auto-lock
+pub fn guard() -> Result<(), AppError> {
+    if is_locked() {
+        return Err(AppError::VaultLocked);
+    }
+    *state().last_activity.lock().unwrap() = Some(Instant::now());
+    Ok(())
+}
+
+fn current_key() -> Result<[u8; KEY_LEN], AppError> {
+    state().key.lock().unwrap().ok_or(AppError::VaultLocked)
+}
+
+fn seal_with(key: &[u8; KEY_LEN], plaintext: &str) -> Result<String, AppError> {
+    Ok(format!("{}{}", PREFIX, hex::encode(crypto::seal(key, plaintext.as_bytes())?)))
+}
+
+// Value to store in an encrypted column; plaintext when encryption is off
+pub fn seal_text(plaintext: &str) -> Result<String, AppError> {
+    if !state().enabled.load(Ordering::SeqCst) {
+        return Ok(plaintext.to_string());
+    }
+    seal_with(&current_key()?, plaintext)
+}
+
+// Inverse of `seal_text`; values written before encryption was enabled pass through
+pub fn open_text(stored: &str) -> Result<String, AppError> {
+    let sealed = match stored.strip_prefix(PREFIX) {
+        Some(sealed) => sealed,
+        None => return Ok(stored.to_string()),
+    };
+    let bytes = hex::decode(sealed).map_err(|e| AppError::CryptoError(e.to_string()))?;
+    let plaintext = crypto::open(&current_key()?, &bytes)?;
+    String::from_utf8(plaintext).map_err(|e| AppError::CryptoError(e.to_string()))
+}
+
+// Stands in for a word in the search index: the word itself, or a keyed digest of it
+// when encryption is on. Equal words give equal tokens, which is all the index needs.
+pub fn search_token(word: &str) -> Result<String, AppError> {
+    if !state().enabled.load(Ordering::SeqCst) {
+        return Ok(word.to_string());
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(&current_key()?).expect("HMAC accepts any key length");
+    mac.update(b"search:");
+    mac.update(word.as_bytes());
+    Ok(hex::encode(&mac.finalize().into_bytes()[..16]))
+}
+
+fn encrypt_existing(conn: &Connection, key: &[u8; KEY_LEN]) -> Result<usize, AppError> {
+    let mut sealed = 0;
+    for (table, column) in ENCRYPTED_COLUMNS {
+        let rows = {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT rowid, {column} FROM {table} WHERE {column} IS NOT NULL AND {column} NOT LIKE '{PREFIX}%'"
+            ))?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+            rows
+        };
+        for (rowid, value) in rows {
+            conn.execute(
+                &format!("UPDATE {table} SET {column} = ? WHERE rowid = ?"),
+                params![seal_with(key, &value)?, rowid],
+            )?;
+            sealed += 1;
+        }
+    }
+    Ok(sealed)
+}
+
+// Drops the unlocked key; false if the vault was not unlocked
+fn forget_key() -> bool {
+    state().key.lock().unwrap().take().is_some()
+}
+
+pub fn lock(app: &AppHandle, auto: bool) {
+    if forget_key() {
+        println!("Vault locked{}", if auto { " after inactivity" } else { "" });
+        events::vault_locked(app, events::VaultLocked { auto });
+    }
+}
+
+pub fn start_auto_lock(app: AppHandle) {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(15));
+        let minutes = state().auto_lock_minutes.load(Ordering::SeqCst);
+        if minutes == 0 || is_locked() || !state().enabled.load(Ordering::SeqCst) {
+            continue;
+        }
+        let idle = state().last_activity.lock().unwrap().map_or(true, |last| {
+            last.elapsed() >= Duration::from_secs(minutes * 60)
+        });
+        if idle {
+            lock(&app, true);
+        }
+    });
+}
+
+#[tauri::command]
+pub fn vault_status_command() -> Result<VaultStatus, String> {
+    Ok(VaultStatus {
+        enabled: state().enabled.load(Ordering::SeqCst),
+        locked: is_locked(),
+        auto_lock_minutes: state().auto_lock_minutes.load(Ordering::SeqCst),
+    })
+}
+
+// Turns on encryption and seals every existing note with a new data key.
+// Returns the number of rows sealed.
+pub fn enable(conn: &mut Connection, passphrase: &str, auto_lock_minutes: u64) -> Result<usize, AppError> {
+    if passphrase.is_empty() {
+        return Err(AppError::InvalidInput("Passphrase must not be empty".to_string()));
+    }
+    if load_config(conn)?.is_some() {
+        return Err(AppError::InvalidInput("Vault encryption is already enabled".to_string()));
+    }
+    if git_mirror::is_enabled(conn)? {
+        return Err(AppError::InvalidInput(git_mirror::ENCRYPTION_CONFLICT.to_string()));
+    }
+
+    let key = crypto::generate_key();
+    let (kdf, wrapped_key) = wrap_key(&key, passphrase)?;
+    let config = VaultConfig { kdf, wrapped_key, auto_lock_minutes };
+
+    let tx = conn.transaction()?;
+    let sealed = encrypt_existing(&tx, &key)?;
+    // Indexed in plaintext so far; rebuilt with digests on the next search
+    search::clear_index(&tx)?;
+    set_json(&tx, CONFIG_KEY, &config)?;
+    tx.commit()?;
+
+    *state().key.lock().unwrap() = Some(key);
+    *state().last_activity.lock().unwrap() = Some(Instant::now());
+    state().auto_lock_minutes.store(auto_lock_minutes, Ordering::SeqCst);
+    state().enabled.store(true, Ordering::SeqCst);
+    Ok(sealed)
+}
+
+#[tauri::command]
+pub fn enable_vault_encryption_command(passphrase: String, auto_lock_minutes: u64) -> Result<(), String> {
+    println!("enable_vault_encryption_command");
+    let mut conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let sealed = enable(&mut conn, &passphrase, auto_lock_minutes).map_err(|e| e.to_string())?;
+    println!("Vault encryption enabled, sealed {} rows", sealed);
+    Ok(())
+}
+
+pub fn unlock(conn: &Connection, passphrase: &str) -> Result<(), AppError> {
+    let config = load_config(conn)?
+        .ok_or_else(|| AppError::CryptoError("Vault encryption is not enabled".to_string()))?;
+    let key = unwrap_key(&config, passphrase)?;
+
+    *state().key.lock().unwrap() = Some(key);
+    *state().last_activity.lock().unwrap() = Some(Instant::now());
+    Ok(())
+}
+
+#[tauri::command]
+pub fn unlock_vault_command(app: AppHandle, passphrase: String) -> Result<(), String> {
+    println!("unlock_vault_command");
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    unlock(&conn, &passphrase).map_err(|e| e.to_string())?;
+    events::vault_unlocked(&app, events::VaultUnlocked {});
+    Ok(())
+}
+
+#[tauri::command]
+pub fn lock_vault_command(app: AppHandle) -> Result<(), String> {
+    println!("lock_vault_command");
+    lock(&app, false);
+    Ok(())
+}
+
+// Rewraps the data key; sealed rows stay as they are
+pub fn change_passphrase(conn: &Connection, current: &str, new_passphrase: &str) -> Result<(), AppError> {
+    if new_passphrase.is_empty() {
+        return Err(AppError::InvalidInput("Passphrase must not be empty".to_string()));
+    }
+    let config = load_config(conn)?
+        .ok_or_else(|| AppError::CryptoError("Vault encryption is not enabled".to_string()))?;
+    let key = unwrap_key(&config, current)?;
+
+    let (kdf, wrapped_key) = wrap_key(&key, new_passphrase)?;
+    let config = VaultConfig { kdf, wrapped_key, ..config };
+    set_json(conn, CONFIG_KEY, &config)
+}
+
+#[tauri::command]
+pub fn change_vault_passphrase_command(current: String, new_passphrase: String) -> Result<(), String> {
+    println!("change_vault_passphrase_command");
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    change_passphrase(&conn, &current, &new_passphrase).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_vault_auto_lock_command(minutes: u64) -> Result<(), String> {
+    guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    let config = load_config(&conn)
+        .map_err(|e| e.to_string())?
+        .ok_or("Vault encryption is not enabled")?;
+    let config = VaultConfig { auto_lock_minutes: minutes, ..config };
+    set_json(&conn, CONFIG_KEY, &config).map_err(|e| e.to_string())?;
+    state().auto_lock_minutes.store(minutes, Ordering::SeqCst);
+    Ok(())
+}
+
+// Writes a consistent copy of the whole database, sealed with a key derived from
+// `passphrase`. Works for plaintext vaults too; encrypted rows stay encrypted inside.
+pub fn write_backup(conn: &Connection, path: &str, passphrase: &str) -> Result<(), AppError> {
+    if passphrase.is_empty() {
+        return Err(AppError::CryptoError("Passphrase must not be empty".to_string()));
+    }
+
+    let snapshot = std::env::temp_dir().join(format!("j_desktop-backup-{:016x}.db", rand::random::<u64>()));
+    conn.execute("VACUUM INTO ?", [snapshot.to_string_lossy()])?;
+    let bytes = fs::read(&snapshot);
+    let _ = fs::remove_file(&snapshot);
+    let bytes = bytes?;
+
+    let kdf = KdfParams::generate();
+    let sealed = crypto::seal(&kdf.derive(passphrase)?, &bytes)?;
+    let header = BackupHeader { format: BACKUP_FORMAT.to_string(), version: 1, kdf };
+
+    let mut file = fs::File::create(path)?;
+    serde_json::to_writer(&mut file, &header)?;
+    file.write_all(b"\n")?;
+    file.write_all(&sealed)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn export_vault_backup_command(path: String, passphrase: String) -> Result<(), String> {
+    println!("export_vault_backup_command -> path: {}", path);
+    guard().map_err(|e| e.to_string())?;
+    let conn = Connection::open(DB_PATH).map_err(|e| e.to_string())?;
+    write_backup(&conn, &path, &passphrase).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{insert_new_folder, load_document_for_editor, save_document, Block, EditorDocument};
+    use serde_json::json;
+
+    fn vault() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn).unwrap();
+        crate::settings::create_tables(&conn).unwrap();
+        crate::collab::create_tables(&conn).unwrap();
+        crate::sync::create_tables(&conn).unwrap();
+        crate::ai::create_tables(&conn).unwrap();
+        crate::summaries::create_tables(&conn).unwrap();
+        crate::related::create_tables(&conn).unwrap();
+        crate::tasks::create_tables(&conn).unwrap();
+        search::create_tables(&conn).unwrap();
+        init(&conn).unwrap();
+        conn
+    }
+
+    fn note(conn: &Connection, text: &str) -> i64 {
+        let folder = insert_new_folder(conn, "Notes", None).unwrap();
+        let doc = EditorDocument {
+            time: 1,
+            blocks: vec![Block { id: "p".to_string(), r#type: "paragraph".to_string(), data: json!({ "text": text }) }],
+            version: "2.30.5".to_string(),
+        };
+        save_document(conn, &doc, &folder).unwrap()
+    }
+
+    fn stored_content(conn: &Connection, id: i64) -> String {
+        conn.query_row("SELECT content FROM documents WHERE id = ?", [id], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn wrapped_key_opens_only_with_its_passphrase() {
+        let key = crypto::generate_key();
+        let (kdf, wrapped_key) = wrap_key(&key, "correct horse").unwrap();
+        assert_eq!(kdf.m_cost, Params::DEFAULT_M_COST);
+        let config = VaultConfig { kdf, wrapped_key, auto_lock_minutes: 0 };
+
+        assert_eq!(unwrap_key(&config, "correct horse").unwrap(), key);
+        match unwrap_key(&config, "battery staple") {
+            Err(AppError::CryptoError(message)) => assert_eq!(message, "Wrong passphrase"),
+            other => panic!("expected a wrong passphrase error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sealed_text_is_prefixed_and_opens_again() {
+        assert_eq!(seal_text("plain").unwrap(), "plain");
+
+        let mut conn = vault();
+        enable(&mut conn, "passphrase", 0).unwrap();
+        let sealed = seal_text("meeting notes").unwrap();
+        assert!(sealed.starts_with(PREFIX));
+        assert!(!sealed.contains("meeting"));
+        assert_ne!(seal_text("meeting notes").unwrap(), sealed);
+        assert_eq!(open_text(&sealed).unwrap(), "meeting notes");
+        // Written before encryption was turned on
+        assert_eq!(open_text("old plaintext").unwrap(), "old plaintext");
+    }
+
+    #[test]
+    fn enabling_seals_existing_rows_and_unlock_checks_the_passphrase() {
+        let mut conn = vault();
+        let id = note(&conn, "before encryption");
+        conn.execute(
+            "INSERT INTO tasks (document_id, block_id, block_index, item_index, text, checked) VALUES (?, 'p', 0, 0, 'buy milk', 0)",
+            [id],
+        )
+        .unwrap();
+
+        let sealed = enable(&mut conn, "passphrase", 5).unwrap();
+        assert_eq!(sealed, 2);
+        assert!(stored_content(&conn, id).starts_with(PREFIX));
+        let task: String = conn.query_row("SELECT text FROM tasks", [], |row| row.get(0)).unwrap();
+        assert!(task.starts_with(PREFIX));
+        assert_eq!(load_document_for_editor(&conn, id).unwrap().blocks[0].data["text"], "before encryption");
+        assert!(matches!(enable(&mut conn, "again", 0), Err(AppError::InvalidInput(_))));
+
+        // Running the migration again leaves sealed rows alone
+        assert_eq!(encrypt_existing(&conn, &current_key().unwrap()).unwrap(), 0);
+
+        forget_key();
+        assert!(unlock(&conn, "wrong").is_err());
+        assert!(is_locked());
+        unlock(&conn, "passphrase").unwrap();
+        assert!(!is_locked());
+        assert_eq!(load_document_for_editor(&conn, id).unwrap().blocks[0].data["text"], "before encryption");
+    }
+
+    #[test]
+    fn changing_the_passphrase_rewraps_the_same_key() {
+        let mut conn = vault();
+        enable(&mut conn, "old", 0).unwrap();
+        let key = current_key().unwrap();
+
+        assert!(change_passphrase(&conn, "wrong", "new").is_err());
+        assert!(matches!(change_passphrase(&conn, "old", ""), Err(AppError::InvalidInput(_))));
+        change_passphrase(&conn, "old", "new").unwrap();
+
+        let config = load_config(&conn).unwrap().unwrap();
+        assert!(unwrap_key(&config, "old").is_err());
+        assert_eq!(unwrap_key(&config, "new").unwrap(), key);
+    }
+
+    #[test]
+    fn guard_fails_once_the_vault_is_locked() {
+        let mut conn = vault();
+        guard().unwrap();
+        let id = note(&conn, "secret");
+        enable(&mut conn, "passphrase", 0).unwrap();
+        guard().unwrap();
+
+        assert!(forget_key());
+        assert!(!forget_key());
+        assert!(matches!(guard(), Err(AppError::VaultLocked)));
+        assert!(matches!(seal_text("x"), Err(AppError::VaultLocked)));
+        assert!(matches!(open_text(&stored_content(&conn, id)), Err(AppError::VaultLocked)));
+
+        // A restart reads the config and starts locked
+        init(&conn).unwrap();
+        assert!(is_locked());
+    }
+}